@@ -0,0 +1,122 @@
+//! Band-limited resampler
+//! Blip buffer style synthesis : amplitude changes at CPU clock times are added as band-limited steps,
+//! taken from a windowed sinc table, then read at the host sample rate without aliasing
+
+use std::f64::consts::PI;
+
+/// Sub-sample positions of the step table
+const PHASES: usize = 32;
+/// Samples covered by a band-limited step
+const KERNEL_WIDTH: usize = 16;
+/// Low-pass cutoff, relative to the output sample rate
+const CUTOFF: f64 = 0.45;
+
+/// Buffer of band-limited steps, read as samples
+pub struct BlipBuffer {
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    samples_per_clock: f64,
+    offset: f64, // Sample position of the current frame start
+    buffer: Vec<f32>,
+    integrator: f32,
+}
+
+impl BlipBuffer {
+    /// Instantiate a buffer converting from a clock rate, able to hold up to `capacity` samples per frame
+    pub fn new(clock_rate: f64, sample_rate: f64, capacity: usize) -> BlipBuffer {
+        BlipBuffer {
+            kernel: build_kernel(),
+            samples_per_clock: sample_rate / clock_rate,
+            offset: 0.0,
+            buffer: vec![0.0; capacity + KERNEL_WIDTH],
+            integrator: 0.0,
+        }
+    }
+
+    /// Change the conversion ratio
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.samples_per_clock = sample_rate / clock_rate;
+    }
+
+    /// Add an amplitude change at a clock time of the current frame
+    pub fn add_delta(&mut self, clock_time: u32, delta: f32) {
+        let position = self.offset + clock_time as f64 * self.samples_per_clock;
+        let index = position as usize;
+        let phase = ((position - index as f64) * PHASES as f64) as usize;
+        for (sample, weight) in self.buffer[index..index + KERNEL_WIDTH]
+            .iter_mut()
+            .zip(self.kernel[phase].iter())
+        {
+            *sample += delta * weight;
+        }
+    }
+
+    /// End the current frame after some clocks, making its samples available
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.offset += clocks as f64 * self.samples_per_clock;
+    }
+
+    /// Get the number of complete samples
+    pub fn get_samples_available(&self) -> usize {
+        self.offset as usize
+    }
+
+    /// Read the complete samples into a vector
+    pub fn read_samples(&mut self, output: &mut Vec<f32>) {
+        let count = self.get_samples_available();
+        for delta in self.buffer[..count].iter() {
+            self.integrator += delta;
+            output.push(self.integrator);
+        }
+        self.buffer.copy_within(count.., 0);
+        let length = self.buffer.len();
+        self.buffer[length - count..].fill(0.0);
+        self.offset -= count as f64;
+    }
+}
+
+/// Build the band-limited impulse of each phase : a Blackman windowed sinc, normalized to a unit step once integrated
+fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let half_width = (KERNEL_WIDTH / 2) as f64;
+    (0..PHASES)
+        .map(|phase| {
+            let fraction = phase as f64 / PHASES as f64;
+            let mut impulse = [0.0; KERNEL_WIDTH];
+            for (index, value) in impulse.iter_mut().enumerate() {
+                let time = index as f64 - half_width + 1.0 - fraction;
+                let sinc = if time == 0.0 {
+                    1.0
+                } else {
+                    (2.0 * PI * CUTOFF * time).sin() / (2.0 * PI * CUTOFF * time)
+                };
+                let window_position = (time + half_width) / (2.0 * half_width);
+                let window = 0.42 - 0.5 * (2.0 * PI * window_position).cos()
+                    + 0.08 * (4.0 * PI * window_position).cos();
+                *value = sinc * window;
+            }
+            let sum: f64 = impulse.iter().sum();
+            impulse.map(|value| (value / sum) as f32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_response() {
+        // A step settles to its amplitude, and the sample count follows the rate ratio
+        let mut blip = BlipBuffer::new(1_789_773.0, 44_100.0, 1024);
+        let mut samples = vec![];
+        blip.add_delta(100, 0.5);
+        for _ in 0..10 {
+            blip.end_frame(29781);
+            blip.read_samples(&mut samples);
+        }
+        assert!((samples.len() as i32 - 7338).abs() <= 1);
+        assert!(samples[..2].iter().all(|sample| *sample == 0.0));
+        assert!(samples[100..]
+            .iter()
+            .all(|sample| (sample - 0.5).abs() < 1e-4));
+    }
+}
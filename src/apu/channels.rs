@@ -0,0 +1,161 @@
+//! Channel controls
+//! Mute, solo and volume of each channel, applied before the mix, and output level meters
+
+use std::str::FromStr;
+
+/// Maximum output of each channel : pulses, triangle and noise are 4 bits, DMC is 7 bits
+const MAX_OUTPUTS: [f32; 5] = [15.0, 15.0, 15.0, 15.0, 127.0];
+/// CPU cycles over which level meters keep the peak output, about one frame
+const LEVEL_WINDOW: u32 = 29781;
+
+/// APU channels
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Channel, String> {
+        match value.to_lowercase().as_str() {
+            "pulse1" => Ok(Channel::Pulse1),
+            "pulse2" => Ok(Channel::Pulse2),
+            "triangle" => Ok(Channel::Triangle),
+            "noise" => Ok(Channel::Noise),
+            "dmc" => Ok(Channel::Dmc),
+            _ => Err(format!("Unknown channel : {}", value)),
+        }
+    }
+}
+
+impl Channel {
+    /// All channels, in mixer order
+    pub const ALL: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+    ];
+
+    /// Index of the channel in the mixer inputs
+    pub fn get_index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Runtime controls of the channels, independent of the $4015 enable flags
+pub struct ChannelControls {
+    volumes: [f32; 5],
+    is_muted: [bool; 5],
+    is_soloed: [bool; 5],
+    peaks: [f32; 5], // Peak levels of the current meter window
+    levels: [f32; 5],
+    level_cycle: u32,
+}
+
+impl ChannelControls {
+    pub fn new() -> ChannelControls {
+        ChannelControls {
+            volumes: [1.0; 5],
+            is_muted: [false; 5],
+            is_soloed: [false; 5],
+            peaks: [0.0; 5],
+            levels: [0.0; 5],
+            level_cycle: 0,
+        }
+    }
+
+    /// Set the volume of a channel, from 0.0 to 1.0
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volumes[channel.get_index()] = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, is_muted: bool) {
+        self.is_muted[channel.get_index()] = is_muted;
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.is_muted[channel.get_index()]
+    }
+
+    /// Solo a channel. While some channels are soloed, the other ones are silent
+    pub fn set_soloed(&mut self, channel: Channel, is_soloed: bool) {
+        self.is_soloed[channel.get_index()] = is_soloed;
+    }
+
+    pub fn is_soloed(&self, channel: Channel) -> bool {
+        self.is_soloed[channel.get_index()]
+    }
+
+    /// Unmute, unsolo and restore the volume of every channel
+    pub fn reset(&mut self) {
+        self.volumes = [1.0; 5];
+        self.is_muted = [false; 5];
+        self.is_soloed = [false; 5];
+    }
+
+    /// Apply the controls to the channel outputs of a CPU cycle, giving the mixer inputs
+    pub fn apply(&mut self, outputs: &[u8; 5]) -> [f32; 5] {
+        let is_solo = self.is_soloed.contains(&true);
+        let mut inputs = [0.0; 5];
+        for (index, input) in inputs.iter_mut().enumerate() {
+            if !self.is_muted[index] && (!is_solo || self.is_soloed[index]) {
+                *input = outputs[index] as f32 * self.volumes[index];
+            }
+            self.peaks[index] = self.peaks[index].max(*input / MAX_OUTPUTS[index]);
+        }
+        self.level_cycle += 1;
+        if self.level_cycle == LEVEL_WINDOW {
+            self.levels = self.peaks;
+            self.peaks = [0.0; 5];
+            self.level_cycle = 0;
+        }
+        inputs
+    }
+
+    /// Get the output level of each channel from 0.0 to 1.0, peak over the last frame after the controls
+    pub fn get_levels(&self) -> [f32; 5] {
+        self.levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mute_and_solo() {
+        let mut controls = ChannelControls::new();
+        assert_eq!(
+            controls.apply(&[15, 15, 15, 15, 127]),
+            [15.0, 15.0, 15.0, 15.0, 127.0]
+        );
+        controls.set_muted(Channel::Pulse1, true);
+        controls.set_volume(Channel::Noise, 0.5);
+        assert_eq!(
+            controls.apply(&[15, 15, 15, 15, 127]),
+            [0.0, 15.0, 15.0, 7.5, 127.0]
+        );
+        controls.set_soloed(Channel::Noise, true);
+        controls.set_soloed(Channel::Pulse1, true);
+        assert_eq!(
+            controls.apply(&[15, 15, 15, 15, 127]),
+            [0.0, 0.0, 0.0, 7.5, 0.0]
+        );
+        // Levels are the peaks of the last complete window
+        for _ in 3..2 * LEVEL_WINDOW {
+            controls.apply(&[15, 15, 15, 15, 127]);
+        }
+        assert_eq!(controls.get_levels(), [0.0, 0.0, 0.0, 0.5, 0.0]);
+        controls.reset();
+        assert!(!controls.is_muted(Channel::Pulse1));
+        assert_eq!(Channel::Dmc.get_index(), 4);
+        assert_eq!("Triangle".parse::<Channel>(), Ok(Channel::Triangle));
+    }
+}
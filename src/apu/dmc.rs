@@ -0,0 +1,213 @@
+//! Delta modulation channel
+//! Plays 1 bit delta encoded samples fetched from the cartridge through the CPU bus, or 7 bits levels written to $4011
+
+use crate::region::Region;
+
+/// DMC Register and channel state
+pub struct Dmc {
+    byte_0: u8,
+    irq_enabled: bool,
+    loop_sample: bool,
+    frequency: u8,
+    load_counter: u8,
+    sample_address: u8,
+    sample_length: u8,
+    rates: &'static [u16; 16],
+    timer: u16,
+    output_level: u8,
+
+    // Memory reader
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    is_irq_raised: bool,
+
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    is_silenced: bool,
+}
+
+impl Dmc {
+    pub fn new() -> Dmc {
+        Dmc {
+            byte_0: 0,
+            irq_enabled: false,
+            loop_sample: false,
+            frequency: 0,
+            load_counter: 0,
+            sample_address: 0,
+            sample_length: 0,
+            rates: Region::Ntsc.get_dmc_rates(),
+            timer: 0,
+            output_level: 0,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            is_irq_raised: false,
+            shift_register: 0,
+            bits_remaining: 8,
+            is_silenced: true,
+        }
+    }
+
+    /// Set the region selecting the rate table
+    pub fn set_region(&mut self, region: Region) {
+        self.rates = region.get_dmc_rates();
+    }
+
+    /// Set IRQ enable, loop flag and rate. Disabling IRQ clears the IRQ flag
+    pub fn set_byte_0(&mut self, value: u8) {
+        self.byte_0 = value;
+        self.irq_enabled = (value & 0x80) != 0;
+        self.loop_sample = (value & 0x40) != 0;
+        self.frequency = value & 0xf;
+        if !self.irq_enabled {
+            self.is_irq_raised = false;
+        }
+    }
+
+    pub fn get_byte_0(&self) -> u8 {
+        self.byte_0
+    }
+
+    /// Direct load of the output level
+    pub fn set_byte_1(&mut self, value: u8) {
+        self.load_counter = value & 0x7f;
+        self.output_level = self.load_counter;
+    }
+
+    pub fn get_byte_1(&self) -> u8 {
+        self.load_counter
+    }
+
+    /// Set the sample address, $C000 + 64 * value
+    pub fn set_byte_2(&mut self, value: u8) {
+        self.sample_address = value;
+    }
+
+    pub fn get_byte_2(&self) -> u8 {
+        self.sample_address
+    }
+
+    /// Set the sample length, 16 * value + 1 bytes
+    pub fn set_byte_3(&mut self, value: u8) {
+        self.sample_length = value;
+    }
+
+    pub fn get_byte_3(&self) -> u8 {
+        self.sample_length
+    }
+
+    /// Enable or disable the channel from $4015. Enabling restarts the sample only if it has ended
+    pub fn set_enabled(&mut self, is_enabled: bool) {
+        self.is_irq_raised = false;
+        if !is_enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /// Return true while sample bytes remain, for $4015 reads
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    /// Return true if the sample ended with IRQ enabled
+    pub fn is_irq_raised(&self) -> bool {
+        self.is_irq_raised
+    }
+
+    /// Restart the sample from its start address
+    fn restart(&mut self) {
+        self.current_address = 0xc000 + 64 * self.sample_address as u16;
+        self.bytes_remaining = 16 * self.sample_length as u16 + 1;
+    }
+
+    /// Get the address of the next sample byte, when the sample buffer is empty and bytes remain
+    pub fn get_fetch_address(&self) -> Option<u16> {
+        (self.sample_buffer.is_none() && self.bytes_remaining > 0).then_some(self.current_address)
+    }
+
+    /// Fill the sample buffer with the byte fetched by the CPU bus, setting the IRQ flag at the end of the sample
+    pub fn set_sample(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // Address wraps to $8000 after $FFFF
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_sample {
+                self.restart();
+            } else if self.irq_enabled {
+                self.is_irq_raised = true;
+            }
+        }
+    }
+
+    /// CPU cycle clock, rates being in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rates[self.frequency as usize] - 1;
+
+        if !self.is_silenced {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.shift_register = sample;
+                    self.is_silenced = false;
+                }
+                None => self.is_silenced = true,
+            }
+        }
+    }
+
+    /// Get the channel output, from 0 to 127
+    pub fn get_output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_playback() {
+        let mut dmc = Dmc::new();
+        dmc.set_byte_0(0x8f); // IRQ, fastest rate
+        dmc.set_byte_1(0x40);
+        dmc.set_byte_2(0xff); // $FFC0
+        dmc.set_byte_3(0x04); // 65 bytes, crossing $FFFF
+        dmc.set_enabled(true);
+        let mut fetched_addresses = vec![];
+        for _ in 0..65 * 8 * 54 + 54 * 8 {
+            if let Some(address) = dmc.get_fetch_address() {
+                fetched_addresses.push(address);
+                dmc.set_sample(0xff); // Only increments
+            }
+            dmc.clock_timer();
+        }
+        assert_eq!(fetched_addresses.len(), 65);
+        assert_eq!(fetched_addresses[63], 0xffff);
+        assert_eq!(fetched_addresses[64], 0x8000);
+        assert!(dmc.is_irq_raised() && !dmc.is_active());
+        assert_eq!(dmc.get_output(), 126); // Steps of 2 from 0x40, up to 126
+        dmc.set_enabled(false);
+        assert!(!dmc.is_irq_raised());
+    }
+}
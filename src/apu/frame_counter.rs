@@ -0,0 +1,164 @@
+//! APU frame counter
+//! Sequencer clocking envelopes and linear counters on quarter frames, length counters and sweeps on half frames,
+//! in 4 step mode with a frame IRQ or 5 step mode without
+
+use crate::region::Region;
+
+/// Clock given to the channels by a frame counter step
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FrameClock {
+    None,
+    Quarter,
+    Half, // Half frames are also quarter frames
+}
+
+/// Frame counter register and sequencer state
+pub struct FrameCounter {
+    byte: u8,
+    steps: &'static [u32; 5],
+    is_five_step_mode: bool,
+    is_irq_inhibited: bool,
+    is_irq_raised: bool,
+    cycle: u32,      // CPU cycles since the sequence start
+    reset_delay: u8, // CPU cycles before a $4017 write resets the sequence, 0 when none is pending
+}
+
+impl FrameCounter {
+    pub fn new() -> FrameCounter {
+        FrameCounter {
+            byte: 0,
+            steps: Region::Ntsc.get_frame_counter_steps(),
+            is_five_step_mode: false,
+            is_irq_inhibited: false,
+            is_irq_raised: false,
+            cycle: 0,
+            reset_delay: 0,
+        }
+    }
+
+    /// Set the region selecting the step timings
+    pub fn set_region(&mut self, region: Region) {
+        self.steps = region.get_frame_counter_steps();
+    }
+
+    /// Set mode and IRQ inhibit flag. The sequence restarts 3 or 4 CPU cycles later, whether written on an APU cycle
+    pub fn set_byte(&mut self, value: u8, is_apu_cycle: bool) {
+        self.byte = value;
+        self.is_irq_inhibited = (value & 0x40) != 0;
+        if self.is_irq_inhibited {
+            self.is_irq_raised = false;
+        }
+        self.reset_delay = if is_apu_cycle { 3 } else { 4 };
+    }
+
+    pub fn get_byte(&self) -> u8 {
+        self.byte
+    }
+
+    /// Return true while the frame IRQ flag is set
+    pub fn is_irq_raised(&self) -> bool {
+        self.is_irq_raised
+    }
+
+    /// Clear the frame IRQ flag, when $4015 is read
+    pub fn clear_irq(&mut self) {
+        self.is_irq_raised = false;
+    }
+
+    /// CPU cycle clock
+    pub fn clock(&mut self) -> FrameClock {
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.cycle = 0;
+                self.is_five_step_mode = (self.byte & 0x80) != 0;
+                // Entering 5 step mode clocks the channels immediately
+                if self.is_five_step_mode {
+                    return FrameClock::Half;
+                }
+                return FrameClock::None;
+            }
+        }
+
+        self.cycle += 1;
+        let [quarter_1, half_1, quarter_2, four_step_end, five_step_end] = *self.steps;
+        let clock = match self.cycle {
+            cycle if cycle == quarter_1 || cycle == quarter_2 => FrameClock::Quarter,
+            cycle if cycle == half_1 => FrameClock::Half,
+            cycle if cycle == four_step_end && !self.is_five_step_mode => FrameClock::Half,
+            cycle if cycle == five_step_end && self.is_five_step_mode => FrameClock::Half,
+            _ => FrameClock::None,
+        };
+
+        if self.is_five_step_mode {
+            if self.cycle == five_step_end + 1 {
+                self.cycle = 0;
+            }
+        } else {
+            // The IRQ flag is set on the 3 last cycles of the sequence
+            if (four_step_end - 1..=four_step_end + 1).contains(&self.cycle)
+                && !self.is_irq_inhibited
+            {
+                self.is_irq_raised = true;
+            }
+            if self.cycle == four_step_end + 1 {
+                self.cycle = 0;
+            }
+        }
+        clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the frame counter for some CPU cycles, giving the cycles with a clock
+    fn run(frame_counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, FrameClock)> {
+        (1..=cycles)
+            .filter_map(|cycle| match frame_counter.clock() {
+                FrameClock::None => None,
+                clock => Some((cycle, clock)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn four_step_mode() {
+        let mut frame_counter = FrameCounter::new();
+        let clocks = run(&mut frame_counter, 29830);
+        assert_eq!(
+            clocks,
+            vec![
+                (7457, FrameClock::Quarter),
+                (14913, FrameClock::Half),
+                (22371, FrameClock::Quarter),
+                (29829, FrameClock::Half)
+            ]
+        );
+        assert!(frame_counter.is_irq_raised());
+        frame_counter.clear_irq();
+        assert_eq!(run(&mut frame_counter, 7457).len(), 1); // Sequence restarted
+        frame_counter.set_byte(0x40, true); // IRQ inhibited
+        run(&mut frame_counter, 29830);
+        assert!(!frame_counter.is_irq_raised());
+    }
+
+    #[test]
+    fn five_step_mode() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.set_byte(0x80, false);
+        let clocks = run(&mut frame_counter, 4 + 37282);
+        assert_eq!(
+            clocks,
+            vec![
+                (4, FrameClock::Half), // Immediate clock after the write delay
+                (4 + 7457, FrameClock::Quarter),
+                (4 + 14913, FrameClock::Half),
+                (4 + 22371, FrameClock::Quarter),
+                (4 + 37281, FrameClock::Half)
+            ]
+        );
+        assert!(!frame_counter.is_irq_raised());
+    }
+}
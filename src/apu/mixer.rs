@@ -0,0 +1,267 @@
+//! APU mixer
+//! Non-linear DAC mix of the channels, band-limited resampling to the host rate, then the console output filters
+
+use super::blip_buffer::BlipBuffer;
+use std::f32::consts::PI;
+
+/// CPU cycles between two reads of the resampled output, about 1 ms
+const MIX_FRAME_CYCLES: u32 = 2048;
+/// Maximum samples per mix frame, for host rates up to 192 kHz
+const MAX_FRAME_SAMPLES: usize = 512;
+/// Lowest supported host sample rate
+const MIN_SAMPLE_RATE: u32 = 8_000;
+/// Highest supported host sample rate, a mix frame must fit in MAX_FRAME_SAMPLES
+const MAX_SAMPLE_RATE: u32 = 192_000;
+
+/// First order filter, applied at the output sample rate
+struct Filter {
+    is_high_pass: bool,
+    alpha: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl Filter {
+    fn new(is_high_pass: bool, cutoff: f32, sample_rate: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter {
+            is_high_pass,
+            alpha: if is_high_pass {
+                rc / (rc + dt)
+            } else {
+                dt / (rc + dt)
+            },
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        self.last_output = if self.is_high_pass {
+            self.alpha * (self.last_output + input - self.last_input)
+        } else {
+            self.last_output + self.alpha * (input - self.last_output)
+        };
+        self.last_input = input;
+        self.last_output
+    }
+}
+
+/// Build the filters of the NES output : 90 Hz and 440 Hz high-pass, then 14 kHz low-pass
+fn build_filters(sample_rate: f32) -> [Filter; 3] {
+    [
+        Filter::new(true, 90.0, sample_rate),
+        Filter::new(true, 440.0, sample_rate),
+        Filter::new(false, 14_000.0, sample_rate),
+    ]
+}
+
+/// Pulse DAC output, indexed by the sum of both pulse channels
+fn build_pulse_table() -> [f32; 31] {
+    let mut table = [0.0; 31];
+    for (index, level) in table.iter_mut().enumerate().skip(1) {
+        *level = 95.52 / (8128.0 / index as f32 + 100.0);
+    }
+    table
+}
+
+/// Triangle, noise and DMC DAC output, indexed by 3 * triangle + 2 * noise + DMC
+fn build_tnd_table() -> [f32; 203] {
+    let mut table = [0.0; 203];
+    for (index, level) in table.iter_mut().enumerate().skip(1) {
+        *level = 163.67 / (24329.0 / index as f32 + 100.0);
+    }
+    table
+}
+
+/// Read a DAC table at a fractional index, interpolating between entries
+fn get_table_level(table: &[f32], index: f32) -> f32 {
+    let position = index as usize;
+    if position + 1 >= table.len() {
+        return table[table.len() - 1];
+    }
+    let fraction = index - position as f32;
+    table[position] + (table[position + 1] - table[position]) * fraction
+}
+
+/// Mixer turning the channel outputs of each CPU cycle into filtered samples at the host rate
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+    blip_buffer: BlipBuffer,
+    clock_rate: f64,
+    sample_rate: f64,
+    rate_adjustment: f64, // Dynamic rate control factor applied to the resampling ratio
+    filters: [Filter; 3],
+    amplitude: f32,
+    cycle: u32, // CPU cycle of the current mix frame
+}
+
+impl Mixer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Mixer {
+        Mixer {
+            pulse_table: build_pulse_table(),
+            tnd_table: build_tnd_table(),
+            blip_buffer: BlipBuffer::new(clock_rate, sample_rate, MAX_FRAME_SAMPLES),
+            clock_rate,
+            sample_rate,
+            rate_adjustment: 1.0,
+            filters: build_filters(sample_rate as f32),
+            amplitude: 0.0,
+            cycle: 0,
+        }
+    }
+
+    /// Change the CPU clock rate, with the region
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.update_rates();
+    }
+
+    /// Change the host sample rate, from MIN_SAMPLE_RATE to MAX_SAMPLE_RATE
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), String> {
+        if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+            return Err(format!(
+                "Unsupported sample rate : {} Hz, expected {} to {} Hz",
+                sample_rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+            ));
+        }
+        self.sample_rate = sample_rate as f64;
+        self.update_rates();
+        self.filters = build_filters(sample_rate as f32);
+        Ok(())
+    }
+
+    /// Get the host sample rate
+    pub fn get_sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Produce slightly more (above 1.0) or less samples than the host rate, keeping the audio latency stable
+    pub fn set_rate_adjustment(&mut self, rate_adjustment: f64) {
+        self.rate_adjustment = rate_adjustment;
+        self.update_rates();
+    }
+
+    fn update_rates(&mut self) {
+        self.blip_buffer
+            .set_rates(self.clock_rate, self.sample_rate * self.rate_adjustment);
+    }
+
+    /// Mix the channel inputs : pulse 1, pulse 2, triangle, noise and DMC. Inputs scaled down by the channel
+    /// volumes fall between table entries
+    pub fn mix(&self, inputs: &[f32; 5]) -> f32 {
+        get_table_level(&self.pulse_table, inputs[0] + inputs[1])
+            + get_table_level(
+                &self.tnd_table,
+                3.0 * inputs[2] + 2.0 * inputs[3] + inputs[4],
+            )
+    }
+
+    /// Get the level of one channel played alone : pulse 1, pulse 2, triangle, noise or DMC
+    pub fn mix_channel(&self, channel: usize, input: f32) -> f32 {
+        match channel {
+            0 | 1 => get_table_level(&self.pulse_table, input),
+            2 => get_table_level(&self.tnd_table, 3.0 * input),
+            3 => get_table_level(&self.tnd_table, 2.0 * input),
+            _ => get_table_level(&self.tnd_table, input),
+        }
+    }
+
+    /// Add the channel inputs of a CPU cycle. Returns true when new samples can be read
+    pub fn add_sample(&mut self, inputs: &[f32; 5]) -> bool {
+        self.add_amplitude(self.mix(inputs))
+    }
+
+    /// Add the mixed amplitude of a CPU cycle. Returns true when new samples can be read
+    pub fn add_amplitude(&mut self, amplitude: f32) -> bool {
+        if amplitude != self.amplitude {
+            self.blip_buffer
+                .add_delta(self.cycle, amplitude - self.amplitude);
+            self.amplitude = amplitude;
+        }
+        self.cycle += 1;
+        if self.cycle == MIX_FRAME_CYCLES {
+            self.blip_buffer.end_frame(self.cycle);
+            self.cycle = 0;
+            return true;
+        }
+        false
+    }
+
+    /// Read the filtered samples of the last mix frames
+    pub fn read_samples(&mut self, output: &mut Vec<f32>) {
+        let start = output.len();
+        self.blip_buffer.read_samples(output);
+        for sample in output[start..].iter_mut() {
+            *sample = self
+                .filters
+                .iter_mut()
+                .fold(*sample, |value, filter| filter.apply(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_linear_mix() {
+        let mixer = Mixer::new(1_789_773.0, 44_100.0);
+        assert_eq!(mixer.mix(&[0.0; 5]), 0.0);
+        assert!((mixer.mix(&[15.0, 15.0, 0.0, 0.0, 0.0]) - 0.2575).abs() < 1e-3);
+        assert!((mixer.mix(&[0.0, 0.0, 15.0, 15.0, 127.0]) - 0.7425).abs() < 1e-3);
+        // Two pulses at full volume are less than twice one pulse
+        assert!(
+            mixer.mix(&[15.0, 15.0, 0.0, 0.0, 0.0]) < 2.0 * mixer.mix(&[15.0, 0.0, 0.0, 0.0, 0.0])
+        );
+        // Scaled inputs are interpolated
+        let half = mixer.mix(&[7.5, 0.0, 0.0, 0.0, 0.0]);
+        assert!(half > mixer.mix(&[7.0, 0.0, 0.0, 0.0, 0.0]));
+        assert!(half < mixer.mix(&[8.0, 0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn filtered_output() {
+        // A constant level is removed by the high-pass filters, a 1 kHz square wave goes through
+        let mut mixer = Mixer::new(1_789_773.0, 48_000.0);
+        let mut samples = vec![];
+        for cycle in 0..1_789_773 {
+            let level = if (cycle / 895) % 2 == 0 { 15.0 } else { 0.0 };
+            if mixer.add_sample(&[level, 0.0, 8.0, 0.0, 64.0]) {
+                mixer.read_samples(&mut samples);
+            }
+        }
+        // The last partial mix frame is not read yet
+        assert!((samples.len() as i32 - 48_000).abs() < 64);
+        let end = &samples[samples.len() - 480..];
+        let average = end.iter().sum::<f32>() / end.len() as f32;
+        let peak = end
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(average.abs() < 0.01);
+        assert!(peak > 0.05);
+    }
+
+    #[test]
+    fn sample_rate_range() {
+        let mut mixer = Mixer::new(1_789_773.0, 44_100.0);
+        assert!(mixer.set_sample_rate(0).is_err());
+        assert!(mixer.set_sample_rate(500_000).is_err());
+        assert_eq!(mixer.get_sample_rate(), 44_100.0);
+
+        // Slowest CPU clock and fastest rate adjustment still fit in a mix frame
+        assert!(mixer.set_sample_rate(MAX_SAMPLE_RATE).is_ok());
+        mixer.set_clock_rate(1_662_607.0);
+        mixer.set_rate_adjustment(1.01);
+        let mut samples = vec![];
+        for cycle in 0..100_000 {
+            if mixer.add_sample(&[(cycle % 16) as f32, 0.0, 0.0, 0.0, 0.0]) {
+                mixer.read_samples(&mut samples);
+            }
+        }
+        assert!(samples.iter().all(|sample| sample.is_finite()));
+    }
+}
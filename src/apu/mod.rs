@@ -1,26 +1,76 @@
 //! APU Component
 
+mod blip_buffer;
+mod channels;
+mod dmc;
+mod frame_counter;
+mod mixer;
+mod noise;
+mod pulse;
+mod recorder;
+mod ring_buffer;
+mod triangle;
+mod units;
+mod wav;
+
 use crate::bus::interrupt::Interrupt;
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use crate::region::Region;
+pub use channels::Channel;
+use channels::ChannelControls;
+use dmc::Dmc;
+use frame_counter::{FrameClock, FrameCounter};
+use log::{error, info};
+use mixer::Mixer;
+use noise::Noise;
+use pulse::Pulse;
+pub use recorder::RecordMode;
+use recorder::Recorder;
+use ring_buffer::RingBuffer;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use triangle::Triangle;
+
+/// Default host sample rate
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+/// Samples buffered between the emulation and the audio callback, about 180 ms at 44.1 kHz
+const RING_BUFFER_SIZE: usize = 8192;
+/// Buffered audio targeted by the frame pacing and the dynamic rate control, in seconds
+const TARGET_LATENCY: f64 = 0.05;
+/// Maximum resampling ratio change of the dynamic rate control, inaudible
+const MAX_RATE_DELTA: f64 = 0.005;
+/// Smoothing of the buffer fill level measured each frame
+const FILL_SMOOTHING: f64 = 0.1;
+/// Longest wait for the audio device, in case it stops consuming samples
+const MAX_AUDIO_WAIT: Duration = Duration::from_millis(100);
 
-#[allow(dead_code)]
 pub struct Apu {
-    sdl_audio: sdl2::AudioSubsystem,
+    sdl_context: Rc<RefCell<sdl2::Sdl>>,
+    audio_driver: Option<String>, // SDL audio driver, SDL default one if None
+    is_headless: bool,
     interrupt_bus: Rc<RefCell<Interrupt>>,
+    region: Region,
     mixer: Mixer,
+    samples: Vec<f32>, // Resampled output, waiting to be queued
+    ring_buffer: Arc<RingBuffer>,
+    volume: Arc<AtomicU32>, // f32 bits, shared with the audio callback
+    audio_device: Option<AudioDevice<AudioOutput>>,
+    average_fill: f64, // Smoothed ring buffer fill level, in samples
+    recorder: Option<Recorder>,
+    channel_controls: ChannelControls,
     pulse_1: Pulse,
     pulse_2: Pulse,
     noise: Noise,
     triangle: Triangle,
     dmc: Dmc,
+    frame_counter: FrameCounter,
 
-    enable_dmc: bool,
-    enable_noise: bool,
-    enable_triangle: bool,
-    enable_pulse_1: bool,
-    enable_pulse_2: bool,
+    is_apu_cycle: bool,    // APU cycles are every other CPU cycle
+    is_irq_asserted: bool, // Frame or DMC IRQ held on the interrupt bus
 }
 
 impl Apu {
@@ -30,80 +80,28 @@ impl Apu {
         _interrupt_bus: Rc<RefCell<Interrupt>>,
     ) -> Apu {
         let mut apu = Apu {
-            mixer: Mixer {
-                phase_inc: 440.0 / 44100_f32,
-                phase: 0.0,
-                volume: 0.25,
-            },
-            sdl_audio: _sdl_context.borrow_mut().audio().unwrap(),
+            mixer: Mixer::new(Region::Ntsc.get_cpu_clock(), DEFAULT_SAMPLE_RATE as f64),
+            samples: vec![],
+            ring_buffer: Arc::new(RingBuffer::new(RING_BUFFER_SIZE)),
+            volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            audio_device: None,
+            average_fill: 0.0,
+            recorder: None,
+            channel_controls: ChannelControls::new(),
+            sdl_context: _sdl_context,
+            audio_driver: None,
+            is_headless: false,
             interrupt_bus: _interrupt_bus,
-            pulse_1: Pulse {
-                byte_0: 0,
-                byte_1: 0,
-                byte_2: 0,
-                byte_3: 0,
-                duty: 0,
-                enveloppe_loop_length_counter_halt: false,
-                constant_volume: false,
-                volume_envelope: 0,
-                sweep_unit_enabled: false,
-                sweep_unit_period: 0,
-                sweep_unit_negate: false,
-                sweep_unit_shift: 0,
-                timer: 0,
-                length_counter_load: 0,
-            },
-            pulse_2: Pulse {
-                byte_0: 0,
-                byte_1: 0,
-                byte_2: 0,
-                byte_3: 0,
-                duty: 0,
-                enveloppe_loop_length_counter_halt: false,
-                constant_volume: false,
-                volume_envelope: 0,
-                sweep_unit_enabled: false,
-                sweep_unit_period: 0,
-                sweep_unit_negate: false,
-                sweep_unit_shift: 0,
-                timer: 0,
-                length_counter_load: 0,
-            },
-            triangle: Triangle {
-                byte_0: 0,
-                byte_2: 0,
-                byte_3: 0,
-                lenght_counter_halt_linear_counter_control: false,
-                linear_counter_load: 0,
-                length_counter_load: 0,
-                timer: 0,
-            },
-            noise: Noise {
-                byte_0: 0,
-                byte_2: 0,
-                byte_3: 0,
-                envelope_loop_length_counter_halt: false,
-                constant_volume: false,
-                volume_envelope: 0,
-                loop_noise: false,
-                noise_period: 0,
-                length_counter_load: 0,
-            },
-            dmc: Dmc {
-                byte_0: 0,
-                irq_enabled: false,
-                loop_sample: false,
-                frequency: 0,
-                load_counter: 0,
-                sample_address: 0,
-                sample_length: 0,
-            },
-
-            enable_dmc: false,
-            enable_noise: false,
-            enable_triangle: false,
-            enable_pulse_1: false,
-            enable_pulse_2: false,
+            region: Region::Ntsc,
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+
+            is_apu_cycle: false,
+            is_irq_asserted: false,
         };
 
         // Initialise registers
@@ -129,27 +127,276 @@ impl Apu {
         apu
     }
 
+    /// Open the audio device and start playing, unless headless. Without device, emulation runs silently
     pub fn start(&mut self) {
-        let _desired_spec = AudioSpecDesired {
-            freq: Some(44100),
+        if self.is_headless {
+            return;
+        }
+        // The hint is only read when the SDL audio subsystem is initialized
+        if let Some(audio_driver) = self.audio_driver.as_ref() {
+            sdl2::hint::set("SDL_AUDIODRIVER", audio_driver);
+        }
+        let sdl_audio = match self.sdl_context.borrow().audio() {
+            Ok(sdl_audio) => sdl_audio,
+            Err(message) => {
+                error!("Unable to initialize audio : {}", message);
+                return;
+            }
+        };
+        let desired_spec = AudioSpecDesired {
+            freq: Some(self.mixer.get_sample_rate() as i32),
             channels: Some(1), // mono
-            samples: None,     // default sample size
+            samples: Some(1024),
         };
-        /*
-        let device = self.sdl_audio.open_playback(None, &desired_spec, |spec| {
-            self.mixer
-        }).unwrap();
+        let ring_buffer = self.ring_buffer.clone();
+        let volume = self.volume.clone();
+        let mut sample_rate = None;
+        match sdl_audio.open_playback(None, &desired_spec, |spec| {
+            sample_rate = Some(spec.freq);
+            AudioOutput {
+                ring_buffer,
+                volume,
+                last_sample: 0.0,
+            }
+        }) {
+            Ok(device) => {
+                if let Some(sample_rate) = sample_rate {
+                    if let Err(message) = self.mixer.set_sample_rate(sample_rate as u32) {
+                        error!("Unable to use audio device : {}", message);
+                        return;
+                    }
+                    info!("Audio started at {} Hz", sample_rate);
+                }
+                self.average_fill = self.get_target_fill();
+                device.resume();
+                self.audio_device = Some(device);
+            }
+            Err(message) => error!("Unable to open audio device : {}", message),
+        }
+    }
 
-        // Start playing sound
-        device.resume();
-        */
+    /// Never open the audio device, samples are still produced for recordings. Must be called before start
+    pub fn set_headless(&mut self) {
+        self.is_headless = true;
     }
 
-    /// Next APU cycle
-    pub fn next(&self) {}
+    /// Select the SDL audio driver, such as "pulseaudio", "alsa" or "dummy". Must be called before start
+    pub fn set_audio_driver(&mut self, audio_driver: &str) {
+        self.audio_driver = Some(audio_driver.to_string());
+    }
+
+    /// Return true if samples are played by an audio device
+    pub fn is_audio_started(&self) -> bool {
+        self.audio_device.is_some()
+    }
+
+    /// Get the number of buffered samples targeted
+    fn get_target_fill(&self) -> f64 {
+        self.mixer.get_sample_rate() * TARGET_LATENCY
+    }
+
+    /// Wait until the audio device has consumed the buffered samples down to the target latency.
+    /// Used as frame pacing in audio sync mode
+    pub fn wait_for_audio(&self) {
+        let start = Instant::now();
+        while self.ring_buffer.len() as f64 > self.get_target_fill()
+            && start.elapsed() < MAX_AUDIO_WAIT
+        {
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Dynamic rate control, called once per frame : adjust the resampling ratio by a small percentage from the
+    /// buffer fill level, so the buffered audio stays at the target latency without underrun nor overflow
+    pub fn update_rate_control(&mut self) {
+        self.average_fill += (self.ring_buffer.len() as f64 - self.average_fill) * FILL_SMOOTHING;
+        let target_fill = self.get_target_fill();
+        let deviation = ((target_fill - self.average_fill) / target_fill).clamp(-1.0, 1.0);
+        self.mixer
+            .set_rate_adjustment(1.0 + MAX_RATE_DELTA * deviation);
+    }
+
+    /// Set the host sample rate, used when the audio device is opened.
+    /// Rates out of MIN_SAMPLE_RATE to MAX_SAMPLE_RATE are rejected
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), String> {
+        self.mixer.set_sample_rate(sample_rate)
+    }
+
+    /// Set the output volume, from 0.0 to 1.0
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume
+            .store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Set the region selecting the noise, DMC and frame counter rate tables
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.frame_counter.set_region(region);
+        self.mixer.set_clock_rate(region.get_cpu_clock());
+    }
+
+    /// Next CPU cycle. Pulse timers are clocked every APU cycle, that is every other CPU cycle
+    pub fn next(&mut self) {
+        match self.frame_counter.clock() {
+            FrameClock::Quarter => self.clock_quarter_frame(),
+            FrameClock::Half => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            FrameClock::None => (),
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.is_apu_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.is_apu_cycle = !self.is_apu_cycle;
+        self.update_irq();
+        let inputs = self.channel_controls.apply(&self.get_channel_outputs());
+        if self.mixer.add_sample(&inputs) {
+            self.queue_samples();
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(error) = recorder.add_sample(&inputs) {
+                error!("Audio recording stopped : {}", error);
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// Start recording the output to WAV files, at the host sample rate. A running recording is completed first
+    pub fn start_recording(&mut self, file_name: &str, mode: RecordMode) -> std::io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::create(
+            file_name,
+            mode,
+            self.region.get_cpu_clock(),
+            self.mixer.get_sample_rate() as u32,
+        )?);
+        Ok(())
+    }
+
+    /// Complete the WAV files of the running recording, if any
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Return true while recording
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Move the resampled output to the audio callback. Samples are dropped when the buffer is full
+    fn queue_samples(&mut self) {
+        self.mixer.read_samples(&mut self.samples);
+        for sample in self.samples.drain(..) {
+            self.ring_buffer.push(sample);
+        }
+    }
+
+    /// Hold the CPU IRQ line while the frame or DMC IRQ flag is set
+    fn update_irq(&mut self) {
+        let is_irq = self.frame_counter.is_irq_raised() || self.dmc.is_irq_raised();
+        if is_irq != self.is_irq_asserted {
+            self.is_irq_asserted = is_irq;
+            if is_irq {
+                self.interrupt_bus.borrow_mut().raise_irq();
+            } else {
+                self.interrupt_bus.borrow_mut().clear_irq();
+            }
+        }
+    }
+
+    /// Quarter frame clock : envelopes and triangle linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.clock_quarter_frame();
+        self.pulse_2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    /// Half frame clock : length counters and sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.clock_half_frame();
+        self.pulse_2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    /// Get the channel outputs : pulses, triangle and noise from 0 to 15, DMC from 0 to 127
+    fn get_channel_outputs(&self) -> [u8; 5] {
+        [
+            self.pulse_1.get_output(),
+            self.pulse_2.get_output(),
+            self.triangle.get_output(),
+            self.noise.get_output(),
+            self.dmc.get_output(),
+        ]
+    }
+
+    /// Mute a channel, whatever its $4015 enable flag
+    pub fn set_channel_muted(&mut self, channel: Channel, is_muted: bool) {
+        self.channel_controls.set_muted(channel, is_muted);
+    }
+
+    pub fn is_channel_muted(&self, channel: Channel) -> bool {
+        self.channel_controls.is_muted(channel)
+    }
+
+    /// Solo a channel. While some channels are soloed, the other ones are silent
+    pub fn set_channel_soloed(&mut self, channel: Channel, is_soloed: bool) {
+        self.channel_controls.set_soloed(channel, is_soloed);
+    }
+
+    pub fn is_channel_soloed(&self, channel: Channel) -> bool {
+        self.channel_controls.is_soloed(channel)
+    }
+
+    /// Set the volume of a channel, from 0.0 to 1.0
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.channel_controls.set_volume(channel, volume);
+    }
+
+    /// Unmute, unsolo and restore the volume of every channel
+    pub fn reset_channel_controls(&mut self) {
+        self.channel_controls.reset();
+    }
+
+    /// Get the output level of each channel from 0.0 to 1.0 : peak over the last frame, after mute, solo and volume
+    pub fn get_channel_levels(&self) -> [f32; 5] {
+        self.channel_controls.get_levels()
+    }
+
+    /// Get the address of the next DMC sample byte to fetch through the CPU bus, if any
+    pub fn get_dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.get_fetch_address()
+    }
+
+    /// Give the DMC sample byte fetched by the CPU bus
+    pub fn set_dmc_sample(&mut self, value: u8) {
+        self.dmc.set_sample(value);
+    }
 
     /// Read APU registers
+    ///
+    /// Reading $4015 clears the frame IRQ flag
     pub fn read_registers(&mut self, address: u16) -> u8 {
+        let value = self.peek_registers(address);
+        if address == 0x4015 {
+            self.frame_counter.clear_irq();
+        }
+        value
+    }
+
+    /// Read APU registers without side effects
+    pub fn peek_registers(&self, address: u16) -> u8 {
         match address {
             0x4000 => self.pulse_1.get_byte_0(),
             0x4001 => self.pulse_1.get_byte_1(),
@@ -206,234 +453,79 @@ impl Apu {
         };
     }
 
+    /// APU registers summary, used by the trace logger
+    pub fn get_registers_as_string(&self) -> String {
+        format!(
+            "SQ1:{:02X}{:02X}{:02X}{:02X} SQ2:{:02X}{:02X}{:02X}{:02X} TRI:{:02X}{:02X}{:02X} NOI:{:02X}{:02X}{:02X} DMC:{:02X}{:02X}{:02X}{:02X} ST:{:02X}",
+            self.pulse_1.get_byte_0(),
+            self.pulse_1.get_byte_1(),
+            self.pulse_1.get_byte_2(),
+            self.pulse_1.get_byte_3(),
+            self.pulse_2.get_byte_0(),
+            self.pulse_2.get_byte_1(),
+            self.pulse_2.get_byte_2(),
+            self.pulse_2.get_byte_3(),
+            self.triangle.get_byte_0(),
+            self.triangle.get_byte_2(),
+            self.triangle.get_byte_3(),
+            self.noise.get_byte_0(),
+            self.noise.get_byte_2(),
+            self.noise.get_byte_3(),
+            self.dmc.get_byte_0(),
+            self.dmc.get_byte_1(),
+            self.dmc.get_byte_2(),
+            self.dmc.get_byte_3(),
+            self.get_status(),
+        )
+    }
+
+    /// Get the channels status : bits are set while length counters are not 0 or DMC bytes remain,
+    /// then frame IRQ and DMC IRQ flags
     fn get_status(&self) -> u8 {
-        0
+        (self.pulse_1.is_active() as u8)
+            | ((self.pulse_2.is_active() as u8) << 1)
+            | ((self.triangle.is_active() as u8) << 2)
+            | ((self.noise.is_active() as u8) << 3)
+            | ((self.dmc.is_active() as u8) << 4)
+            | ((self.frame_counter.is_irq_raised() as u8) << 6)
+            | ((self.dmc.is_irq_raised() as u8) << 7)
     }
 
-    fn set_status(&mut self, _value: u8) {}
+    /// Enable channels. Disabled channels have their length counter cleared and stay silent
+    fn set_status(&mut self, value: u8) {
+        self.pulse_1.set_enabled((value & 0b1) != 0);
+        self.pulse_2.set_enabled((value & 0b10) != 0);
+        self.triangle.set_enabled((value & 0b100) != 0);
+        self.noise.set_enabled((value & 0b1000) != 0);
+        self.dmc.set_enabled((value & 0b10000) != 0);
+    }
 
     fn get_frame_counter(&self) -> u8 {
-        0
+        self.frame_counter.get_byte()
     }
 
-    fn set_frame_counter(&mut self, _value: u8) {}
+    /// Set the frame counter mode and IRQ inhibit flag
+    fn set_frame_counter(&mut self, value: u8) {
+        self.frame_counter.set_byte(value, self.is_apu_cycle);
+    }
 }
 
-struct Mixer {
-    phase: f32,
-    volume: f32,
-    phase_inc: f32,
+/// SDL audio callback, playing the samples of the ring buffer
+struct AudioOutput {
+    ring_buffer: Arc<RingBuffer>,
+    volume: Arc<AtomicU32>,
+    last_sample: f32, // Held on buffer underrun, avoiding clicks
 }
 
-impl AudioCallback for Mixer {
-    // Temporary approach. Will need to be changed to represent NES APU function
+impl AudioCallback for AudioOutput {
     type Channel = f32;
     fn callback(&mut self, out: &mut [f32]) {
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
         for x in out.iter_mut() {
-            if self.phase >= 0.0 && self.phase <= 0.5 {
-                *x = self.volume;
-            } else {
-                *x = -self.volume;
+            if let Some(sample) = self.ring_buffer.pop() {
+                self.last_sample = sample;
             }
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *x = self.last_sample * volume;
         }
     }
 }
-
-/// Pulse register
-struct Pulse {
-    byte_0: u8,
-    byte_1: u8,
-    byte_2: u8,
-    byte_3: u8,
-    duty: u8,
-    enveloppe_loop_length_counter_halt: bool,
-    constant_volume: bool,
-    volume_envelope: u8,
-    sweep_unit_enabled: bool,
-    sweep_unit_period: u8,
-    sweep_unit_negate: bool,
-    sweep_unit_shift: u8,
-    timer: u16,
-    length_counter_load: u8,
-}
-
-impl Pulse {
-    pub fn set_byte_0(&mut self, value: u8) {
-        self.byte_0 = value;
-        self.duty = value >> 6;
-        self.enveloppe_loop_length_counter_halt = (value & 0b100000) != 0;
-        self.constant_volume = (value & 0b10000) != 0;
-        self.volume_envelope = value & 0b1111
-    }
-
-    pub fn get_byte_0(&self) -> u8 {
-        self.byte_0
-    }
-
-    pub fn set_byte_1(&mut self, value: u8) {
-        self.byte_1 = value;
-        self.sweep_unit_enabled = (value & 0b10000000) != 0;
-        self.sweep_unit_period = (value >> 4) & 0b111;
-        self.sweep_unit_negate = (value & 0b1000) != 0;
-        self.sweep_unit_shift = value & 0b111;
-    }
-
-    pub fn get_byte_1(&self) -> u8 {
-        self.byte_1
-    }
-
-    pub fn set_byte_2(&mut self, value: u8) {
-        self.byte_2 = value;
-        self.timer = (self.timer & 0xff00) | (value as u16);
-    }
-
-    pub fn get_byte_2(&self) -> u8 {
-        self.byte_2
-    }
-
-    pub fn set_byte_3(&mut self, value: u8) {
-        self.byte_3 = value;
-        self.length_counter_load = value >> 3;
-        self.timer = (self.timer & 0xff) | ((value as u16 & 0b111) << 8);
-    }
-
-    pub fn get_byte_3(&self) -> u8 {
-        self.byte_3
-    }
-}
-
-/// Triangle register
-struct Triangle {
-    byte_0: u8,
-    byte_2: u8,
-    byte_3: u8,
-    lenght_counter_halt_linear_counter_control: bool,
-    linear_counter_load: u8,
-    length_counter_load: u8,
-    timer: u16,
-}
-
-impl Triangle {
-    pub fn set_byte_0(&mut self, value: u8) {
-        self.byte_0 = value;
-        self.lenght_counter_halt_linear_counter_control = (value & 0b10000000) != 0;
-        self.linear_counter_load = value & 0x7f;
-    }
-
-    pub fn get_byte_0(&self) -> u8 {
-        self.byte_0
-    }
-
-    pub fn set_byte_2(&mut self, value: u8) {
-        self.byte_2 = value;
-        self.timer = (self.timer & 0xff00) | (value as u16);
-    }
-
-    pub fn get_byte_2(&self) -> u8 {
-        self.byte_2
-    }
-
-    pub fn set_byte_3(&mut self, value: u8) {
-        self.byte_3 = value;
-        self.length_counter_load = value >> 3;
-        self.timer = (self.timer & 0xff) | ((value as u16 & 0b111) << 8);
-    }
-
-    pub fn get_byte_3(&self) -> u8 {
-        self.byte_3
-    }
-}
-
-/// Noise register
-struct Noise {
-    byte_0: u8,
-    byte_2: u8,
-    byte_3: u8,
-    envelope_loop_length_counter_halt: bool,
-    constant_volume: bool,
-    volume_envelope: u8,
-    loop_noise: bool,
-    noise_period: u8,
-    length_counter_load: u8,
-}
-
-impl Noise {
-    pub fn set_byte_0(&mut self, value: u8) {
-        self.byte_0 = value;
-        self.envelope_loop_length_counter_halt = (value & 0b100000) != 0;
-        self.constant_volume = (value & 0b10000) != 0;
-        self.volume_envelope = value & 0b1111;
-    }
-
-    pub fn get_byte_0(&self) -> u8 {
-        self.byte_0
-    }
-
-    pub fn set_byte_2(&mut self, value: u8) {
-        self.byte_2 = value;
-        self.loop_noise = (value & 0x80) != 0;
-        self.noise_period = value & 0xf;
-    }
-
-    pub fn get_byte_2(&self) -> u8 {
-        self.byte_2
-    }
-
-    pub fn set_byte_3(&mut self, value: u8) {
-        self.byte_3 = value;
-        self.length_counter_load = value >> 3;
-    }
-
-    pub fn get_byte_3(&self) -> u8 {
-        self.byte_3
-    }
-}
-
-/// DMC Register
-struct Dmc {
-    byte_0: u8,
-    irq_enabled: bool,
-    loop_sample: bool,
-    frequency: u8,
-    load_counter: u8,
-    sample_address: u8,
-    sample_length: u8,
-}
-
-impl Dmc {
-    pub fn set_byte_0(&mut self, value: u8) {
-        self.byte_0 = value;
-        self.irq_enabled = (value & 0x80) != 0;
-        self.loop_sample = (value & 0x40) != 0;
-        self.frequency = value & 0xf;
-    }
-
-    pub fn get_byte_0(&self) -> u8 {
-        self.byte_0
-    }
-
-    pub fn set_byte_1(&mut self, value: u8) {
-        self.load_counter = value & 0x7f;
-    }
-
-    pub fn get_byte_1(&self) -> u8 {
-        self.load_counter
-    }
-
-    pub fn set_byte_2(&mut self, value: u8) {
-        self.sample_address = value;
-    }
-
-    pub fn get_byte_2(&self) -> u8 {
-        self.sample_address
-    }
-
-    pub fn set_byte_3(&mut self, value: u8) {
-        self.sample_length = value;
-    }
-
-    pub fn get_byte_3(&self) -> u8 {
-        self.sample_length
-    }
-}
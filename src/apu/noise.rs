@@ -0,0 +1,140 @@
+//! Noise channel
+//! 15 bits linear feedback shift register, with a short mode, clocked by a timer from the region period table
+
+use super::units::{Envelope, LengthCounter};
+use crate::region::Region;
+
+/// Noise register and channel state
+pub struct Noise {
+    byte_0: u8,
+    byte_2: u8,
+    byte_3: u8,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+    loop_noise: bool, // Short mode, feedback from bit 6 instead of bit 1
+    noise_period: u8,
+    periods: &'static [u16; 16],
+    timer: u16,
+    shift_register: u16,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            byte_0: 0,
+            byte_2: 0,
+            byte_3: 0,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            loop_noise: false,
+            noise_period: 0,
+            periods: Region::Ntsc.get_noise_periods(),
+            timer: 0,
+            shift_register: 1,
+        }
+    }
+
+    /// Set the region selecting the period table
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = region.get_noise_periods();
+    }
+
+    pub fn set_byte_0(&mut self, value: u8) {
+        self.byte_0 = value;
+        self.length_counter.set_halt((value & 0b100000) != 0);
+        self.envelope.set_byte(value);
+    }
+
+    pub fn get_byte_0(&self) -> u8 {
+        self.byte_0
+    }
+
+    pub fn set_byte_2(&mut self, value: u8) {
+        self.byte_2 = value;
+        self.loop_noise = (value & 0x80) != 0;
+        self.noise_period = value & 0xf;
+    }
+
+    pub fn get_byte_2(&self) -> u8 {
+        self.byte_2
+    }
+
+    /// Set length, restarting the envelope
+    pub fn set_byte_3(&mut self, value: u8) {
+        self.byte_3 = value;
+        self.length_counter.load(value >> 3);
+        self.envelope.restart();
+    }
+
+    pub fn get_byte_3(&self) -> u8 {
+        self.byte_3
+    }
+
+    /// Enable or disable the channel from $4015
+    pub fn set_enabled(&mut self, is_enabled: bool) {
+        self.length_counter.set_enabled(is_enabled);
+    }
+
+    /// Return true while the length counter is not 0, for $4015 reads
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    /// CPU cycle clock, periods being in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.periods[self.noise_period as usize] - 1;
+            let feedback_bit = if self.loop_noise { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> feedback_bit)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Quarter frame clock
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Half frame clock
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    /// Get the channel output, from 0 to 15. Silent while bit 0 of the shift register is set
+    pub fn get_output(&self) -> u8 {
+        if !self.length_counter.is_active() || self.shift_register & 1 != 0 {
+            0
+        } else {
+            self.envelope.get_volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Count shift register clocks until it comes back to its initial value
+    fn get_sequence_length(is_short_mode: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.set_byte_2(if is_short_mode { 0x80 } else { 0x00 });
+        let mut length = 0;
+        loop {
+            for _ in 0..4 {
+                noise.clock_timer();
+            }
+            length += 1;
+            if noise.shift_register == 1 {
+                return length;
+            }
+        }
+    }
+
+    #[test]
+    fn sequence_lengths() {
+        assert_eq!(get_sequence_length(false), 32767);
+        assert_eq!(get_sequence_length(true), 93);
+    }
+}
@@ -0,0 +1,222 @@
+//! Pulse channels
+//! Duty cycle sequencer driven by an 11 bits timer, with envelope, sweep unit and length counter
+
+use super::units::{Envelope, LengthCounter};
+
+/// Duty cycle waveforms, read backwards by the sequencer : 12.5%, 25%, 50% and 25% negated
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Pulse register and channel state
+pub struct Pulse {
+    byte_0: u8,
+    byte_1: u8,
+    byte_2: u8,
+    byte_3: u8,
+    is_pulse_1: bool, // Pulse 1 sweep negates with ones' complement
+    duty: u8,
+    duty_step: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+    sweep_unit_enabled: bool,
+    sweep_unit_period: u8,
+    sweep_unit_negate: bool,
+    sweep_unit_shift: u8,
+    sweep_divider: u8,
+    is_sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(is_pulse_1: bool) -> Pulse {
+        Pulse {
+            byte_0: 0,
+            byte_1: 0,
+            byte_2: 0,
+            byte_3: 0,
+            is_pulse_1,
+            duty: 0,
+            duty_step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            sweep_unit_enabled: false,
+            sweep_unit_period: 0,
+            sweep_unit_negate: false,
+            sweep_unit_shift: 0,
+            sweep_divider: 0,
+            is_sweep_reload: false,
+        }
+    }
+
+    pub fn set_byte_0(&mut self, value: u8) {
+        self.byte_0 = value;
+        self.duty = value >> 6;
+        self.length_counter.set_halt((value & 0b100000) != 0);
+        self.envelope.set_byte(value);
+    }
+
+    pub fn get_byte_0(&self) -> u8 {
+        self.byte_0
+    }
+
+    pub fn set_byte_1(&mut self, value: u8) {
+        self.byte_1 = value;
+        self.sweep_unit_enabled = (value & 0b10000000) != 0;
+        self.sweep_unit_period = (value >> 4) & 0b111;
+        self.sweep_unit_negate = (value & 0b1000) != 0;
+        self.sweep_unit_shift = value & 0b111;
+        self.is_sweep_reload = true;
+    }
+
+    pub fn get_byte_1(&self) -> u8 {
+        self.byte_1
+    }
+
+    pub fn set_byte_2(&mut self, value: u8) {
+        self.byte_2 = value;
+        self.timer_period = (self.timer_period & 0xff00) | (value as u16);
+    }
+
+    pub fn get_byte_2(&self) -> u8 {
+        self.byte_2
+    }
+
+    /// Set timer high bits and length, restarting the envelope and the duty sequence
+    pub fn set_byte_3(&mut self, value: u8) {
+        self.byte_3 = value;
+        self.length_counter.load(value >> 3);
+        self.timer_period = (self.timer_period & 0xff) | ((value as u16 & 0b111) << 8);
+        self.envelope.restart();
+        self.duty_step = 0;
+    }
+
+    pub fn get_byte_3(&self) -> u8 {
+        self.byte_3
+    }
+
+    /// Enable or disable the channel from $4015
+    pub fn set_enabled(&mut self, is_enabled: bool) {
+        self.length_counter.set_enabled(is_enabled);
+    }
+
+    /// Return true while the length counter is not 0, for $4015 reads
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    /// APU cycle clock, every 2 CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_step = self.duty_step.wrapping_sub(1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Quarter frame clock
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Half frame clock
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        let target_period = self.get_sweep_target_period();
+        if self.sweep_divider == 0
+            && self.sweep_unit_enabled
+            && self.sweep_unit_shift > 0
+            && !self.is_muted()
+        {
+            self.timer_period = target_period;
+        }
+        if self.sweep_divider == 0 || self.is_sweep_reload {
+            self.sweep_divider = self.sweep_unit_period;
+            self.is_sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// Get the period the sweep unit is aiming at, computed continuously
+    fn get_sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_unit_shift;
+        if self.sweep_unit_negate {
+            self.timer_period
+                .saturating_sub(change + self.is_pulse_1 as u16)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    /// Channel is muted by a period too low, or a sweep target overflowing, even when the sweep is disabled
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.get_sweep_target_period() > 0x7ff
+    }
+
+    /// Get the channel output, from 0 to 15
+    pub fn get_output(&self) -> u8 {
+        if !self.length_counter.is_active()
+            || self.is_muted()
+            || DUTY_TABLE[self.duty as usize][self.duty_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.get_volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_negate() {
+        // Pulse 1 subtracts one more than pulse 2
+        for (is_pulse_1, expected_period) in [(true, 0x17f), (false, 0x180)] {
+            let mut pulse = Pulse::new(is_pulse_1);
+            pulse.set_byte_1(0b10001001); // Enabled, period 0, negate, shift 1
+            pulse.set_byte_2(0x00);
+            pulse.set_byte_3(0x03);
+            pulse.clock_half_frame();
+            assert_eq!(pulse.timer_period, expected_period);
+        }
+    }
+
+    #[test]
+    fn muting() {
+        let mut pulse = Pulse::new(false);
+        pulse.set_enabled(true);
+        pulse.set_byte_0(0b10111111); // 50% duty, constant volume 15
+        pulse.set_byte_2(0x00);
+        pulse.set_byte_3(0x08); // Length loaded
+        let outputs: Vec<u8> = (0..8)
+            .map(|_| {
+                pulse.duty_step = pulse.duty_step.wrapping_sub(1) & 0b111;
+                pulse.get_output()
+            })
+            .collect();
+        assert_eq!(outputs, vec![0; 8]); // Period lower than 8
+        pulse.set_byte_2(0x80);
+        let outputs: Vec<u8> = (0..8)
+            .map(|_| {
+                pulse.duty_step = pulse.duty_step.wrapping_sub(1) & 0b111;
+                pulse.get_output()
+            })
+            .collect();
+        assert_eq!(outputs.iter().filter(|output| **output == 15).count(), 4);
+        pulse.set_byte_3(0x07); // Period 0x780, sweep target overflows
+        assert_eq!(pulse.get_output(), 0);
+        pulse.set_enabled(false);
+        pulse.set_byte_3(0x08);
+        assert!(!pulse.is_active());
+    }
+}
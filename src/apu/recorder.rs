@@ -0,0 +1,134 @@
+//! Audio recorder
+//! Records the final mix, or each channel played alone, to WAV files. The recording has its own resampling at a
+//! fixed rate, independent of the audio device, the volume and the dynamic rate control
+
+use super::mixer::Mixer;
+use super::wav::WavWriter;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Channel names, used as file name suffixes
+const CHANNEL_NAMES: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+
+/// What to record
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RecordMode {
+    /// Final mix, in a single file
+    Mix,
+    /// One file per channel, named after the channel
+    Channels,
+}
+
+impl FromStr for RecordMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<RecordMode, String> {
+        match value.to_lowercase().as_str() {
+            "mix" => Ok(RecordMode::Mix),
+            "channels" => Ok(RecordMode::Channels),
+            _ => Err(format!("Unknown record mode : {}", value)),
+        }
+    }
+}
+
+/// One recorded file with its resampler
+struct Track {
+    mixer: Mixer,
+    wav: WavWriter,
+}
+
+/// WAV recorder, fed with the mixer inputs of every CPU cycle
+pub struct Recorder {
+    mode: RecordMode,
+    tracks: Vec<Track>,
+    samples: Vec<f32>,
+}
+
+impl Recorder {
+    /// Create the WAV files. In channels mode, the channel name is appended to the file stem
+    pub fn create(
+        file_name: &str,
+        mode: RecordMode,
+        clock_rate: f64,
+        sample_rate: u32,
+    ) -> std::io::Result<Recorder> {
+        let file_names = match mode {
+            RecordMode::Mix => vec![file_name.to_string()],
+            RecordMode::Channels => {
+                let path = Path::new(file_name);
+                let stem = path.with_extension("");
+                CHANNEL_NAMES
+                    .iter()
+                    .map(|name| format!("{}_{}.wav", stem.display(), name))
+                    .collect()
+            }
+        };
+        let mut tracks = vec![];
+        for file_name in file_names.iter() {
+            tracks.push(Track {
+                mixer: Mixer::new(clock_rate, sample_rate as f64),
+                wav: WavWriter::create(file_name, sample_rate)?,
+            });
+        }
+        Ok(Recorder {
+            mode,
+            tracks,
+            samples: vec![],
+        })
+    }
+
+    /// Add the mixer inputs of a CPU cycle
+    pub fn add_sample(&mut self, inputs: &[f32; 5]) -> std::io::Result<()> {
+        for (channel, track) in self.tracks.iter_mut().enumerate() {
+            let amplitude = match self.mode {
+                RecordMode::Mix => track.mixer.mix(inputs),
+                RecordMode::Channels => track.mixer.mix_channel(channel, inputs[channel]),
+            };
+            if track.mixer.add_amplitude(amplitude) {
+                track.mixer.read_samples(&mut self.samples);
+                track.wav.write_samples(&self.samples)?;
+                self.samples.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Complete the WAV files
+    pub fn finish(mut self) -> std::io::Result<()> {
+        for track in self.tracks.iter_mut() {
+            track.wav.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_files() {
+        let directory = std::env::temp_dir().join("nes_recorder_test");
+        std::fs::create_dir_all(&directory).unwrap();
+        let file_name = directory.join("song.wav");
+        let mut recorder = Recorder::create(
+            file_name.to_str().unwrap(),
+            RecordMode::Channels,
+            1_789_773.0,
+            44100,
+        )
+        .unwrap();
+        for cycle in 0..1_789_773 {
+            let level = ((cycle / 2000) % 2 * 15) as f32;
+            recorder.add_sample(&[level, 0.0, 0.0, 0.0, 0.0]).unwrap();
+        }
+        recorder.finish().unwrap();
+        let pulse = std::fs::read(directory.join("song_pulse1.wav")).unwrap();
+        let dmc = std::fs::read(directory.join("song_dmc.wav")).unwrap();
+        // One second of 16 bits samples, except the last partial mix frame
+        assert!((pulse.len() as i32 - 44 - 88200).abs() < 2 * 64);
+        assert_eq!(pulse.len(), dmc.len());
+        assert!(pulse[44..].chunks(2).any(|sample| sample != [0, 0]));
+        assert!(dmc[44..].chunks(2).all(|sample| sample == [0, 0]));
+    }
+}
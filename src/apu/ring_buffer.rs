@@ -0,0 +1,85 @@
+//! Lock-free sample ring buffer
+//! Single producer (emulation thread), single consumer (audio callback thread)
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Ring buffer of f32 samples, stored as bits in atomics
+pub struct RingBuffer {
+    samples: Vec<AtomicU32>,
+    mask: usize,
+    read_index: AtomicUsize,
+    write_index: AtomicUsize,
+}
+
+impl RingBuffer {
+    /// Instantiate a buffer, the capacity is rounded up to a power of two
+    pub fn new(capacity: usize) -> RingBuffer {
+        let capacity = capacity.next_power_of_two();
+        RingBuffer {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            mask: capacity - 1,
+            read_index: AtomicUsize::new(0),
+            write_index: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the number of samples waiting to be read
+    pub fn len(&self) -> usize {
+        self.write_index
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.read_index.load(Ordering::Acquire))
+    }
+
+    /// Add a sample, from the producer. Returns false and drops the sample if the buffer is full
+    pub fn push(&self, sample: f32) -> bool {
+        let write_index = self.write_index.load(Ordering::Relaxed);
+        if write_index.wrapping_sub(self.read_index.load(Ordering::Acquire)) == self.samples.len() {
+            return false;
+        }
+        self.samples[write_index & self.mask].store(sample.to_bits(), Ordering::Relaxed);
+        self.write_index
+            .store(write_index.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Take the oldest sample, from the consumer
+    pub fn pop(&self) -> Option<f32> {
+        let read_index = self.read_index.load(Ordering::Relaxed);
+        if read_index == self.write_index.load(Ordering::Acquire) {
+            return None;
+        }
+        let sample = f32::from_bits(self.samples[read_index & self.mask].load(Ordering::Relaxed));
+        self.read_index
+            .store(read_index.wrapping_add(1), Ordering::Release);
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn threaded_transfer() {
+        let ring = Arc::new(RingBuffer::new(100));
+        let producer_ring = ring.clone();
+        let producer = thread::spawn(move || {
+            for value in 0..10_000 {
+                while !producer_ring.push(value as f32) {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 10_000 {
+            if let Some(sample) = ring.pop() {
+                assert_eq!(sample, expected as f32);
+                expected += 1;
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(ring.len(), 0);
+    }
+}
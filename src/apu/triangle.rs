@@ -0,0 +1,151 @@
+//! Triangle channel
+//! 32 steps sequence driven by an 11 bits timer clocked every CPU cycle, gated by the linear and length counters
+
+use super::units::LengthCounter;
+
+/// Triangle waveform, from 15 down to 0 then back up
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// Triangle register and channel state
+pub struct Triangle {
+    byte_0: u8,
+    byte_2: u8,
+    byte_3: u8,
+    lenght_counter_halt_linear_counter_control: bool,
+    linear_counter_load: u8,
+    linear_counter: u8,
+    is_linear_counter_reload: bool,
+    length_counter: LengthCounter,
+    timer_period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Triangle {
+    pub fn new() -> Triangle {
+        Triangle {
+            byte_0: 0,
+            byte_2: 0,
+            byte_3: 0,
+            lenght_counter_halt_linear_counter_control: false,
+            linear_counter_load: 0,
+            linear_counter: 0,
+            is_linear_counter_reload: false,
+            length_counter: LengthCounter::new(),
+            timer_period: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    pub fn set_byte_0(&mut self, value: u8) {
+        self.byte_0 = value;
+        self.lenght_counter_halt_linear_counter_control = (value & 0b10000000) != 0;
+        self.length_counter
+            .set_halt(self.lenght_counter_halt_linear_counter_control);
+        self.linear_counter_load = value & 0x7f;
+    }
+
+    pub fn get_byte_0(&self) -> u8 {
+        self.byte_0
+    }
+
+    pub fn set_byte_2(&mut self, value: u8) {
+        self.byte_2 = value;
+        self.timer_period = (self.timer_period & 0xff00) | (value as u16);
+    }
+
+    pub fn get_byte_2(&self) -> u8 {
+        self.byte_2
+    }
+
+    /// Set timer high bits and length, reloading the linear counter on next quarter frame
+    pub fn set_byte_3(&mut self, value: u8) {
+        self.byte_3 = value;
+        self.length_counter.load(value >> 3);
+        self.timer_period = (self.timer_period & 0xff) | ((value as u16 & 0b111) << 8);
+        self.is_linear_counter_reload = true;
+    }
+
+    pub fn get_byte_3(&self) -> u8 {
+        self.byte_3
+    }
+
+    /// Enable or disable the channel from $4015
+    pub fn set_enabled(&mut self, is_enabled: bool) {
+        self.length_counter.set_enabled(is_enabled);
+    }
+
+    /// Return true while the length counter is not 0, for $4015 reads
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    /// CPU cycle clock. The sequence stops, holding its output, when a counter is 0
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            // Ultrasonic periods are not stepped, avoiding pops like most emulators
+            if self.linear_counter > 0 && self.length_counter.is_active() && self.timer_period >= 2
+            {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Quarter frame clock
+    pub fn clock_quarter_frame(&mut self) {
+        if self.is_linear_counter_reload {
+            self.linear_counter = self.linear_counter_load;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.lenght_counter_halt_linear_counter_control {
+            self.is_linear_counter_reload = false;
+        }
+    }
+
+    /// Half frame clock
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    /// Get the channel output, from 0 to 15
+    pub fn get_output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.step as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_counter() {
+        let mut triangle = Triangle::new();
+        triangle.set_enabled(true);
+        triangle.set_byte_0(0x02); // Linear counter of 2 quarter frames
+        triangle.set_byte_2(0x02);
+        triangle.set_byte_3(0x08);
+        for _ in 0..3 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.get_output(), 15); // Linear counter not reloaded yet
+        triangle.clock_quarter_frame();
+        for _ in 0..3 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.get_output(), 14);
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        for _ in 0..30 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.get_output(), 14); // Linear counter expired, output held
+    }
+}
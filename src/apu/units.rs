@@ -0,0 +1,160 @@
+//! Units shared by the APU channels : length counter and envelope generator
+
+/// Length counter load values, indexed by the 5 high bits written to the last register of a channel
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Length counter, silencing a channel when it reaches 0. Clocked on half frames
+pub struct LengthCounter {
+    counter: u8,
+    is_halted: bool,
+    is_enabled: bool, // Channel enable bit of $4015
+}
+
+impl LengthCounter {
+    pub fn new() -> LengthCounter {
+        LengthCounter {
+            counter: 0,
+            is_halted: false,
+            is_enabled: false,
+        }
+    }
+
+    /// Enable or disable the channel. Disabling clears the counter
+    pub fn set_enabled(&mut self, is_enabled: bool) {
+        self.is_enabled = is_enabled;
+        if !is_enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// Halt or resume counting
+    pub fn set_halt(&mut self, is_halted: bool) {
+        self.is_halted = is_halted;
+    }
+
+    /// Load the counter from its 5 bits table index, if the channel is enabled
+    pub fn load(&mut self, index: u8) {
+        if self.is_enabled {
+            self.counter = LENGTH_TABLE[index as usize & 0x1f];
+        }
+    }
+
+    /// Half frame clock
+    pub fn clock(&mut self) {
+        if !self.is_halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    /// Return true while the channel is not silenced by the counter
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+/// Envelope generator, giving a constant volume or a decaying saw. Clocked on quarter frames
+pub struct Envelope {
+    is_start: bool,
+    is_loop: bool,
+    is_constant: bool,
+    volume: u8, // Constant volume, or divider period
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            is_start: false,
+            is_loop: false,
+            is_constant: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    /// Set from the first register of a channel : loop flag, constant volume flag and volume
+    pub fn set_byte(&mut self, value: u8) {
+        self.is_loop = (value & 0b100000) != 0;
+        self.is_constant = (value & 0b10000) != 0;
+        self.volume = value & 0b1111;
+    }
+
+    /// Restart the decay, when the last register of the channel is written
+    pub fn restart(&mut self) {
+        self.is_start = true;
+    }
+
+    /// Quarter frame clock
+    pub fn clock(&mut self) {
+        if self.is_start {
+            self.is_start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider > 0 {
+            self.divider -= 1;
+        } else {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.is_loop {
+                self.decay = 15;
+            }
+        }
+    }
+
+    /// Get the output volume, from 0 to 15
+    pub fn get_volume(&self) -> u8 {
+        if self.is_constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_decay() {
+        let mut envelope = Envelope::new();
+        envelope.set_byte(0b00001); // Decay, divider period of 2 clocks
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 15);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 14);
+        for _ in 0..40 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.get_volume(), 0);
+        envelope.set_byte(0b110111); // Loop and constant volume
+        assert_eq!(envelope.get_volume(), 7);
+    }
+
+    #[test]
+    fn length_counter() {
+        let mut length_counter = LengthCounter::new();
+        length_counter.load(1);
+        assert!(!length_counter.is_active()); // Not loaded while disabled
+        length_counter.set_enabled(true);
+        length_counter.load(3); // 2 half frames
+        length_counter.clock();
+        assert!(length_counter.is_active());
+        length_counter.clock();
+        assert!(!length_counter.is_active());
+        length_counter.load(1);
+        length_counter.set_halt(true);
+        length_counter.clock();
+        assert!(length_counter.is_active());
+        length_counter.set_enabled(false);
+        assert!(!length_counter.is_active());
+    }
+}
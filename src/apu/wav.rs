@@ -0,0 +1,98 @@
+//! WAV file writer
+//! Mono 16 bits PCM, the sizes of the header are written when the file is finished
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+/// Size of the RIFF, fmt and data chunk headers
+const HEADER_SIZE: u32 = 44;
+
+/// Build the header of a mono 16 bits PCM WAV file holding `data_size` bytes of samples
+pub fn encode_wav_header(sample_rate: u32, data_size: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(HEADER_SIZE - 8 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // mono
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    header.extend_from_slice(&2u16.to_le_bytes()); // bytes per sample
+    header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+/// Convert a sample from -1.0..1.0 to 16 bits PCM
+fn to_pcm(sample: f32) -> i16 {
+    (sample * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// WAV file being recorded
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl WavWriter {
+    /// Create the file, with a header completed by `finish`
+    pub fn create(file_name: &str, sample_rate: u32) -> std::io::Result<WavWriter> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        writer.write_all(&encode_wav_header(sample_rate, 0))?;
+        Ok(WavWriter {
+            writer,
+            sample_rate,
+            data_size: 0,
+        })
+    }
+
+    /// Append samples
+    pub fn write_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        for sample in samples.iter() {
+            self.writer.write_all(&to_pcm(*sample).to_le_bytes())?;
+        }
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    /// Write the sizes into the header. Samples written after are lost
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer
+            .write_all(&encode_wav_header(self.sample_rate, self.data_size))?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    /// Keep the file readable if it was not finished
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_header() {
+        let header = encode_wav_header(44100, 88200);
+        assert_eq!(header.len(), HEADER_SIZE as usize);
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(header[4..8].try_into().unwrap()), 88236);
+        assert_eq!(
+            u32::from_le_bytes(header[24..28].try_into().unwrap()),
+            44100
+        );
+        assert_eq!(
+            u32::from_le_bytes(header[40..44].try_into().unwrap()),
+            88200
+        );
+        assert_eq!(to_pcm(1.5), i16::MAX);
+        assert_eq!(to_pcm(-0.5), -16383);
+    }
+}
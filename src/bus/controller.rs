@@ -92,4 +92,9 @@ impl Controller {
     pub fn clear_right(&mut self) {
         self.status &= 0b01111111;
     }
+
+    /// Set all buttons at once : A, B, Select, Start, Up, Down, Left and Right from bit 0 to 7
+    pub fn set_status(&mut self, buttons: u8) {
+        self.status = buttons;
+    }
 }
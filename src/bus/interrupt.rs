@@ -1,6 +1,7 @@
 pub struct Interrupt {
     is_nmi: bool,
     is_irq: bool,
+    irq_count: u32, // IRQ assertions, for the PPU event log
     is_frame_updated: bool,
 }
 
@@ -10,6 +11,7 @@ impl Interrupt {
         Interrupt {
             is_nmi: false,
             is_irq: false,
+            irq_count: 0,
             is_frame_updated: false,
         }
     }
@@ -19,6 +21,11 @@ impl Interrupt {
         self.is_nmi = true;
     }
 
+    /// Cancel an NMI not taken yet by the CPU
+    pub fn cancel_nmi(&mut self) {
+        self.is_nmi = false;
+    }
+
     /// Checked and clear NMI interrupt
     pub fn check_and_clear_nmi(&mut self) -> bool {
         let ret = self.is_nmi;
@@ -27,15 +34,24 @@ impl Interrupt {
     }
 
     /// Raises an IRQ interrupt
-    pub fn _raise_irq(&mut self) {
+    pub fn raise_irq(&mut self) {
         self.is_irq = true;
+        self.irq_count = self.irq_count.wrapping_add(1);
     }
 
-    /// Checked and clear NMI interrupt
-    pub fn check_and_clear_irq(&mut self) -> bool {
-        let ret = self.is_irq;
+    /// Get the number of IRQ assertions since power on
+    pub fn get_irq_count(&self) -> u32 {
+        self.irq_count
+    }
+
+    /// Release the IRQ line, when its source acknowledges it
+    pub fn clear_irq(&mut self) {
         self.is_irq = false;
-        ret
+    }
+
+    /// Return true while the IRQ line is held. IRQ is level triggered, the CPU does not clear it
+    pub fn is_irq(&self) -> bool {
+        self.is_irq
     }
 
     /// Set is_frame_updated to true
@@ -2,12 +2,19 @@
 use crate::apu::Apu;
 use crate::bus::controller::Controller;
 use crate::cartridge::Cartridge;
-use crate::ppu::Ppu;
+use crate::ppu::{Ppu, PpuEventKind};
 use std::cell::RefCell;
 use std::rc::Rc;
 
 use super::interrupt::Interrupt;
 
+/// Memory access recorded for debugging purpose
+pub struct MemoryAccess {
+    pub address: u16,
+    pub value: u8,
+    pub is_write: bool,
+}
+
 pub struct Bus<'a> {
     internal_ram: [u8; 0x800],
     pub apu: Apu,
@@ -18,6 +25,8 @@ pub struct Bus<'a> {
     controller_2_status: u8,
     cartridge: Rc<RefCell<Cartridge>>,
     pub interrupt: Rc<RefCell<Interrupt>>,
+    is_access_log_enabled: bool,
+    access_log: Vec<MemoryAccess>,
 }
 
 impl Bus<'_> {
@@ -37,9 +46,57 @@ impl Bus<'_> {
             controller_1_status: 0,
             controller_2_status: 0,
             interrupt: _interrupt,
+            is_access_log_enabled: false,
+            access_log: vec![],
+        }
+    }
+
+    /// Record every CPU memory access, and PPU memory accesses through 0x2007, for watchpoints
+    pub fn enable_access_log(&mut self) {
+        self.is_access_log_enabled = true;
+        self.ppu.enable_access_log();
+    }
+
+    /// Get CPU memory accesses recorded since last clear
+    pub fn get_access_log(&self) -> &[MemoryAccess] {
+        &self.access_log
+    }
+
+    /// Clear recorded CPU and PPU memory accesses
+    pub fn clear_access_log(&mut self) {
+        self.access_log.clear();
+        self.ppu.clear_access_log();
+    }
+
+    /// Record a memory access if access log is enabled
+    fn log_access(&mut self, address: u16, value: u8, is_write: bool) {
+        if self.is_access_log_enabled {
+            self.access_log.push(MemoryAccess {
+                address,
+                value,
+                is_write,
+            });
+        }
+    }
+
+    /// Next CPU cycle of the APU. DMC sample fetches read the bus and return the CPU cycles they steal
+    pub fn next_apu_cycle(&mut self) -> u32 {
+        self.apu.next();
+        match self.apu.get_dmc_fetch_address() {
+            Some(address) => {
+                let value = self.read_rom(address);
+                self.apu.set_dmc_sample(value);
+                4
+            }
+            None => 0,
         }
     }
 
+    /// Get PRG ROM bank mapped at this address, if any
+    pub fn get_prg_bank(&self, address: u16) -> Option<usize> {
+        self.cartridge.borrow().get_prg_bank(address)
+    }
+
     /// Read 16-bit little endian address from memory
     pub fn read_rom_16(&mut self, address: u16) -> u16 {
         let high;
@@ -54,6 +111,8 @@ impl Bus<'_> {
             low = self.internal_ram[address as usize];
             high = self.internal_ram[(address + 1) as usize]; // So that reading never cross pages
         }
+        self.log_access(address, low, false);
+        self.log_access(address + 1, high, false);
         low as u16 + ((high as u16) << 8)
     }
 
@@ -72,6 +131,8 @@ impl Bus<'_> {
             low = self.internal_ram[address as usize];
             high = self.internal_ram[high_address as usize]; // So that reading never cross pages
         }
+        self.log_access(address, low, false);
+        self.log_access(high_address, high, false);
         low as u16 + ((high as u16) << 8)
     }
 
@@ -84,7 +145,7 @@ impl Bus<'_> {
     /// 0x6000 to 0x7fff : Cartridge ram
     /// 0x8000 to 0xffff : Cartridge prg_rom
     pub fn read_rom(&mut self, address: u16) -> u8 {
-        match address {
+        let value = match address {
             0..=0x1fff => self.internal_ram[(address % 0x800) as usize],
             0x2000..=0x3fff => {
                 let local_address = 0x2000 + (address % 8);
@@ -92,7 +153,7 @@ impl Bus<'_> {
                     0x2002 => self.ppu.read_0x2002(),
                     0x2004 => self.ppu.read_0x2004(),
                     0x2007 => self.ppu.read_0x2007(),
-                    _ => panic!("Write only address : {}", address),
+                    _ => self.ppu.read_open_bus(), // Write only registers
                 }
             }
             0x4000..=0x4017 => {
@@ -117,6 +178,25 @@ impl Bus<'_> {
             0x4020..=0x5fff => 0, // Cartridge space, but for what ?
             0x6000..=0x7fff => self.cartridge.borrow_mut().read_ram(address - 0x6000),
             0x8000..=0xffff => self.cartridge.borrow_mut().read_prg_rom(address - 0x8000),
+        };
+        self.log_access(address, value, false);
+        value
+    }
+
+    /// Read memory without side effects, for debuggers and tooling
+    ///
+    /// Returns what read_rom would return, without clearing vblank, moving VRAM address or shifting controllers
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0..=0x1fff => self.internal_ram[(address % 0x800) as usize],
+            0x2000..=0x3fff => self.ppu.peek_register(0x2000 + (address % 8)),
+            0x4016 => self.controller_1_status & 1,
+            0x4017 => self.controller_2_status & 1,
+            0x4000..=0x4015 => self.apu.peek_registers(address),
+            0x4018..=0x401f => 0, // Normally disabled
+            0x4020..=0x5fff => 0, // Cartridge space, but for what ?
+            0x6000..=0x7fff => self.cartridge.borrow().peek_ram(address - 0x6000),
+            0x8000..=0xffff => self.cartridge.borrow().peek_prg_rom(address - 0x8000),
         }
     }
 
@@ -129,14 +209,18 @@ impl Bus<'_> {
     /// 0x6000 to 0x7fff : Cartridge ram
     /// 0x8000 to 0xffff : Cartridge prg_rom
     pub fn write_rom(&mut self, address: u16, value: u8) -> u32 {
+        self.log_access(address, value, true);
         match address {
             0..=0x1fff => self.internal_ram[(address % 0x800) as usize] = value,
             0x2000..=0x3fff => {
                 let local_address = 0x2000 + (address % 8);
+                self.ppu
+                    .log_event(PpuEventKind::RegisterWrite(local_address, value));
+                self.ppu.refresh_open_bus(value, 0xff);
                 match local_address {
                     0x2000 => self.ppu.write_0x2000(value),
                     0x2001 => self.ppu.write_0x2001(value),
-                    0x2002 => panic!("Read only address {}", address), // Read-only
+                    0x2002 => (), // Read-only, only drives open bus
                     0x2003 => self.ppu.write_0x2003(value),
                     0x2004 => self.ppu.write_0x2004(value),
                     0x2005 => self.ppu.write_0x2005(value),
@@ -151,6 +235,7 @@ impl Bus<'_> {
                     0x4014 => {
                         let start = (value as usize) << 8;
                         let end = start + 0x100 - 1;
+                        self.ppu.log_event(PpuEventKind::OamDma(value));
                         self.ppu.write_oamdma(&self.internal_ram[start..=end]);
                         return 514;
                     }
@@ -166,15 +251,21 @@ impl Bus<'_> {
                 }
             }
             0x4018..=0x401f => (), // Normally disabled
-            0x4020..=0x5fff => (), // Cartridge space, but for what ?
+            // Cartridge space, used by the registers of some mappers
+            0x4020..=0x5fff => self
+                .ppu
+                .log_event(PpuEventKind::MapperWrite(address, value)),
             0x6000..=0x7fff => self
                 .cartridge
                 .borrow_mut()
                 .write_ram(address - 0x6000, value),
-            0x8000..=0xffff => self
-                .cartridge
-                .borrow_mut()
-                .write_prg_rom(address - 0x8000, value),
+            0x8000..=0xffff => {
+                self.ppu
+                    .log_event(PpuEventKind::MapperWrite(address, value));
+                self.cartridge
+                    .borrow_mut()
+                    .write_prg_rom(address - 0x8000, value)
+            }
         }
         0
     }
@@ -189,43 +280,11 @@ impl Bus<'_> {
     }
 
     /// Print 0x20 long memory chunk
-    pub fn _get_memory_as_string(&self, address: u16) -> String {
-        let address = address as usize;
-        format!("{:04x}:{:04x}    {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x}",
-            address,
-            address + 0x1f,
-            self.internal_ram[address],
-            self.internal_ram[address+1],
-            self.internal_ram[address+2],
-            self.internal_ram[address+3],
-            self.internal_ram[address+4],
-            self.internal_ram[address+5],
-            self.internal_ram[address+6],
-            self.internal_ram[address+7],
-            self.internal_ram[address+8],
-            self.internal_ram[address+9],
-            self.internal_ram[address+10],
-            self.internal_ram[address+11],
-            self.internal_ram[address+12],
-            self.internal_ram[address+13],
-            self.internal_ram[address+14],
-            self.internal_ram[address+15],
-            self.internal_ram[address+16],
-            self.internal_ram[address+17],
-            self.internal_ram[address+18],
-            self.internal_ram[address+19],
-            self.internal_ram[address+20],
-            self.internal_ram[address+21],
-            self.internal_ram[address+22],
-            self.internal_ram[address+23],
-            self.internal_ram[address+24],
-            self.internal_ram[address+25],
-            self.internal_ram[address+26],
-            self.internal_ram[address+27],
-            self.internal_ram[address+28],
-            self.internal_ram[address+29],
-            self.internal_ram[address+30],
-            self.internal_ram[address+31],
-        )
+    pub fn get_memory_as_string(&self, address: u16) -> String {
+        let mut line = format!("{:04x}:{:04x}   ", address, address.wrapping_add(0x1f));
+        for i in 0..0x20 {
+            line.push_str(&format!(" {:02x}", self.peek(address.wrapping_add(i))));
+        }
+        line
     }
 }
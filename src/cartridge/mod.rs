@@ -1,4 +1,5 @@
 //! Cartridge object
+use crate::region::Region;
 use log::info;
 use std::fs::File;
 use std::io::BufReader;
@@ -8,6 +9,7 @@ pub struct Cartridge {
     pub file_name: String,
     magic: Vec<u8>,
     prg_rom_size: usize,
+    prg_bank_count: usize,
     chr_rom_size: usize,
     f6: u8,
     is_trainer: bool,
@@ -16,6 +18,8 @@ pub struct Cartridge {
     prg_ram_size: usize,
     f9: u8,
     f10: u8,
+    region: Option<Region>,
+    crc32: u32,
 
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
@@ -33,6 +37,7 @@ impl Cartridge {
             file_name: String::new(),
             magic: vec![],
             prg_rom_size: 0,
+            prg_bank_count: 0,
             chr_rom_size: 0,
             f6: 0,
             is_trainer: false,
@@ -41,6 +46,8 @@ impl Cartridge {
             prg_ram_size: 0,
             f9: 0,
             f10: 0,
+            region: None,
+            crc32: 0,
 
             prg_rom: vec![],
             chr_rom: vec![],
@@ -58,7 +65,7 @@ impl Cartridge {
     /// Parse a rom
     pub fn parse_rom(&mut self, file_name: String) {
         info!("Attemp to read file : {}", file_name);
-        let file = File::open(file_name).unwrap();
+        let file = File::open(&file_name).unwrap();
         let mut buf_reader = BufReader::new(file);
         buf_reader = self.parse_header(buf_reader);
 
@@ -66,7 +73,8 @@ impl Cartridge {
 
         self.prg_rom = vec![];
         self.chr_rom = vec![];
-        self.prg_ram = vec![];
+        // A size of 0 in header means 8kB for compatibility
+        self.prg_ram = vec![0; self.prg_ram_size.max(0x2000)];
 
         if self.is_trainer {
             buf_reader
@@ -86,6 +94,7 @@ impl Cartridge {
             .take(self.chr_rom_size as u64)
             .read_to_end(&mut self.chr_rom)
             .expect("File too short, check your file for error");
+        self.crc32 = crc32(self.prg_rom.iter().chain(self.chr_rom.iter()));
 
         // Mapper 0 trick
         if self.prg_rom_size == 0x4000 {
@@ -100,7 +109,7 @@ impl Cartridge {
             self.chr_rom.extend(tmp_vec.iter());
         }
 
-        //self.file_name = file_name.clone();
+        self.file_name = file_name;
         info!("{}", self.file_name)
     }
 
@@ -124,6 +133,7 @@ impl Cartridge {
             .expect("File too short, check your file for error");
         info!("Byte for prg_rom_size is {}", tmp[0]);
         self.prg_rom_size = (tmp[0] as usize) * 16 * 1024;
+        self.prg_bank_count = tmp[0] as usize;
         let mut tmp: Vec<u8> = vec![];
         buf_reader
             .by_ref()
@@ -168,7 +178,7 @@ impl Cartridge {
             .read_to_end(&mut tmp)
             .expect("File too short, check your file for error");
         self.f10 = tmp[0];
-        // Read header padding bytes
+        // Read header padding bytes, or NES 2.0 extension bytes 11 to 15
         let mut tmp: Vec<u8> = vec![];
         buf_reader
             .by_ref()
@@ -176,6 +186,21 @@ impl Cartridge {
             .read_to_end(&mut tmp)
             .expect("File too short, check your file for error");
 
+        let is_nes_2 = (self.f7 & 0b1100) == 0b1000;
+        self.region = if is_nes_2 {
+            // Byte 12 : CPU/PPU timing
+            match tmp[1] & 0b11 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                3 => Some(Region::Dendy),
+                _ => None, // Multiple regions
+            }
+        } else if self.f9 & 1 != 0 {
+            Some(Region::Pal)
+        } else {
+            None // iNES header rarely sets the TV system, NTSC is only a guess
+        };
+
         self.mapper_id = (self.f7 as u16 & 0b11110000) + ((self.f6 as u16 & 0b11110000) >> 4);
 
         buf_reader
@@ -214,6 +239,44 @@ impl Cartridge {
 }
 
 impl Cartridge {
+    /// Get the region declared in the ROM header, if any
+    pub fn get_region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /// Get the CRC32 of PRG and CHR ROM, as used by ROM databases
+    pub fn get_crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// Get the whole PRG ROM content
+    pub fn get_prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    /// Get the 16kB PRG ROM bank mapped at a CPU address, if any
+    pub fn get_prg_bank(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 || self.prg_bank_count == 0 {
+            return None;
+        }
+        Some(((address - 0x8000) as usize / 0x4000) % self.prg_bank_count)
+    }
+
+    /// Read cartridge RAM without side effects
+    pub fn peek_ram(&self, address: u16) -> u8 {
+        self.prg_ram[address as usize]
+    }
+
+    /// Read cartridge PRG ROM without side effects
+    pub fn peek_prg_rom(&self, address: u16) -> u8 {
+        self.prg_rom[address as usize]
+    }
+
+    /// Read cartridge CHR ROM without side effects
+    pub fn peek_chr_rom(&self, address: u16) -> u8 {
+        self.chr_rom[address as usize]
+    }
+
     /// Read cartridge RAM
     pub fn read_ram(&mut self, address: u16) -> u8 {
         self.prg_ram[address as usize]
@@ -244,3 +307,39 @@ impl Cartridge {
         self.chr_rom[address as usize] = value;
     }
 }
+
+/// Compute the standard CRC32 (IEEE 802.3) of a byte sequence
+pub fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Cartridge};
+    use crate::test_utils::write_test_rom;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789".iter()), 0xcbf43926);
+    }
+
+    #[test]
+    fn nrom_mirroring() {
+        let cartridge = Cartridge::new(write_test_rom("cartridge", &[0xa9, 0x42]));
+        assert_eq!(cartridge.get_prg_rom().len(), 0x8000);
+        assert_eq!(cartridge.peek_prg_rom(0x4001), 0x42);
+        assert_eq!(cartridge.peek_prg_rom(0x7ffc), 0x00);
+        assert_eq!(cartridge.peek_prg_rom(0x7ffd), 0xc0);
+    }
+}
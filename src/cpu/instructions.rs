@@ -8,63 +8,6 @@ pub struct Instruction {
     pub operation: fn(cpu: &mut Cpu) -> InstructionResult,
 }
 
-impl Instruction {
-    pub fn get_syntax(&self, cpu: &mut Cpu) -> String {
-        let instruction_name = self.name.to_string();
-        let argument = match self.mode {
-            InstructionMode::Implied => String::new(),
-            InstructionMode::Immediate => format!("#${:02x}", cpu.get_immediate()).to_uppercase(),
-            InstructionMode::Accumulator => String::from("A"),
-            InstructionMode::Absolute => String::from(format!(
-                "${:04x} = {:02x}",
-                cpu.get_absolute_address(),
-                cpu.get_absolute_value()
-            ))
-            .to_uppercase(),
-            InstructionMode::AbsoluteX => String::from(format!(
-                "${:04x} = {:02x}",
-                cpu.get_absolute_x_address(false),
-                cpu.get_absolute_x_value(false)
-            ))
-            .to_uppercase(),
-            InstructionMode::AbsoluteY => String::from(format!(
-                "${:04x} = {:02x}",
-                cpu.get_absolute_y_address(false),
-                cpu.get_absolute_y_value(false)
-            ))
-            .to_uppercase(),
-            InstructionMode::ZeroPage => String::from(format!(
-                "${:02x} = {:02x}",
-                cpu.get_immediate(),
-                cpu.get_zero_page_value()
-            ))
-            .to_uppercase(),
-            InstructionMode::ZeroPageX => String::from(format!(
-                "${:04x} = {:02x}",
-                cpu.get_zero_page_x_address(),
-                cpu.get_zero_page_x_value()
-            ))
-            .to_uppercase(),
-            InstructionMode::ZeroPageY => String::from(format!(
-                "${:04x} = {:02x}",
-                cpu.get_zero_page_y_address(),
-                cpu.get_zero_page_y_value()
-            ))
-            .to_uppercase(),
-
-            InstructionMode::IndirectX => String::from(format!(
-                "(${:02x},X) @ 80 = {:04x} = {:02x}",
-                cpu.get_immediate(),
-                cpu.get_indirect_x_address(),
-                cpu.get_indirect_x_value(),
-            ))
-            .to_uppercase(),
-            _ => String::new(),
-        };
-        format!("{} {}", instruction_name, argument)
-    }
-}
-
 pub struct InstructionResult {
     pub step: u16,
     pub remaining_cycles: u32,
@@ -97,6 +40,7 @@ pub enum InstructionCode {
     BIT,
     BCC,
     BCS,
+    BEQ,
     BMI,
     BNE,
     BPL,
@@ -174,6 +118,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
             cpu.push((cpu.program_counter >> 8) as u8);
             cpu.push((cpu.program_counter & 0xff) as u8);
             cpu.push(cpu.get_status_register() | (1 << 4));
+            cpu.interrupt = true;
             cpu.program_counter = cpu.bus.read_rom_16(0xfffe);
             InstructionResult {
                 step: 0,
@@ -219,7 +164,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x04,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPage,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 3,
@@ -317,7 +262,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x0c,
         name: InstructionCode::TOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::Absolute,
         operation: |_cpu| InstructionResult {
             step: 3,
             remaining_cycles: 4,
@@ -423,7 +368,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x14,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPageX,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 4,
@@ -524,7 +469,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x1c,
         name: InstructionCode::TOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::AbsoluteX,
         operation: |cpu| {
             cpu.get_absolute_x_value(true); // Need extra cycle
             InstructionResult {
@@ -852,7 +797,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x34,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPageX,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 4,
@@ -955,7 +900,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x3c,
         name: InstructionCode::TOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::AbsoluteX,
         operation: |cpu| {
             cpu.get_absolute_x_value(true); // Need extra cycle
             InstructionResult {
@@ -1061,7 +1006,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x44,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPage,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 3,
@@ -1262,7 +1207,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x54,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPageX,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 4,
@@ -1347,7 +1292,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x5b,
         name: InstructionCode::SRE,
-        mode: InstructionMode::ZeroPage,
+        mode: InstructionMode::AbsoluteY,
         operation: |cpu| {
             let val = cpu.get_absolute_y_value(false);
             cpu.carry = (val & 1) != 0;
@@ -1363,7 +1308,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x5c,
         name: InstructionCode::TOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::AbsoluteX,
         operation: |cpu| {
             cpu.get_absolute_x_value(true); // Need extra cycle
             InstructionResult {
@@ -1467,7 +1412,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x64,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPage,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 3,
@@ -1684,7 +1629,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x74,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPageX,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 4,
@@ -1787,7 +1732,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x7c,
         name: InstructionCode::TOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::AbsoluteX,
         operation: |cpu| {
             cpu.get_absolute_x_value(true); // Need extra cycle
             InstructionResult {
@@ -1842,7 +1787,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x80,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::Immediate,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 2,
@@ -1864,7 +1809,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x82,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::Immediate,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 2,
@@ -1873,7 +1818,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x83,
         name: InstructionCode::SAX,
-        mode: InstructionMode::Absolute,
+        mode: InstructionMode::IndirectX,
         operation: |cpu| {
             let val = cpu.accumulator & cpu.x_register;
             cpu.set_indirect_x(val);
@@ -1951,7 +1896,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0x89,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::Immediate,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 2,
@@ -2636,7 +2581,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0xc2,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::Immediate,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 2,
@@ -2871,7 +2816,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0xd4,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPageX,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 4,
@@ -2972,7 +2917,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0xdc,
         name: InstructionCode::TOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::AbsoluteX,
         operation: |cpu| {
             cpu.get_absolute_x_value(true); // Need extra cycle
             InstructionResult {
@@ -3053,7 +2998,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0xe2,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::Immediate,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 2,
@@ -3229,7 +3174,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     },
     Instruction {
         opcode: 0xf0,
-        name: InstructionCode::BNE,
+        name: InstructionCode::BEQ,
         mode: InstructionMode::Relative,
         operation: |cpu| {
             let old_pc = cpu.program_counter + 2;
@@ -3285,7 +3230,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0xf4,
         name: InstructionCode::DOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::ZeroPageX,
         operation: |_cpu| InstructionResult {
             step: 2,
             remaining_cycles: 4,
@@ -3386,7 +3331,7 @@ pub const INSTRUCTION_TABLE: [Instruction; 0x100] = [
     Instruction {
         opcode: 0xfc,
         name: InstructionCode::TOP,
-        mode: InstructionMode::Implied,
+        mode: InstructionMode::AbsoluteX,
         operation: |cpu| {
             cpu.get_absolute_x_value(true); // Need extra cycle
             InstructionResult {
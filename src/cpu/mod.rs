@@ -119,9 +119,10 @@ impl Cpu<'_> {
         self.compteur += 1;
     }
 
-    /// Get interrup flag status. Required for emulator to raise IRQ
-    pub fn get_interrupt_flag(&self) -> bool {
-        self.interrupt
+    /// Suspend the CPU for some cycles, while DMA units use the bus
+    pub fn stall(&mut self, cycles: u32) {
+        self.remaining_cycles += cycles;
+        self.total_cycles += cycles;
     }
 
     /// Raises an NMI interruption
@@ -129,9 +130,13 @@ impl Cpu<'_> {
         self.general_interrupt(0xfffa);
     }
 
-    /// Raises an IRQ interruption
-    pub fn irq(&mut self) {
+    /// Raises an IRQ interruption, unless the interrupt disable flag is set. Returns true if the IRQ is taken
+    pub fn irq(&mut self) -> bool {
+        if self.interrupt {
+            return false;
+        }
         self.general_interrupt(0xfffe);
+        true
     }
 
     /// General interruption sequence used for NMI and IRQ
@@ -142,7 +147,7 @@ impl Cpu<'_> {
         self.push((self.program_counter & 255) as u8);
         self.push(self.get_status_register() & 0b11101111); // NMI and IRQ set break flag to 0
 
-        self.interrupt = false;
+        self.interrupt = true; // Interrupt disable flag, until RTI restores it
 
         self.program_counter = self.bus.read_rom_16(address);
         self.remaining_cycles = 7 - 1; // do not count current cycle twice
@@ -552,6 +557,16 @@ impl Cpu<'_> {
         }
     }
 
+    /// Set CPU registers from a Status. Total cycles are left unchanged. Usefull for debugging
+    pub fn set_status(&mut self, status: &Status) {
+        self.program_counter = status.program_counter;
+        self.stack_pointer = status.stack_pointer;
+        self.accumulator = status.accumulator;
+        self.x_register = status.x_register;
+        self.y_register = status.y_register;
+        self.set_status_register(status.status_register);
+    }
+
     pub fn get_remaining_cycles(&self) -> u32 {
         self.remaining_cycles
     }
@@ -560,3 +575,68 @@ impl Cpu<'_> {
         self.total_cycles
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Cpu;
+    use crate::cartridge::Cartridge;
+    use crate::test_utils::{lock_sdl, write_test_rom};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// CPU with a test NROM inserted, started in RAM at $0200
+    fn new_cpu() -> Cpu<'static> {
+        let sdl_context = Rc::new(RefCell::new(sdl2::init().unwrap()));
+        let cartridge = Rc::new(RefCell::new(Cartridge::new(write_test_rom("cpu", &[]))));
+        let mut cpu = Cpu::new(sdl_context, cartridge);
+        cpu.start(Some(0x0200));
+        cpu
+    }
+
+    /// Finish the current instruction or interrupt sequence, then execute the next instruction
+    fn step(cpu: &mut Cpu) {
+        while cpu.remaining_cycles > 0 {
+            cpu.next();
+        }
+        cpu.next();
+    }
+
+    #[test]
+    fn interrupt_disable_flag() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let irq_vector = cpu.bus.read_rom_16(0xfffe);
+        let nmi_vector = cpu.bus.read_rom_16(0xfffa);
+
+        // IRQ is ignored while I is set
+        cpu.interrupt = true;
+        assert!(!cpu.irq());
+        assert_eq!(cpu.program_counter, 0x0200);
+
+        // IRQ entry sets I, RTI restores it
+        cpu.interrupt = false;
+        assert!(cpu.irq());
+        assert!(cpu.interrupt);
+        assert_eq!(cpu.program_counter, irq_vector);
+        cpu.bus.write_rom(0x0300, 0x40); // RTI
+        cpu.program_counter = 0x0300;
+        step(&mut cpu);
+        assert!(!cpu.interrupt);
+        assert_eq!(cpu.program_counter, 0x0200);
+
+        // NMI entry sets I, even if it was clear
+        cpu.nmi();
+        assert!(cpu.interrupt);
+        assert_eq!(cpu.program_counter, nmi_vector);
+
+        // BRK sets I, with the break flag in the pushed status
+        cpu.interrupt = false;
+        cpu.program_counter = 0x0200;
+        cpu.bus.write_rom(0x0200, 0x00); // BRK
+        step(&mut cpu);
+        assert!(cpu.interrupt);
+        assert_eq!(cpu.program_counter, irq_vector);
+        let pushed_status = cpu.bus.read_rom(0x0100 | (cpu.stack_pointer as u16 + 1));
+        assert_eq!(pushed_status & 0b10100, 0b10000); // Break set, I clear as before BRK
+    }
+}
@@ -0,0 +1,396 @@
+//! 6502 disassembler
+//! Pure decoding of byte slices, based on the CPU instruction table. Does not need a running CPU
+
+use log::info;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+
+use crate::cartridge::Cartridge;
+use crate::cpu::instructions::{InstructionCode, InstructionMode, INSTRUCTION_TABLE};
+use crate::cpu::opcodes::OPCODES;
+
+/// PRG ROM bank size used for dumps
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// One decoded instruction
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub operand: String,
+    pub is_unofficial: bool,
+    /// Address referenced by the operand, if any. Used for labels
+    pub target: Option<u16>,
+}
+
+impl DisassembledInstruction {
+    /// Instruction as written in assembly, without address nor bytes
+    pub fn get_syntax(&self) -> String {
+        if self.operand.is_empty() {
+            self.mnemonic.clone()
+        } else {
+            format!("{} {}", self.mnemonic, self.operand)
+        }
+    }
+}
+
+/// Labels loaded from symbol files
+pub struct SymbolTable {
+    labels: HashMap<u16, String>,
+}
+
+impl SymbolTable {
+    /// Instantiate an empty symbol table
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            labels: HashMap::new(),
+        }
+    }
+
+    /// Load labels from a symbol file
+    ///
+    /// Supported line formats :
+    /// - FCEUX .nl files : `$C000#Reset#comment`
+    /// - ld65 label files : `al 00C000 .Reset`
+    /// - Assignments : `Reset = $C000`
+    pub fn load(&mut self, file_name: &str) {
+        info!("Loading symbols from {}", file_name);
+        let file = File::open(file_name).expect("Cannot open symbol file");
+        for line in BufReader::new(file).lines() {
+            let line = line.expect("Cannot read symbol file");
+            if let Some((address, label)) = SymbolTable::parse_line(line.trim()) {
+                self.add_label(address, label);
+            }
+        }
+    }
+
+    /// Parse a symbol file line into an address and a label
+    fn parse_line(line: &str) -> Option<(u16, String)> {
+        let parse_address = |value: &str| {
+            u16::from_str_radix(value.trim().trim_start_matches('$'), 16)
+                .ok()
+                .or_else(|| u32::from_str_radix(value.trim(), 16).ok().map(|v| v as u16))
+        };
+        if line.starts_with('$') && line.contains('#') {
+            let mut fields = line.split('#');
+            let address = parse_address(fields.next()?)?;
+            let label = fields.next()?.trim();
+            return (!label.is_empty()).then(|| (address, label.to_string()));
+        }
+        if let Some(rest) = line.strip_prefix("al ") {
+            let mut fields = rest.split_whitespace();
+            let address = parse_address(fields.next()?)?;
+            let label = fields.next()?.trim_start_matches('.');
+            return Some((address, label.to_string()));
+        }
+        if let Some((label, address)) = line.split_once('=') {
+            let label = label.trim();
+            let address = parse_address(address)?;
+            return (!label.is_empty()).then(|| (address, label.to_string()));
+        }
+        None
+    }
+
+    /// Add or replace a label
+    pub fn add_label(&mut self, address: u16, label: String) {
+        self.labels.insert(address, label);
+    }
+
+    /// Get label for address, if any
+    pub fn get_label(&self, address: u16) -> Option<&String> {
+        self.labels.get(&address)
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
+/// Number of bytes used by an instruction, as listed in the opcodes catalog
+pub fn get_instruction_length(opcode: u8) -> usize {
+    OPCODES
+        .get(&opcode)
+        .map_or(1, |catalog_entry| catalog_entry.len as usize)
+}
+
+/// Unofficial opcodes are flagged with a star by most tools
+pub fn is_unofficial(opcode: u8) -> bool {
+    match INSTRUCTION_TABLE[opcode as usize].name {
+        InstructionCode::NOP => opcode != 0xea,
+        InstructionCode::SBC => opcode == 0xeb,
+        InstructionCode::SLO
+        | InstructionCode::RLA
+        | InstructionCode::SRE
+        | InstructionCode::RRA
+        | InstructionCode::SAX
+        | InstructionCode::LAX
+        | InstructionCode::DCP
+        | InstructionCode::ISC
+        | InstructionCode::DOP
+        | InstructionCode::TOP
+        | InstructionCode::Unknown => true,
+        _ => false,
+    }
+}
+
+/// Mnemonic as printed by the reference emulators
+fn get_mnemonic(name: &InstructionCode) -> String {
+    match name {
+        InstructionCode::DOP | InstructionCode::TOP => String::from("NOP"),
+        InstructionCode::ISC => String::from("ISB"),
+        _ => name.to_string(),
+    }
+}
+
+/// Format an address, replaced by its label when there is one
+fn format_address(address: u16, is_zero_page: bool, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|symbols| symbols.get_label(address)) {
+        Some(label) => label.clone(),
+        None if is_zero_page => format!("${:02X}", address),
+        None => format!("${:04X}", address),
+    }
+}
+
+/// Decode the instruction at the start of bytes, located at address
+///
+/// If bytes is too short for the instruction, or the opcode is unknown, a data byte is returned.
+/// Returns None if bytes is empty
+pub fn decode(
+    bytes: &[u8],
+    address: u16,
+    symbols: Option<&SymbolTable>,
+) -> Option<DisassembledInstruction> {
+    let opcode = *bytes.first()?;
+    let instruction = &INSTRUCTION_TABLE[opcode as usize];
+    let length = get_instruction_length(opcode);
+    if instruction.name == InstructionCode::Unknown || bytes.len() < length {
+        return Some(DisassembledInstruction {
+            address,
+            bytes: vec![opcode],
+            mnemonic: String::from(".byte"),
+            operand: format!("${:02X}", opcode),
+            is_unofficial: false,
+            target: None,
+        });
+    }
+
+    let byte_1 = if length > 1 { bytes[1] } else { 0 };
+    let word = if length > 2 {
+        byte_1 as u16 | ((bytes[2] as u16) << 8)
+    } else {
+        byte_1 as u16
+    };
+
+    let (operand, target) = match instruction.mode {
+        InstructionMode::Implied | InstructionMode::Undefined => (String::new(), None),
+        InstructionMode::Accumulator => (String::from("A"), None),
+        InstructionMode::Immediate => (format!("#${:02X}", byte_1), None),
+        InstructionMode::ZeroPage => (format_address(word, true, symbols), Some(word)),
+        InstructionMode::ZeroPageX => (
+            format!("{},X", format_address(word, true, symbols)),
+            Some(word),
+        ),
+        InstructionMode::ZeroPageY => (
+            format!("{},Y", format_address(word, true, symbols)),
+            Some(word),
+        ),
+        InstructionMode::Absolute => (format_address(word, false, symbols), Some(word)),
+        InstructionMode::AbsoluteX => (
+            format!("{},X", format_address(word, false, symbols)),
+            Some(word),
+        ),
+        InstructionMode::AbsoluteY => (
+            format!("{},Y", format_address(word, false, symbols)),
+            Some(word),
+        ),
+        InstructionMode::Relative => {
+            let target = address.wrapping_add(2).wrapping_add(byte_1 as i8 as u16);
+            (format_address(target, false, symbols), Some(target))
+        }
+        InstructionMode::Indirect => (
+            format!("({})", format_address(word, false, symbols)),
+            Some(word),
+        ),
+        InstructionMode::IndirectX => (
+            format!("({},X)", format_address(word, true, symbols)),
+            Some(word),
+        ),
+        InstructionMode::IndirectY => (
+            format!("({}),Y", format_address(word, true, symbols)),
+            Some(word),
+        ),
+    };
+
+    Some(DisassembledInstruction {
+        address,
+        bytes: bytes[..length].to_vec(),
+        mnemonic: get_mnemonic(&instruction.name),
+        operand,
+        is_unofficial: is_unofficial(opcode),
+        target,
+    })
+}
+
+/// Decode every instruction of a byte slice, the first byte being located at origin
+pub fn disassemble(
+    bytes: &[u8],
+    origin: u16,
+    symbols: Option<&SymbolTable>,
+) -> Vec<DisassembledInstruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while let Some(instruction) = decode(
+        &bytes[offset..],
+        origin.wrapping_add(offset as u16),
+        symbols,
+    ) {
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// Render instructions as a listing, one instruction per line, with labels on their own line
+pub fn format_listing(
+    instructions: &[DisassembledInstruction],
+    symbols: Option<&SymbolTable>,
+) -> String {
+    let mut listing = String::new();
+    for instruction in instructions {
+        if let Some(label) = symbols.and_then(|symbols| symbols.get_label(instruction.address)) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        let bytes: Vec<String> = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        listing.push_str(&format!(
+            "  {:04X}  {:<8} {}{}\n",
+            instruction.address,
+            bytes.join(" "),
+            if instruction.is_unofficial { "*" } else { " " },
+            instruction.get_syntax(),
+        ));
+    }
+    listing
+}
+
+/// Disassemble every PRG ROM bank of a ROM file
+///
+/// Banks are 16kB. The last bank is mapped at $C000, the other ones at $8000, as most mappers do
+pub fn disassemble_rom(rom_file: String, symbols: Option<&SymbolTable>) -> String {
+    let cartridge = Cartridge::new(rom_file);
+    let prg_rom = cartridge.get_prg_rom();
+    let bank_count = prg_rom.len().div_ceil(PRG_BANK_SIZE);
+    let mut listing = String::new();
+    for (bank, bank_bytes) in prg_rom.chunks(PRG_BANK_SIZE).enumerate() {
+        let origin = if bank + 1 == bank_count {
+            0xc000
+        } else {
+            0x8000
+        };
+        listing.push_str(&format!("; PRG bank {} at ${:04X}\n", bank, origin));
+        listing.push_str(&format_listing(
+            &disassemble(bank_bytes, origin, symbols),
+            symbols,
+        ));
+        listing.push('\n');
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, disassemble, SymbolTable};
+
+    #[test]
+    fn decode_addressing_modes() {
+        assert_eq!(
+            decode(&[0x4c, 0xf5, 0xc5], 0xc000, None)
+                .unwrap()
+                .get_syntax(),
+            "JMP $C5F5"
+        );
+        assert_eq!(
+            decode(&[0xa2, 0x00], 0xc5f5, None).unwrap().get_syntax(),
+            "LDX #$00"
+        );
+        assert_eq!(decode(&[0x4a], 0xcefc, None).unwrap().get_syntax(), "LSR A");
+        assert_eq!(
+            decode(&[0xb0, 0x04], 0xc72f, None).unwrap().get_syntax(),
+            "BCS $C735"
+        );
+        assert_eq!(
+            decode(&[0xf0, 0x03], 0xc095, None).unwrap().get_syntax(),
+            "BEQ $C09A"
+        );
+        assert_eq!(
+            decode(&[0xd0, 0xfe], 0xc000, None).unwrap().get_syntax(),
+            "BNE $C000"
+        );
+        assert_eq!(
+            decode(&[0x6c, 0x00, 0x02], 0xdb7b, None)
+                .unwrap()
+                .get_syntax(),
+            "JMP ($0200)"
+        );
+        assert_eq!(
+            decode(&[0xa1, 0x80], 0xcfdb, None).unwrap().get_syntax(),
+            "LDA ($80,X)"
+        );
+        assert_eq!(
+            decode(&[0xb1, 0x89], 0xd922, None).unwrap().get_syntax(),
+            "LDA ($89),Y"
+        );
+        assert_eq!(
+            decode(&[0xb4, 0x33], 0xdbcd, None).unwrap().get_syntax(),
+            "LDY $33,X"
+        );
+        assert_eq!(
+            decode(&[0xb9, 0x00, 0x03], 0xdf60, None)
+                .unwrap()
+                .get_syntax(),
+            "LDA $0300,Y"
+        );
+        assert!(decode(&[0x04, 0xa9], 0xc6bd, None).unwrap().is_unofficial);
+        assert!(decode(&[], 0xc000, None).is_none());
+    }
+
+    #[test]
+    fn disassemble_with_labels_and_data() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_label(0xc000, String::from("Reset"));
+        let instructions = disassemble(
+            &[0x78, 0x4c, 0x00, 0xc0, 0x02, 0x20],
+            0xc000,
+            Some(&symbols),
+        );
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(instructions[1].get_syntax(), "JMP Reset");
+        assert_eq!(instructions[2].get_syntax(), ".byte $02");
+        assert_eq!(instructions[3].get_syntax(), ".byte $20"); // Truncated JSR
+        assert!(disassemble(&[], 0xc000, None).is_empty());
+    }
+
+    #[test]
+    fn parse_symbol_files() {
+        assert_eq!(
+            SymbolTable::parse_line("$C000#Reset#entry point"),
+            Some((0xc000, String::from("Reset")))
+        );
+        assert_eq!(
+            SymbolTable::parse_line("al 00C000 .Reset"),
+            Some((0xc000, String::from("Reset")))
+        );
+        assert_eq!(
+            SymbolTable::parse_line("Reset = $C000"),
+            Some((0xc000, String::from("Reset")))
+        );
+        assert_eq!(SymbolTable::parse_line("; comment"), None);
+    }
+}
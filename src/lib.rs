@@ -2,5 +2,9 @@ mod apu;
 mod bus;
 mod cartridge;
 mod cpu;
+pub mod disassembler;
 pub mod nes_emulator;
 mod ppu;
+mod region;
+#[cfg(test)]
+mod test_utils;
@@ -4,26 +4,358 @@ extern crate log;
 
 extern crate argparse;
 extern crate yaml_rust;
-use argparse::{ArgumentParser, Store, StoreTrue};
+use argparse::{ArgumentParser, Collect, Store, StoreOption, StoreTrue};
 use log::info;
-use nes_emu_rust::nes_emulator::NesEmulator;
+use nes_emu_rust::disassembler::{self, SymbolTable};
+use nes_emu_rust::nes_emulator::{
+    Channel, NesEmulator, NtscPreset, RecordMode, Region, SyncMode, TraceConfig, TraceFormat,
+};
+use std::io::Write;
+
+/// Parse an hexadecimal address given on the command line, with optional $ or 0x prefix
+fn parse_address(value: &str) -> u16 {
+    let value = value.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(value, 16).unwrap_or_else(|_| {
+        eprintln!("Invalid address : {}", value);
+        std::process::exit(2);
+    })
+}
+
+/// Parse a channel volume given on the command line as channel=volume
+fn parse_channel_volume(value: &str) -> (Channel, f32) {
+    let parsed = value.split_once('=').and_then(|(channel, volume)| {
+        Some((
+            channel.parse::<Channel>().ok()?,
+            volume.parse::<f32>().ok()?,
+        ))
+    });
+    parsed.unwrap_or_else(|| {
+        eprintln!("Invalid channel volume : {}", value);
+        std::process::exit(2);
+    })
+}
+
+/// `disasm` subcommand : dump the PRG ROM banks of a ROM file
+fn disasm(args: Vec<String>) {
+    let mut rom_file: String = String::new();
+    let mut symbol_files: Vec<String> = vec![];
+    let mut output_file: Option<String> = None;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Disassemble the PRG ROM banks of a ROM file");
+        ap.refer(&mut symbol_files).add_option(
+            &["-s", "--symbols"],
+            Collect,
+            "Symbol file to load labels from (.nl, ld65 labels or NAME = $ADDR), can be repeated",
+        );
+        ap.refer(&mut output_file).add_option(
+            &["-o", "--output"],
+            StoreOption,
+            "Write the listing to this file instead of standard output",
+        );
+        ap.refer(&mut rom_file)
+            .add_argument("rom_file", Store, "File path to ROM File")
+            .required();
+        if let Err(code) = ap.parse(args, &mut std::io::stdout(), &mut std::io::stderr()) {
+            std::process::exit(code);
+        }
+    }
+
+    let mut symbols = SymbolTable::new();
+    for symbol_file in symbol_files.iter() {
+        symbols.load(symbol_file);
+    }
+    let listing = disassembler::disassemble_rom(rom_file, Some(&symbols));
+    match output_file {
+        Some(output_file) => std::fs::write(output_file, listing).expect("Cannot write listing"),
+        None => std::io::stdout()
+            .write_all(listing.as_bytes())
+            .expect("Cannot write listing"),
+    }
+}
 
 fn main() {
     simple_logger::init().unwrap();
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        let mut subcommand_args = vec![format!("{} disasm", args[0])];
+        subcommand_args.extend_from_slice(&args[2..]);
+        disasm(subcommand_args);
+        return;
+    }
+
     let mut verbose = false;
     let mut rom_file: String = String::new();
+    let mut trace_file: Option<String> = None;
+    let mut trace_format = TraceFormat::Nintendulator;
+    let mut trace_start_frame: Option<u128> = None;
+    let mut trace_stop_frame: Option<u128> = None;
+    let mut trace_start_pc: Option<String> = None;
+    let mut trace_stop_pc: Option<String> = None;
+    let mut trace_start_cycle: Option<u32> = None;
+    let mut trace_stop_cycle: Option<u32> = None;
+    let mut trace_ppu = false;
+    let mut trace_apu = false;
+    let mut debug = false;
+    let mut breakpoints: Vec<String> = vec![];
+    let mut gdb_port: Option<u16> = None;
+    let mut no_sprite_limit = false;
+    let mut region: Option<Region> = None;
+    let mut rom_database: Option<String> = None;
+    let mut palette: Option<String> = None;
+    let mut ntsc_filter: Option<NtscPreset> = None;
+    let mut crop_overscan = false;
+    let mut ppu_viewer = false;
+    let mut event_overlay = false;
+    let mut volume: Option<f32> = None;
+    let mut sample_rate: Option<u32> = None;
+    let mut sync_mode: Option<SyncMode> = None;
+    let mut audio_driver: Option<String> = None;
+    let mut record_audio: Option<String> = None;
+    let mut record_mode = RecordMode::Mix;
+    let mut muted_channels: Vec<Channel> = vec![];
+    let mut soloed_channels: Vec<Channel> = vec![];
+    let mut channel_volumes: Vec<String> = vec![];
     {
         // this block limits scope of borrows by ap.refer() method
         let mut ap = ArgumentParser::new();
-        ap.set_description("Yet another NES Emulator in Rust");
+        ap.set_description(
+            "Yet another NES Emulator in Rust. Use `disasm <rom_file>` to disassemble a ROM instead",
+        );
         ap.refer(&mut verbose)
             .add_option(&["-v", "--verbose"], StoreTrue, "Be verbose");
+        ap.refer(&mut trace_file).add_option(
+            &["--trace"],
+            StoreOption,
+            "Write an execution trace to this file",
+        );
+        ap.refer(&mut trace_format).add_option(
+            &["--trace-format"],
+            Store,
+            "Trace format : nintendulator (default), fceux or mesen",
+        );
+        ap.refer(&mut trace_start_frame).add_option(
+            &["--trace-start-frame"],
+            StoreOption,
+            "Start tracing at this frame",
+        );
+        ap.refer(&mut trace_stop_frame).add_option(
+            &["--trace-stop-frame"],
+            StoreOption,
+            "Stop tracing at this frame",
+        );
+        ap.refer(&mut trace_start_pc).add_option(
+            &["--trace-start-pc"],
+            StoreOption,
+            "Start tracing when PC reaches this hexadecimal address",
+        );
+        ap.refer(&mut trace_stop_pc).add_option(
+            &["--trace-stop-pc"],
+            StoreOption,
+            "Stop tracing when PC reaches this hexadecimal address",
+        );
+        ap.refer(&mut trace_start_cycle).add_option(
+            &["--trace-start-cycle"],
+            StoreOption,
+            "Start tracing at this CPU cycle",
+        );
+        ap.refer(&mut trace_stop_cycle).add_option(
+            &["--trace-stop-cycle"],
+            StoreOption,
+            "Stop tracing at this CPU cycle",
+        );
+        ap.refer(&mut trace_ppu).add_option(
+            &["--trace-ppu"],
+            StoreTrue,
+            "Add PPU registers to the trace",
+        );
+        ap.refer(&mut trace_apu).add_option(
+            &["--trace-apu"],
+            StoreTrue,
+            "Add APU registers to the trace",
+        );
+        ap.refer(&mut debug).add_option(
+            &["-d", "--debug"],
+            StoreTrue,
+            "Start paused with the interactive debugger on standard input",
+        );
+        ap.refer(&mut breakpoints).add_option(
+            &["-b", "--break"],
+            Collect,
+            "Add a debugger breakpoint at this hexadecimal address, can be repeated",
+        );
+        ap.refer(&mut gdb_port).add_option(
+            &["--gdb"],
+            StoreOption,
+            "Start a GDB remote protocol server on this local TCP port",
+        );
+        ap.refer(&mut no_sprite_limit).add_option(
+            &["--no-sprite-limit"],
+            StoreTrue,
+            "Lift the 8 sprites per line limit to reduce flickering",
+        );
+        ap.refer(&mut region).add_option(
+            &["--region"],
+            StoreOption,
+            "Console region : ntsc, pal or dendy. Defaults to the ROM header or database",
+        );
+        ap.refer(&mut rom_database).add_option(
+            &["--rom-db"],
+            StoreOption,
+            "YAML ROM database file giving per game settings, looked up by ROM CRC32",
+        );
+        ap.refer(&mut palette).add_option(
+            &["--palette"],
+            StoreOption,
+            "Palette : 2c02 (default), 2c02-measured, composite, smooth, fbx, 2c03, 2c04-0001, 2c04-0003, 2c05 or a .pal file. Overrides the ROM database",
+        );
+        ap.refer(&mut ntsc_filter).add_option(
+            &["--ntsc-filter"],
+            StoreOption,
+            "NTSC video filter preset : composite, svideo or rgb",
+        );
+        ap.refer(&mut crop_overscan).add_option(
+            &["--crop-overscan"],
+            StoreTrue,
+            "Crop the 8 top and bottom overscan lines from F12 screenshots",
+        );
+        ap.refer(&mut ppu_viewer).add_option(
+            &["--ppu-viewer"],
+            StoreTrue,
+            "Open the nametables, pattern tables, OAM and palette viewer windows",
+        );
+        ap.refer(&mut event_overlay).add_option(
+            &["--event-overlay"],
+            StoreTrue,
+            "Draw PPU register writes, mapper writes and interrupts over the frame",
+        );
+        ap.refer(&mut volume).add_option(
+            &["--volume"],
+            StoreOption,
+            "Audio volume, from 0.0 to 1.0",
+        );
+        ap.refer(&mut sample_rate).add_option(
+            &["--sample-rate"],
+            StoreOption,
+            "Audio sample rate in Hz, from 8000 to 192000, 44100 (default) or 48000 usually",
+        );
+        ap.refer(&mut audio_driver).add_option(
+            &["--audio-driver"],
+            StoreOption,
+            "SDL audio driver, such as pulseaudio, alsa or dummy",
+        );
+        ap.refer(&mut sync_mode).add_option(
+            &["--sync"],
+            StoreOption,
+            "Frame pacing : audio (default) follows the sound device, video sleeps to the frame rate",
+        );
+        ap.refer(&mut record_audio).add_option(
+            &["--record-audio"],
+            StoreOption,
+            "Record the audio output to this WAV file, F8 toggles recording",
+        );
+        ap.refer(&mut record_mode).add_option(
+            &["--record-mode"],
+            Store,
+            "Audio recording : mix (default) or channels, one <file>_<channel>.wav per channel",
+        );
+        ap.refer(&mut muted_channels).add_option(
+            &["--mute"],
+            Collect,
+            "Mute a channel : pulse1, pulse2, triangle, noise or dmc, can be repeated",
+        );
+        ap.refer(&mut soloed_channels).add_option(
+            &["--solo"],
+            Collect,
+            "Solo a channel, silencing the other ones, can be repeated",
+        );
+        ap.refer(&mut channel_volumes).add_option(
+            &["--channel-volume"],
+            Collect,
+            "Set a channel volume from 0.0 to 1.0, as channel=volume, can be repeated",
+        );
         ap.refer(&mut rom_file)
             .add_argument("rom_file", Store, "File path to ROM File");
         ap.parse_args_or_exit();
     }
 
     let mut emulator = NesEmulator::new(rom_file);
+    if let Some(trace_file) = trace_file {
+        info!("Tracing execution into {}", trace_file);
+        let mut config = TraceConfig::new(trace_file, trace_format);
+        config.start_frame = trace_start_frame;
+        config.stop_frame = trace_stop_frame;
+        config.start_pc = trace_start_pc.as_deref().map(parse_address);
+        config.stop_pc = trace_stop_pc.as_deref().map(parse_address);
+        config.start_cycle = trace_start_cycle;
+        config.stop_cycle = trace_stop_cycle;
+        config.with_ppu_registers = trace_ppu;
+        config.with_apu_registers = trace_apu;
+        emulator.set_tracer(config);
+    }
+    if debug || !breakpoints.is_empty() {
+        emulator.set_debug_mode();
+        for breakpoint in breakpoints.iter() {
+            emulator.add_breakpoint(parse_address(breakpoint), None);
+        }
+    }
+    if let Some(rom_database) = rom_database {
+        if let Err(error) = emulator.load_rom_database(&rom_database) {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    }
+    if let Some(region) = region {
+        emulator.set_region(region);
+    }
+    emulator.set_sprite_limit(!no_sprite_limit);
+    if let Some(palette) = palette {
+        if let Err(error) = emulator.set_palette(&palette) {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    }
+    emulator.set_ntsc_filter(ntsc_filter);
+    emulator.set_overscan_crop(crop_overscan);
+    if ppu_viewer {
+        emulator.open_ppu_viewer();
+    }
+    emulator.set_event_overlay(event_overlay);
+    if let Some(volume) = volume {
+        emulator.set_volume(volume);
+    }
+    if let Some(sample_rate) = sample_rate {
+        if let Err(error) = emulator.set_sample_rate(sample_rate) {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    }
+    if let Some(audio_driver) = audio_driver {
+        emulator.set_audio_driver(&audio_driver);
+    }
+    if let Some(sync_mode) = sync_mode {
+        emulator.set_sync_mode(sync_mode);
+    }
+    for channel in muted_channels.iter() {
+        emulator.set_channel_muted(*channel, true);
+    }
+    for channel in soloed_channels.iter() {
+        emulator.set_channel_soloed(*channel, true);
+    }
+    for channel_volume in channel_volumes.iter() {
+        let (channel, volume) = parse_channel_volume(channel_volume);
+        emulator.set_channel_volume(channel, volume);
+    }
+    emulator.set_audio_record_mode(record_mode);
+    if let Some(record_audio) = record_audio {
+        if let Err(error) = emulator.start_audio_recording(&record_audio, record_mode) {
+            eprintln!("Cannot record audio to {} : {}", record_audio, error);
+            std::process::exit(2);
+        }
+    }
+    if let Some(gdb_port) = gdb_port {
+        emulator.set_gdb_stub(gdb_port);
+    }
     emulator.start(None);
 }
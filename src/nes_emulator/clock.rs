@@ -2,17 +2,41 @@
 //! Allows to managed the framerate
 
 use std::collections::VecDeque;
+use std::str::FromStr;
 use std::thread::sleep;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
 /// FPS based on last 10 frame intervals average
 const CLOCK_HISTORY_SIZE: usize = 11;
 
+/// Frame pacing source
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SyncMode {
+    /// Emulation speed follows the audio device consumption
+    Audio,
+    /// Emulation sleeps to reach the region frame rate
+    Video,
+}
+
+impl FromStr for SyncMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<SyncMode, String> {
+        match value.to_lowercase().as_str() {
+            "audio" => Ok(SyncMode::Audio),
+            "video" => Ok(SyncMode::Video),
+            _ => Err(format!("Unknown sync mode : {}", value)),
+        }
+    }
+}
+
 /// Internal clock component, used to cadence the whole execution
 pub struct Clock {
     target_frame_duration: Duration,
-    frame_history: VecDeque<SystemTime>,
+    frame_history: VecDeque<Instant>,
     clock_count: u128,
+    is_throttled: bool,
+    sync_mode: SyncMode,
 }
 
 impl Clock {
@@ -22,21 +46,53 @@ impl Clock {
             Duration::from_nanos((1_000_000_000f64 / _target_framerate as f64) as u64);
         Clock {
             target_frame_duration: _target_frame_duration,
-            frame_history: VecDeque::from([SystemTime::now()]),
+            frame_history: VecDeque::from([Instant::now()]),
             clock_count: 0,
+            is_throttled: true,
+            sync_mode: SyncMode::Video,
         }
     }
 
-    /// Tick at each frame and wait to reach the target frame rate
+    /// Change the target frame rate
+    pub fn set_target_framerate(&mut self, target_framerate: f64) {
+        self.target_frame_duration =
+            Duration::from_nanos((1_000_000_000f64 / target_framerate) as u64);
+    }
+
+    /// Run as fast as possible when throttle is disabled
+    pub fn set_throttle(&mut self, is_throttled: bool) {
+        self.is_throttled = is_throttled;
+    }
+
+    /// Return true if the execution is limited to real time
+    pub fn is_throttled(&self) -> bool {
+        self.is_throttled
+    }
+
+    /// Select the frame pacing source
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
+    }
+
+    /// Get the frame pacing source
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    /// Tick at each frame and wait to reach the target frame rate. In audio sync mode the caller waits for the
+    /// audio device instead
     pub fn tick(&mut self) {
         self.clock_count += 1;
-        let now = SystemTime::now();
-        sleep(
-            self.target_frame_duration.saturating_sub(
-                now.duration_since(*self.frame_history.back().unwrap())
-                    .unwrap(),
-            ),
-        );
+        let now = Instant::now();
+        if !self.is_throttled {
+            return;
+        }
+        if self.sync_mode == SyncMode::Video {
+            sleep(
+                self.target_frame_duration
+                    .saturating_sub(now.duration_since(*self.frame_history.back().unwrap())),
+            );
+        }
         self.frame_history.push_back(now);
         if self.frame_history.len() > CLOCK_HISTORY_SIZE {
             self.frame_history.pop_front();
@@ -55,7 +111,7 @@ impl Clock {
         }
         let front = *self.frame_history.front().unwrap();
         let back = *self.frame_history.back().unwrap();
-        let duration_10_frames = back.duration_since(front).unwrap();
+        let duration_10_frames = back.duration_since(front);
         let fps: f64 = 10_000_000.0 / (duration_10_frames.as_micros()) as f64;
         fps
     }
@@ -77,12 +133,23 @@ mod tests {
         let expected_duration = std::time::Duration::from_nanos(FRAME_DURATION_NANOS as u64);
         let upper = expected_duration.checked_add(tolerance).unwrap();
         let lower = expected_duration.checked_sub(tolerance).unwrap();
-        let now = std::time::SystemTime::now();
+        let now = std::time::Instant::now();
         clock.tick();
-        let elapsed = now.elapsed().unwrap();
+        let elapsed = now.elapsed();
         assert!(elapsed <= upper);
         assert!(elapsed >= lower);
     }
+
+    #[test]
+    fn audio_sync_tick() {
+        // In audio sync mode, the clock leaves the waiting to the audio device
+        let mut clock = Clock::new(6);
+        clock.set_sync_mode(super::SyncMode::Audio);
+        let now = std::time::Instant::now();
+        clock.tick();
+        assert!(now.elapsed() < std::time::Duration::from_millis(50));
+        assert_eq!(clock.get_clock_count(), 1);
+    }
     /*
     #[test]
     fn clock_fps() {
@@ -0,0 +1,927 @@
+//! Interactive debugger
+//! Terminal REPL running alongside the SDL window, with breakpoints, watchpoints and stepping
+
+use std::fmt;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use crate::cpu::Cpu;
+
+/// RTS opcode, used to step out of a subroutine
+const RTS_OPCODE: u8 = 0x60;
+/// RTI opcode, used to step out of an interrupt handler
+const RTI_OPCODE: u8 = 0x40;
+/// JSR opcode, stepped over by the next command
+const JSR_OPCODE: u8 = 0x20;
+
+const HELP: &str = "Commands :
+  c | continue                      Resume execution
+  s | step [count]                  Execute count instructions (default 1), stepping into subroutines
+  n | next                          Execute next instruction, stepping over subroutines
+  f | finish                        Run until the current subroutine returns
+  b | break <addr>[:bank]           Add an execution breakpoint, optionally for a PRG bank only
+  break nmi|irq|frame               Break on NMI, IRQ or on each new frame
+  break scanline <line>             Break when the PPU reaches a scanline
+  w | watch <start>[-<end>] [r|w|rw] [cpu|ppu]
+                                    Add a watchpoint on a CPU or PPU address range
+  l | list                          List breakpoints and watchpoints
+  d | delete <id>|all               Delete a breakpoint or watchpoint by id, or every one of them
+  r | regs                          Show registers
+  set a|x|y|p|sp|pc <value>         Edit a register
+  m | mem [ppu] <addr> [length]     Dump memory
+  e | edit [ppu] <addr> <values...> Edit memory
+  h | help                          Show this help
+  q | quit                          Quit the emulator
+Addresses and values are hexadecimal, with optional $ or 0x prefix.";
+
+/// Memory access type watched by a watchpoint
+#[derive(PartialEq, Clone, Copy)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Address space watched by a watchpoint
+#[derive(PartialEq, Clone, Copy)]
+pub enum AddressSpace {
+    Cpu,
+    Ppu,
+}
+
+/// Execution breakpoint on program counter
+pub struct Breakpoint {
+    pub address: u16,
+    pub bank: Option<usize>,
+}
+
+/// Memory watchpoint on an address range
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+    pub space: AddressSpace,
+}
+
+impl Watchpoint {
+    /// Check if an access matches the watchpoint
+    pub fn matches(&self, space: AddressSpace, address: u16, is_write: bool) -> bool {
+        self.space == space
+            && address >= self.start
+            && address <= self.end
+            && match self.kind {
+                WatchKind::Read => !is_write,
+                WatchKind::Write => is_write,
+                WatchKind::ReadWrite => true,
+            }
+    }
+}
+
+/// Breakpoint kinds other than program counter and memory accesses
+#[derive(PartialEq)]
+pub enum EventBreakpoint {
+    Nmi,
+    Irq,
+    Frame,
+    Scanline(u16),
+}
+
+/// Why the execution has been stopped
+pub enum BreakReason {
+    Step,
+    Breakpoint(u16),
+    Watchpoint(AddressSpace, u16, u8, bool),
+    Nmi,
+    Irq,
+    Frame,
+    Scanline(u16),
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Step => write!(f, "Step"),
+            BreakReason::Breakpoint(address) => write!(f, "Breakpoint at ${:04X}", address),
+            BreakReason::Watchpoint(space, address, value, is_write) => write!(
+                f,
+                "Watchpoint : {} {} ${:04X} = ${:02X}",
+                if *space == AddressSpace::Cpu {
+                    "CPU"
+                } else {
+                    "PPU"
+                },
+                if *is_write { "write" } else { "read" },
+                address,
+                value
+            ),
+            BreakReason::Nmi => write!(f, "NMI"),
+            BreakReason::Irq => write!(f, "IRQ"),
+            BreakReason::Frame => write!(f, "New frame"),
+            BreakReason::Scanline(line) => write!(f, "Scanline {}", line),
+        }
+    }
+}
+
+/// What the emulator must do after a debugger command
+#[derive(PartialEq)]
+pub enum DebuggerAction {
+    None,
+    Resume,
+    Quit,
+}
+
+/// Start reading debugger commands from standard input, one per line
+pub fn read_stdin_commands() -> Receiver<String> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+/// Print the command prompt
+pub fn print_prompt() {
+    print!("(nes) ");
+    let _ = std::io::stdout().flush();
+}
+
+/// Debugger component
+pub struct Debugger {
+    commands: Receiver<String>,
+    breakpoints: Vec<(usize, Breakpoint)>,
+    watchpoints: Vec<(usize, Watchpoint)>,
+    event_breakpoints: Vec<(usize, EventBreakpoint)>,
+    next_id: usize,
+
+    // Stepping
+    step_count: Option<u32>,
+    step_over_address: Option<u16>,
+    step_out_stack_pointer: Option<u8>,
+    last_opcode: u8,
+
+    // Events since last instruction boundary
+    is_nmi: bool,
+    is_irq: bool,
+    is_frame: bool,
+    last_line: u16,
+}
+
+impl Debugger {
+    /// Instantiate the debugger, reading commands from a channel such as read_stdin_commands
+    pub fn new(commands: Receiver<String>) -> Debugger {
+        Debugger {
+            commands,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            event_breakpoints: vec![],
+            next_id: 1,
+            step_count: None,
+            step_over_address: None,
+            step_out_stack_pointer: None,
+            last_opcode: 0,
+            is_nmi: false,
+            is_irq: false,
+            is_frame: false,
+            last_line: 0,
+        }
+    }
+
+    /// Get next command typed by the user, if any
+    pub fn poll_command(&self) -> Option<String> {
+        self.commands.try_recv().ok()
+    }
+
+    /// Add an execution breakpoint and return its id
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.get_next_id();
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    /// Add a watchpoint and return its id
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.get_next_id();
+        self.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    /// Add an event breakpoint and return its id
+    pub fn add_event_breakpoint(&mut self, event: EventBreakpoint) -> usize {
+        let id = self.get_next_id();
+        self.event_breakpoints.push((id, event));
+        id
+    }
+
+    fn get_next_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Must be called when the CPU is about to fetch a new instruction
+    pub fn before_instruction(&mut self, cpu: &Cpu) {
+        let program_counter = cpu.get_status().program_counter;
+        self.last_opcode = cpu.bus.peek(program_counter);
+    }
+
+    /// Must be called when an NMI is serviced
+    pub fn on_nmi(&mut self) {
+        self.is_nmi = true;
+    }
+
+    /// Must be called when an IRQ is serviced
+    pub fn on_irq(&mut self) {
+        self.is_irq = true;
+    }
+
+    /// Must be called when a new frame starts
+    pub fn on_frame(&mut self) {
+        self.is_frame = true;
+    }
+
+    /// Check break conditions at an instruction boundary
+    pub fn check(&mut self, cpu: &Cpu) -> Option<BreakReason> {
+        let cpu_status = cpu.get_status();
+        let line = cpu.bus.ppu.get_status().line;
+        let reason = self.find_break_reason(cpu, cpu_status.program_counter, line);
+        self.is_nmi = false;
+        self.is_irq = false;
+        self.is_frame = false;
+        self.last_line = line;
+        if reason.is_some() {
+            self.step_count = None;
+            self.step_over_address = None;
+            self.step_out_stack_pointer = None;
+        }
+        reason
+    }
+
+    fn find_break_reason(
+        &mut self,
+        cpu: &Cpu,
+        program_counter: u16,
+        line: u16,
+    ) -> Option<BreakReason> {
+        if let Some(count) = self.step_count {
+            if count <= 1 {
+                return Some(BreakReason::Step);
+            }
+            self.step_count = Some(count - 1);
+        }
+        if self.step_over_address == Some(program_counter) {
+            return Some(BreakReason::Step);
+        }
+        if let Some(stack_pointer) = self.step_out_stack_pointer {
+            if (self.last_opcode == RTS_OPCODE || self.last_opcode == RTI_OPCODE)
+                && cpu.get_status().stack_pointer > stack_pointer
+            {
+                return Some(BreakReason::Step);
+            }
+        }
+
+        for (_, breakpoint) in self.breakpoints.iter() {
+            if breakpoint.address == program_counter
+                && (breakpoint.bank.is_none()
+                    || breakpoint.bank == cpu.bus.get_prg_bank(program_counter))
+            {
+                return Some(BreakReason::Breakpoint(program_counter));
+            }
+        }
+
+        for (_, watchpoint) in self.watchpoints.iter() {
+            let cpu_accesses = cpu
+                .bus
+                .get_access_log()
+                .iter()
+                .map(|access| (AddressSpace::Cpu, access));
+            let ppu_accesses = cpu
+                .bus
+                .ppu
+                .get_access_log()
+                .iter()
+                .map(|access| (AddressSpace::Ppu, access));
+            for (space, access) in cpu_accesses.chain(ppu_accesses) {
+                if watchpoint.matches(space, access.address, access.is_write) {
+                    return Some(BreakReason::Watchpoint(
+                        space,
+                        access.address,
+                        access.value,
+                        access.is_write,
+                    ));
+                }
+            }
+        }
+
+        for (_, event) in self.event_breakpoints.iter() {
+            match event {
+                EventBreakpoint::Nmi if self.is_nmi => return Some(BreakReason::Nmi),
+                EventBreakpoint::Irq if self.is_irq => return Some(BreakReason::Irq),
+                EventBreakpoint::Frame if self.is_frame => return Some(BreakReason::Frame),
+                EventBreakpoint::Scanline(target)
+                    if line == *target && self.last_line != *target =>
+                {
+                    return Some(BreakReason::Scanline(line))
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Execute a command typed by the user. Returns the action and the text to show, empty if none
+    pub fn execute(&mut self, command: &str, cpu: &mut Cpu) -> (DebuggerAction, String) {
+        let arguments: Vec<&str> = command.split_whitespace().collect();
+        if arguments.is_empty() {
+            return (DebuggerAction::None, String::new());
+        }
+        let result = match arguments[0] {
+            "c" | "continue" => Ok(DebuggerAction::Resume),
+            "s" | "step" => self.step(&arguments[1..]),
+            "n" | "next" => Ok(self.step_over(cpu)),
+            "f" | "finish" => {
+                self.step_out_stack_pointer = Some(cpu.get_status().stack_pointer);
+                Ok(DebuggerAction::Resume)
+            }
+            "q" | "quit" => Ok(DebuggerAction::Quit),
+            _ => {
+                let output = match arguments[0] {
+                    "b" | "break" => self.add_break_command(&arguments[1..]),
+                    "w" | "watch" => self.add_watch_command(&arguments[1..]),
+                    "l" | "list" => Ok(self.list()),
+                    "d" | "delete" => self.delete(&arguments[1..]),
+                    "r" | "regs" => Ok(get_registers_as_string(cpu)),
+                    "set" => set_register(&arguments[1..], cpu),
+                    "m" | "mem" => dump_memory(&arguments[1..], cpu),
+                    "e" | "edit" => edit_memory(&arguments[1..], cpu),
+                    "h" | "help" => Ok(String::from(HELP)),
+                    _ => Err(format!("Unknown command : {}", arguments[0])),
+                };
+                return (
+                    DebuggerAction::None,
+                    output.unwrap_or_else(|message| message),
+                );
+            }
+        };
+        match result {
+            Ok(action) => (action, String::new()),
+            Err(message) => (DebuggerAction::None, message),
+        }
+    }
+
+    fn step(&mut self, arguments: &[&str]) -> Result<DebuggerAction, String> {
+        let count = match arguments.first() {
+            Some(count) => count
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid count : {}", count))?,
+            None => 1,
+        };
+        self.step_count = Some(count.max(1));
+        Ok(DebuggerAction::Resume)
+    }
+
+    fn step_over(&mut self, cpu: &Cpu) -> DebuggerAction {
+        let program_counter = cpu.get_status().program_counter;
+        if cpu.bus.peek(program_counter) == JSR_OPCODE {
+            self.step_over_address = Some(program_counter.wrapping_add(3));
+        } else {
+            self.step_count = Some(1);
+        }
+        DebuggerAction::Resume
+    }
+
+    fn add_break_command(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let target = arguments.first().ok_or("Missing breakpoint address")?;
+        let id = match *target {
+            "nmi" => self.add_event_breakpoint(EventBreakpoint::Nmi),
+            "irq" => self.add_event_breakpoint(EventBreakpoint::Irq),
+            "frame" => self.add_event_breakpoint(EventBreakpoint::Frame),
+            "scanline" => {
+                let line = arguments.get(1).ok_or("Missing scanline")?;
+                let line = line
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid scanline : {}", line))?;
+                self.add_event_breakpoint(EventBreakpoint::Scanline(line))
+            }
+            _ => {
+                let (address, bank) = match target.split_once(':') {
+                    Some((address, bank)) => (
+                        address,
+                        Some(
+                            bank.parse::<usize>()
+                                .map_err(|_| format!("Invalid bank : {}", bank))?,
+                        ),
+                    ),
+                    None => (*target, None),
+                };
+                let address = parse_hex(address)?;
+                self.add_breakpoint(Breakpoint { address, bank })
+            }
+        };
+        Ok(format!("Breakpoint {} added", id))
+    }
+
+    fn add_watch_command(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let range = arguments.first().ok_or("Missing watchpoint address")?;
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+            None => (parse_hex(range)?, parse_hex(range)?),
+        };
+        let mut kind = WatchKind::ReadWrite;
+        let mut space = AddressSpace::Cpu;
+        for argument in arguments[1..].iter() {
+            match *argument {
+                "r" => kind = WatchKind::Read,
+                "w" => kind = WatchKind::Write,
+                "rw" => kind = WatchKind::ReadWrite,
+                "cpu" => space = AddressSpace::Cpu,
+                "ppu" => space = AddressSpace::Ppu,
+                _ => return Err(format!("Invalid watchpoint option : {}", argument)),
+            }
+        }
+        let id = self.add_watchpoint(Watchpoint {
+            start: start.min(end),
+            end: start.max(end),
+            kind,
+            space,
+        });
+        Ok(format!("Watchpoint {} added", id))
+    }
+
+    /// List breakpoints and watchpoints, one per line
+    fn list(&self) -> String {
+        let mut lines = vec![];
+        for (id, breakpoint) in self.breakpoints.iter() {
+            lines.push(match breakpoint.bank {
+                Some(bank) => format!(
+                    "{:>3} : break ${:04X} bank {}",
+                    id, breakpoint.address, bank
+                ),
+                None => format!("{:>3} : break ${:04X}", id, breakpoint.address),
+            });
+        }
+        for (id, watchpoint) in self.watchpoints.iter() {
+            lines.push(format!(
+                "{:>3} : watch {} ${:04X}-${:04X} {}",
+                id,
+                if watchpoint.space == AddressSpace::Cpu {
+                    "cpu"
+                } else {
+                    "ppu"
+                },
+                watchpoint.start,
+                watchpoint.end,
+                match watchpoint.kind {
+                    WatchKind::Read => "r",
+                    WatchKind::Write => "w",
+                    WatchKind::ReadWrite => "rw",
+                }
+            ));
+        }
+        for (id, event) in self.event_breakpoints.iter() {
+            lines.push(match event {
+                EventBreakpoint::Nmi => format!("{:>3} : break nmi", id),
+                EventBreakpoint::Irq => format!("{:>3} : break irq", id),
+                EventBreakpoint::Frame => format!("{:>3} : break frame", id),
+                EventBreakpoint::Scanline(line) => format!("{:>3} : break scanline {}", id, line),
+            });
+        }
+        lines.join("\n")
+    }
+
+    fn delete(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let target = arguments.first().ok_or("Missing id")?;
+        if *target == "all" {
+            self.breakpoints.clear();
+            self.watchpoints.clear();
+            self.event_breakpoints.clear();
+            return Ok(String::new());
+        }
+        let id = target
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid id : {}", target))?;
+        self.breakpoints.retain(|(other, _)| *other != id);
+        self.watchpoints.retain(|(other, _)| *other != id);
+        self.event_breakpoints.retain(|(other, _)| *other != id);
+        Ok(String::new())
+    }
+}
+
+/// Parse an hexadecimal number, with optional $ or 0x prefix
+pub fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hexadecimal value : {}", value))
+}
+
+/// Parse an hexadecimal byte, with optional $ or 0x prefix
+fn parse_hex_byte(value: &str) -> Result<u8, String> {
+    u8::try_from(parse_hex(value)?).map_err(|_| format!("Value out of range : {}", value))
+}
+
+fn get_registers_as_string(cpu: &Cpu) -> String {
+    let status = cpu.get_status();
+    let ppu_status = cpu.bus.ppu.get_status();
+    format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{} PPU:{},{}",
+        status.program_counter,
+        status.accumulator,
+        status.x_register,
+        status.y_register,
+        status.status_register,
+        status.stack_pointer,
+        status.total_cycles,
+        ppu_status.line,
+        ppu_status.col,
+    )
+}
+
+fn set_register(arguments: &[&str], cpu: &mut Cpu) -> Result<String, String> {
+    if arguments.len() != 2 {
+        return Err(String::from("Usage : set a|x|y|p|sp|pc <value>"));
+    }
+    let value = arguments[1];
+    let mut status = cpu.get_status();
+    match arguments[0].to_lowercase().as_str() {
+        "a" => status.accumulator = parse_hex_byte(value)?,
+        "x" => status.x_register = parse_hex_byte(value)?,
+        "y" => status.y_register = parse_hex_byte(value)?,
+        "p" => status.status_register = parse_hex_byte(value)?,
+        "sp" => status.stack_pointer = parse_hex_byte(value)?,
+        "pc" => status.program_counter = parse_hex(value)?,
+        register => return Err(format!("Unknown register : {}", register)),
+    }
+    cpu.set_status(&status);
+    Ok(get_registers_as_string(cpu))
+}
+
+fn dump_memory(arguments: &[&str], cpu: &mut Cpu) -> Result<String, String> {
+    let is_ppu = arguments.first() == Some(&"ppu");
+    let arguments = if is_ppu { &arguments[1..] } else { arguments };
+    let address = parse_hex(arguments.first().ok_or("Missing address")?)?;
+    let length = match arguments.get(1) {
+        Some(length) => parse_hex(length)?,
+        None => 0x20,
+    };
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < length {
+        let line_address = address.wrapping_add(offset);
+        if is_ppu {
+            let values: Vec<String> = (0..0x20u16)
+                .map(|i| {
+                    format!(
+                        "{:02x}",
+                        cpu.bus.ppu.peek_vram(line_address.wrapping_add(i))
+                    )
+                })
+                .collect();
+            lines.push(format!(
+                "{:04x}:{:04x}    {}",
+                line_address,
+                line_address.wrapping_add(0x1f),
+                values.join(" ")
+            ));
+        } else {
+            lines.push(cpu.bus.get_memory_as_string(line_address));
+        }
+        offset = offset.saturating_add(0x20);
+    }
+    Ok(lines.join("\n"))
+}
+
+fn edit_memory(arguments: &[&str], cpu: &mut Cpu) -> Result<String, String> {
+    let is_ppu = arguments.first() == Some(&"ppu");
+    let arguments = if is_ppu { &arguments[1..] } else { arguments };
+    if arguments.len() < 2 {
+        return Err(String::from("Usage : edit [ppu] <addr> <values...>"));
+    }
+    let address = parse_hex(arguments[0])?;
+    let values = arguments[1..]
+        .iter()
+        .map(|value| parse_hex_byte(value))
+        .collect::<Result<Vec<u8>, String>>()?;
+    for (i, value) in values.into_iter().enumerate() {
+        let address = address.wrapping_add(i as u16);
+        if is_ppu {
+            cpu.bus.ppu.write_vram(address, value);
+        } else {
+            cpu.bus.write_rom(address, value);
+        }
+    }
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::test_utils::{lock_sdl, write_test_rom};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc::Sender;
+
+    /// CPU after the reset sequence, with the access log enabled, at $C000 :
+    /// JSR $C010, STA $0305, LDA $2007, then NOPs. The subroutine at $C010 is INX, INX, RTS and
+    /// the NMI handler at $C100 is INX, RTI
+    fn new_cpu() -> Cpu<'static> {
+        let mut code = vec![0xea; 0x102];
+        code[..9].copy_from_slice(&[0x20, 0x10, 0xc0, 0x8d, 0x05, 0x03, 0xad, 0x07, 0x20]);
+        code[0x10..0x13].copy_from_slice(&[0xe8, 0xe8, 0x60]);
+        code[0x100..0x102].copy_from_slice(&[0xe8, 0x40]);
+        let sdl_context = Rc::new(RefCell::new(sdl2::init().unwrap()));
+        let cartridge = Rc::new(RefCell::new(Cartridge::new(write_test_rom(
+            "debugger", &code,
+        ))));
+        let mut cpu = Cpu::new(sdl_context, cartridge);
+        cpu.bus.ppu.set_headless();
+        cpu.bus.enable_access_log();
+        cpu.start(None);
+        while cpu.get_remaining_cycles() > 0 {
+            cpu.next();
+        }
+        cpu
+    }
+
+    /// Debugger with a channel to send commands
+    fn new_debugger() -> (Debugger, Sender<String>) {
+        let (sender, receiver) = channel();
+        (Debugger::new(receiver), sender)
+    }
+
+    /// Execute a command that does not resume the execution, returning its output
+    fn execute(debugger: &mut Debugger, cpu: &mut Cpu, command: &str) -> String {
+        let (action, output) = debugger.execute(command, cpu);
+        assert!(action == DebuggerAction::None);
+        output
+    }
+
+    /// Execute a resuming command, then run instructions like the emulator until the debugger breaks.
+    /// Returns the break reason and the number of instructions executed
+    fn resume(debugger: &mut Debugger, cpu: &mut Cpu, command: &str) -> Option<(String, u32)> {
+        let (action, output) = debugger.execute(command, cpu);
+        assert!(action == DebuggerAction::Resume);
+        assert_eq!(output, "");
+        for count in 1..=1000 {
+            debugger.before_instruction(cpu);
+            cpu.bus.clear_access_log();
+            cpu.next();
+            while cpu.get_remaining_cycles() > 0 {
+                cpu.next();
+            }
+            if let Some(reason) = debugger.check(cpu) {
+                return Some((reason.to_string(), count));
+            }
+        }
+        None
+    }
+
+    fn get_program_counter(cpu: &Cpu) -> u16 {
+        cpu.get_status().program_counter
+    }
+
+    #[test]
+    fn commands_from_channel() {
+        let (debugger, sender) = new_debugger();
+        sender.send(String::from("regs")).unwrap();
+        assert_eq!(debugger.poll_command(), Some(String::from("regs")));
+        assert_eq!(debugger.poll_command(), None);
+    }
+
+    #[test]
+    fn step_count() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+
+        // JSR, INX, INX
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "s 3"),
+            Some((String::from("Step"), 3))
+        );
+        assert_eq!(get_program_counter(&cpu), 0xc012);
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "step"),
+            Some((String::from("Step"), 1))
+        );
+        assert_eq!(get_program_counter(&cpu), 0xc003);
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "s 0"),
+            Some((String::from("Step"), 1))
+        );
+        assert_eq!(execute(&mut debugger, &mut cpu, "s x"), "Invalid count : x");
+    }
+
+    #[test]
+    fn step_over_subroutine() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+
+        // JSR, INX, INX, RTS
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "n"),
+            Some((String::from("Step"), 4))
+        );
+        assert_eq!(get_program_counter(&cpu), 0xc003);
+        assert_eq!(cpu.get_status().x_register, 2);
+        // Other instructions are single stepped
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "next"),
+            Some((String::from("Step"), 1))
+        );
+        assert_eq!(get_program_counter(&cpu), 0xc006);
+    }
+
+    #[test]
+    fn finish_subroutine_and_interrupt() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+
+        // Out of the subroutine, after its RTS
+        resume(&mut debugger, &mut cpu, "s").unwrap();
+        assert_eq!(get_program_counter(&cpu), 0xc010);
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "f"),
+            Some((String::from("Step"), 3))
+        );
+        assert_eq!(get_program_counter(&cpu), 0xc003);
+
+        // Out of the NMI handler, after its RTI
+        cpu.nmi();
+        while cpu.get_remaining_cycles() > 0 {
+            cpu.next();
+        }
+        assert_eq!(get_program_counter(&cpu), 0xc100);
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "finish"),
+            Some((String::from("Step"), 2))
+        );
+        assert_eq!(get_program_counter(&cpu), 0xc003);
+    }
+
+    #[test]
+    fn bank_breakpoints() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+
+        // NROM-128 has a single bank
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "b c006:1"),
+            "Breakpoint 1 added"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "break $c006:0"),
+            "Breakpoint 2 added"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "l"),
+            "  1 : break $C006 bank 1\n  2 : break $C006 bank 0"
+        );
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "c"),
+            Some((String::from("Breakpoint at $C006"), 5))
+        );
+
+        execute(&mut debugger, &mut cpu, "d 2");
+        execute(&mut debugger, &mut cpu, "set pc c000");
+        assert_eq!(resume(&mut debugger, &mut cpu, "c"), None);
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "b c006:x"),
+            "Invalid bank : x"
+        );
+    }
+
+    #[test]
+    fn watchpoint_ranges() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+
+        // Outside the range or another access kind
+        execute(&mut debugger, &mut cpu, "w 0306-0310");
+        execute(&mut debugger, &mut cpu, "w 0305 r");
+        execute(&mut debugger, &mut cpu, "w 0300-0305 w ppu");
+        // PPU read of CHR through $2007
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "w 0000-000f r ppu"),
+            "Watchpoint 4 added"
+        );
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "c"),
+            Some((String::from("Watchpoint : PPU read $0000 = $00"), 6))
+        );
+        assert_eq!(get_program_counter(&cpu), 0xc009);
+
+        // Range given in reverse order
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "w 0305-0301 w"),
+            "Watchpoint 5 added"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "l").lines().last(),
+            Some("  5 : watch cpu $0301-$0305 w")
+        );
+        execute(&mut debugger, &mut cpu, "set pc c003");
+        assert_eq!(
+            resume(&mut debugger, &mut cpu, "c"),
+            Some((String::from("Watchpoint : CPU write $0305 = $00"), 1))
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "w 0300 x"),
+            "Invalid watchpoint option : x"
+        );
+    }
+
+    #[test]
+    fn event_breakpoints() {
+        let _sdl_lock = lock_sdl();
+        let cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+
+        // Events are ignored without breakpoints
+        debugger.on_nmi();
+        debugger.on_irq();
+        debugger.on_frame();
+        assert!(debugger.check(&cpu).is_none());
+
+        debugger.add_event_breakpoint(EventBreakpoint::Nmi);
+        debugger.add_event_breakpoint(EventBreakpoint::Irq);
+        debugger.add_event_breakpoint(EventBreakpoint::Frame);
+        debugger.on_nmi();
+        assert_eq!(debugger.check(&cpu).unwrap().to_string(), "NMI");
+        assert!(debugger.check(&cpu).is_none());
+        debugger.on_irq();
+        assert_eq!(debugger.check(&cpu).unwrap().to_string(), "IRQ");
+        debugger.on_frame();
+        assert_eq!(debugger.check(&cpu).unwrap().to_string(), "New frame");
+        assert!(debugger.check(&cpu).is_none());
+    }
+
+    #[test]
+    fn scanline_breakpoint() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "break scanline 10"),
+            "Breakpoint 1 added"
+        );
+
+        // Breaks once, when the PPU enters the line
+        let mut breaks = vec![];
+        for _ in 0..341 * 20 {
+            cpu.bus.ppu.next();
+            if let Some(reason) = debugger.check(&cpu) {
+                let status = cpu.bus.ppu.get_status();
+                breaks.push((reason.to_string(), status.line, status.col));
+            }
+        }
+        assert_eq!(breaks, vec![(String::from("Scanline 10"), 10, 0)]);
+    }
+
+    #[test]
+    fn register_and_memory_values() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let (mut debugger, _sender) = new_debugger();
+
+        assert!(execute(&mut debugger, &mut cpu, "set a 7f").starts_with("PC:C000 A:7F"));
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "set a 1ff"),
+            "Value out of range : 1ff"
+        );
+        assert_eq!(cpu.get_status().accumulator, 0x7f);
+        assert!(execute(&mut debugger, &mut cpu, "set pc 1234").starts_with("PC:1234"));
+
+        assert_eq!(execute(&mut debugger, &mut cpu, "e 0300 12 $34"), "");
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "edit 0300 56 1ff"),
+            "Value out of range : 1ff"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "m 0300 1"),
+            cpu.bus.get_memory_as_string(0x0300)
+        );
+        assert!(execute(&mut debugger, &mut cpu, "m 0300").starts_with("0300:031f    12 34 00"));
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "mem ppu 3f00 40")
+                .lines()
+                .count(),
+            2
+        );
+        assert_eq!(execute(&mut debugger, &mut cpu, "x"), "Unknown command : x");
+    }
+}
@@ -0,0 +1,624 @@
+//! GDB remote serial protocol stub
+//! Registers are exposed as A, X, Y, P, SP (8 bits each) then PC (16 bits, little endian)
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use log::{info, warn};
+
+use super::debugger::{AddressSpace, WatchKind, Watchpoint};
+use crate::cpu::Cpu;
+
+/// Interrupt request sent by the client (Ctrl-C)
+const INTERRUPT_BYTE: u8 = 0x03;
+/// Stop reply for a breakpoint, a single step or an interruption (SIGTRAP)
+const STOP_TRAP: &str = "S05";
+/// Stop reply for an interruption requested by the client (SIGINT)
+const STOP_INTERRUPT: &str = "S02";
+
+/// Data received from the client
+#[derive(PartialEq, Debug)]
+pub enum Incoming {
+    Packet(String),
+    Interrupt,
+    InvalidChecksum,
+}
+
+/// What the emulator must do after the stub has been polled
+#[derive(PartialEq)]
+pub enum GdbAction {
+    None,
+    Halt,
+    Resume,
+    Quit,
+}
+
+/// GDB server component
+pub struct GdbStub {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    is_ack_mode: bool,
+    is_running: bool,
+    is_stepping: bool,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl GdbStub {
+    /// Start listening for a GDB client on a local TCP port
+    pub fn new(port: u16) -> GdbStub {
+        let listener = TcpListener::bind(("127.0.0.1", port)).expect("Cannot bind GDB port");
+        listener
+            .set_nonblocking(true)
+            .expect("Cannot set GDB listener non blocking");
+        info!("GDB stub listening on 127.0.0.1:{}", port);
+        GdbStub {
+            listener,
+            stream: None,
+            buffer: vec![],
+            is_ack_mode: true,
+            is_running: true,
+            is_stepping: false,
+            breakpoints: vec![],
+            watchpoints: vec![],
+        }
+    }
+
+    /// Accept client connection and execute received packets
+    pub fn poll(&mut self, cpu: &mut Cpu) -> GdbAction {
+        if self.stream.is_none() {
+            return self.accept();
+        }
+
+        let mut data = [0; 1024];
+        loop {
+            let stream = self.stream.as_mut().unwrap();
+            match stream.read(&mut data) {
+                Ok(0) => {
+                    info!("GDB client disconnected");
+                    self.disconnect();
+                    return GdbAction::Resume;
+                }
+                Ok(length) => self.buffer.extend_from_slice(&data[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => {
+                    warn!("GDB connection error : {}", error);
+                    self.disconnect();
+                    return GdbAction::Resume;
+                }
+            }
+        }
+
+        let mut action = GdbAction::None;
+        for incoming in parse_incoming(&mut self.buffer) {
+            let packet_action = match incoming {
+                Incoming::Packet(packet) => {
+                    if self.is_ack_mode {
+                        self.send_raw(b"+");
+                    }
+                    self.execute(&packet, cpu)
+                }
+                Incoming::Interrupt => {
+                    if self.is_running {
+                        self.is_running = false;
+                        self.send(STOP_INTERRUPT);
+                    }
+                    GdbAction::Halt
+                }
+                Incoming::InvalidChecksum => {
+                    self.send_raw(b"-");
+                    GdbAction::None
+                }
+            };
+            if packet_action != GdbAction::None {
+                action = packet_action;
+            }
+        }
+        action
+    }
+
+    /// Check breakpoints, watchpoints and single step at an instruction boundary. Returns true if the execution must halt
+    pub fn check(&mut self, cpu: &Cpu) -> bool {
+        if self.stream.is_none() || !self.is_running {
+            return false;
+        }
+        let program_counter = cpu.get_status().program_counter;
+        let reply = if self.is_stepping || self.breakpoints.contains(&program_counter) {
+            Some(String::from(STOP_TRAP))
+        } else {
+            self.find_watchpoint(cpu)
+        };
+        match reply {
+            Some(reply) => {
+                self.is_running = false;
+                self.is_stepping = false;
+                self.send(&reply);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find_watchpoint(&self, cpu: &Cpu) -> Option<String> {
+        for watchpoint in self.watchpoints.iter() {
+            for access in cpu.bus.get_access_log() {
+                if watchpoint.matches(AddressSpace::Cpu, access.address, access.is_write) {
+                    let kind = match watchpoint.kind {
+                        WatchKind::Write => "watch",
+                        WatchKind::Read => "rwatch",
+                        WatchKind::ReadWrite => "awatch",
+                    };
+                    return Some(format!("T05{}:{:04x};", kind, access.address));
+                }
+            }
+        }
+        None
+    }
+
+    fn accept(&mut self) -> GdbAction {
+        match self.listener.accept() {
+            Ok((stream, address)) => {
+                info!("GDB client connected from {}", address);
+                stream
+                    .set_nonblocking(true)
+                    .expect("Cannot set GDB stream non blocking");
+                let _ = stream.set_nodelay(true);
+                self.stream = Some(stream);
+                self.buffer.clear();
+                self.is_ack_mode = true;
+                self.is_running = false;
+                self.is_stepping = false;
+                GdbAction::Halt
+            }
+            Err(_) => GdbAction::None,
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.is_running = true;
+        self.is_stepping = false;
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    /// Execute a packet received from the client and send the reply
+    fn execute(&mut self, packet: &str, cpu: &mut Cpu) -> GdbAction {
+        let (command, arguments) =
+            packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "?" => String::from(STOP_TRAP),
+            "g" => read_registers(cpu),
+            "G" => write_registers(arguments, cpu),
+            "p" => read_register(arguments, cpu),
+            "P" => write_register(arguments, cpu),
+            "m" => read_memory(arguments, cpu),
+            "M" => write_memory(arguments, cpu),
+            "c" | "s" => {
+                if let Some(address) = parse_hex(arguments) {
+                    let mut status = cpu.get_status();
+                    status.program_counter = address as u16;
+                    cpu.set_status(&status);
+                }
+                return self.resume(command == "s");
+            }
+            "Z" | "z" => self.update_breakpoint(command == "Z", arguments),
+            "H" | "T" => String::from("OK"),
+            "k" => {
+                info!("GDB client killed the emulation");
+                return GdbAction::Quit;
+            }
+            "D" => {
+                self.send("OK");
+                info!("GDB client detached");
+                self.disconnect();
+                return GdbAction::Resume;
+            }
+            "Q" if packet == "QStartNoAckMode" => {
+                self.send("OK");
+                self.is_ack_mode = false;
+                return GdbAction::None;
+            }
+            "q" | "Q" | "v" => match self.execute_query(packet) {
+                Some(reply) => reply,
+                None => return self.resume(packet == "vCont;s" || packet.starts_with("vCont;s:")),
+            },
+            _ => String::new(),
+        };
+        self.send(&reply);
+        GdbAction::None
+    }
+
+    /// Execute query packets. Returns None for vCont resume packets
+    fn execute_query(&mut self, packet: &str) -> Option<String> {
+        let reply = match packet.split(':').next().unwrap_or("") {
+            "qSupported" => "PacketSize=1000;QStartNoAckMode+;swbreak+",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            "vCont?" => "vCont;c;C;s;S",
+            "vMustReplyEmpty" => "",
+            _ if packet.starts_with("vCont;") => return None,
+            _ => "",
+        };
+        Some(String::from(reply))
+    }
+
+    fn resume(&mut self, is_stepping: bool) -> GdbAction {
+        self.is_running = true;
+        self.is_stepping = is_stepping;
+        GdbAction::Resume
+    }
+
+    fn update_breakpoint(&mut self, is_insert: bool, arguments: &str) -> String {
+        let parts: Vec<&str> = arguments.split(',').collect();
+        if parts.len() < 3 {
+            return String::from("E01");
+        }
+        let address = match parse_hex(parts[1]) {
+            Some(address) => address as u16,
+            None => return String::from("E01"),
+        };
+        let length = parse_hex(parts[2]).unwrap_or(1).max(1) as u16;
+        let kind = match parts[0] {
+            "0" | "1" => None,
+            "2" => Some(WatchKind::Write),
+            "3" => Some(WatchKind::Read),
+            "4" => Some(WatchKind::ReadWrite),
+            _ => return String::new(),
+        };
+        match kind {
+            None => {
+                self.breakpoints.retain(|other| *other != address);
+                if is_insert {
+                    self.breakpoints.push(address);
+                }
+            }
+            Some(kind) => {
+                let end = address.saturating_add(length - 1);
+                self.watchpoints.retain(|other| {
+                    !(other.start == address && other.end == end && other.kind == kind)
+                });
+                if is_insert {
+                    self.watchpoints.push(Watchpoint {
+                        start: address,
+                        end,
+                        kind,
+                        space: AddressSpace::Cpu,
+                    });
+                }
+            }
+        }
+        String::from("OK")
+    }
+
+    /// Send a packet to the client
+    fn send(&mut self, data: &str) {
+        self.send_raw(encode_packet(data).as_bytes());
+    }
+
+    fn send_raw(&mut self, data: &[u8]) {
+        if let Some(stream) = self.stream.as_mut() {
+            let _ = stream.set_nonblocking(false);
+            if let Err(error) = stream.write_all(data) {
+                warn!("GDB connection error : {}", error);
+            }
+            let _ = stream.set_nonblocking(true);
+        }
+    }
+}
+
+/// Compute the modulo 256 checksum of a packet
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Frame a packet as $data#checksum
+pub fn encode_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data.as_bytes()))
+}
+
+/// Extract complete packets and interrupt requests from received data. Incomplete data is left in the buffer
+pub fn parse_incoming(buffer: &mut Vec<u8>) -> Vec<Incoming> {
+    let mut result = vec![];
+    let mut index = 0;
+    while index < buffer.len() {
+        match buffer[index] {
+            INTERRUPT_BYTE => {
+                result.push(Incoming::Interrupt);
+                index += 1;
+            }
+            b'$' => {
+                let end = match buffer[index..].iter().position(|byte| *byte == b'#') {
+                    Some(position) => index + position,
+                    None => break,
+                };
+                if end + 2 >= buffer.len() {
+                    break;
+                }
+                let data = &buffer[index + 1..end];
+                let expected = std::str::from_utf8(&buffer[end + 1..end + 3])
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+                if expected == Some(checksum(data)) {
+                    result.push(Incoming::Packet(String::from_utf8_lossy(data).into_owned()));
+                } else {
+                    result.push(Incoming::InvalidChecksum);
+                }
+                index = end + 3;
+            }
+            // Acknowledgments and noise
+            _ => index += 1,
+        }
+    }
+    buffer.drain(..index);
+    result
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 16).ok()
+}
+
+fn decode_hex_bytes(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parse an "address,length" argument
+fn parse_range(value: &str) -> Option<(u16, u16)> {
+    let (address, length) = value.split_once(',')?;
+    Some((parse_hex(address)? as u16, parse_hex(length)? as u16))
+}
+
+fn read_registers(cpu: &Cpu) -> String {
+    let status = cpu.get_status();
+    format!(
+        "{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        status.accumulator,
+        status.x_register,
+        status.y_register,
+        status.status_register,
+        status.stack_pointer,
+        status.program_counter & 0xff,
+        status.program_counter >> 8,
+    )
+}
+
+fn write_registers(arguments: &str, cpu: &mut Cpu) -> String {
+    let bytes = match decode_hex_bytes(arguments) {
+        Some(bytes) if bytes.len() >= 7 => bytes,
+        _ => return String::from("E01"),
+    };
+    let mut status = cpu.get_status();
+    status.accumulator = bytes[0];
+    status.x_register = bytes[1];
+    status.y_register = bytes[2];
+    status.status_register = bytes[3];
+    status.stack_pointer = bytes[4];
+    status.program_counter = u16::from_le_bytes([bytes[5], bytes[6]]);
+    cpu.set_status(&status);
+    String::from("OK")
+}
+
+fn read_register(arguments: &str, cpu: &Cpu) -> String {
+    let status = cpu.get_status();
+    match parse_hex(arguments) {
+        Some(0) => format!("{:02x}", status.accumulator),
+        Some(1) => format!("{:02x}", status.x_register),
+        Some(2) => format!("{:02x}", status.y_register),
+        Some(3) => format!("{:02x}", status.status_register),
+        Some(4) => format!("{:02x}", status.stack_pointer),
+        Some(5) => format!(
+            "{:02x}{:02x}",
+            status.program_counter & 0xff,
+            status.program_counter >> 8
+        ),
+        _ => String::from("E01"),
+    }
+}
+
+fn write_register(arguments: &str, cpu: &mut Cpu) -> String {
+    let (register, value) = match arguments.split_once('=') {
+        Some((register, value)) => (parse_hex(register), decode_hex_bytes(value)),
+        None => return String::from("E01"),
+    };
+    let value = match value {
+        Some(value) if !value.is_empty() => value,
+        _ => return String::from("E01"),
+    };
+    let mut status = cpu.get_status();
+    match register {
+        Some(0) => status.accumulator = value[0],
+        Some(1) => status.x_register = value[0],
+        Some(2) => status.y_register = value[0],
+        Some(3) => status.status_register = value[0],
+        Some(4) => status.stack_pointer = value[0],
+        Some(5) => {
+            status.program_counter = u16::from_le_bytes([value[0], *value.get(1).unwrap_or(&0)])
+        }
+        _ => return String::from("E01"),
+    }
+    cpu.set_status(&status);
+    String::from("OK")
+}
+
+fn read_memory(arguments: &str, cpu: &mut Cpu) -> String {
+    match parse_range(arguments) {
+        Some((address, length)) => (0..length)
+            .map(|i| format!("{:02x}", cpu.bus.peek(address.wrapping_add(i))))
+            .collect(),
+        None => String::from("E01"),
+    }
+}
+
+fn write_memory(arguments: &str, cpu: &mut Cpu) -> String {
+    let (range, data) = match arguments.split_once(':') {
+        Some(split) => split,
+        None => return String::from("E01"),
+    };
+    match (parse_range(range), decode_hex_bytes(data)) {
+        (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+            for (i, byte) in bytes.iter().enumerate() {
+                cpu.bus.write_rom(address.wrapping_add(i as u16), *byte);
+            }
+            String::from("OK")
+        }
+        _ => String::from("E01"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::test_utils::{lock_sdl, write_test_rom};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// CPU after the reset sequence, at $C000 : LDA #$42, LDX #$07, STA $10, then NOPs
+    fn new_cpu() -> Cpu<'static> {
+        let code = [0xa9, 0x42, 0xa2, 0x07, 0x85, 0x10];
+        let sdl_context = Rc::new(RefCell::new(sdl2::init().unwrap()));
+        let cartridge = Rc::new(RefCell::new(Cartridge::new(write_test_rom("gdb", &code))));
+        let mut cpu = Cpu::new(sdl_context, cartridge);
+        cpu.start(None);
+        while cpu.get_remaining_cycles() > 0 {
+            cpu.next();
+        }
+        cpu
+    }
+
+    /// Poll the stub until the client receives a reply packet or the execution is resumed
+    fn poll_reply(stub: &mut GdbStub, client: &mut TcpStream, cpu: &mut Cpu) -> Option<String> {
+        let mut buffer = vec![];
+        let mut data = [0; 256];
+        for _ in 0..200 {
+            if stub.poll(cpu) == GdbAction::Resume {
+                return None;
+            }
+            if let Ok(length) = client.read(&mut data) {
+                buffer.extend_from_slice(&data[..length]);
+            }
+            for incoming in parse_incoming(&mut buffer) {
+                if let Incoming::Packet(packet) = incoming {
+                    return Some(packet);
+                }
+            }
+        }
+        panic!("No reply from the GDB stub");
+    }
+
+    /// Send a packet and wait for its reply
+    fn request(stub: &mut GdbStub, client: &mut TcpStream, cpu: &mut Cpu, packet: &str) -> String {
+        client.write_all(encode_packet(packet).as_bytes()).unwrap();
+        poll_reply(stub, client, cpu).expect("Execution resumed instead of a reply")
+    }
+
+    /// Send a resume packet, run the CPU until the stub stops it, then read the stop reply
+    fn resume(stub: &mut GdbStub, client: &mut TcpStream, cpu: &mut Cpu, packet: &str) -> String {
+        client.write_all(encode_packet(packet).as_bytes()).unwrap();
+        assert_eq!(poll_reply(stub, client, cpu), None);
+        for _ in 0..1000 {
+            cpu.next();
+            if cpu.get_remaining_cycles() == 0 && stub.check(cpu) {
+                return poll_reply(stub, client, cpu).unwrap();
+            }
+        }
+        panic!("The GDB stub did not stop the execution");
+    }
+
+    #[test]
+    fn loopback_session() {
+        let _sdl_lock = lock_sdl();
+        let mut cpu = new_cpu();
+        let mut stub = GdbStub::new(0);
+        let address = stub.listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let mut action = GdbAction::None;
+        for _ in 0..200 {
+            action = stub.poll(&mut cpu);
+            if action != GdbAction::None {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(action == GdbAction::Halt);
+
+        assert_eq!(request(&mut stub, &mut client, &mut cpu, "?"), STOP_TRAP);
+        let status = cpu.get_status();
+        assert_eq!(
+            request(&mut stub, &mut client, &mut cpu, "g"),
+            format!(
+                "000000{:02x}{:02x}00c0",
+                status.status_register, status.stack_pointer
+            )
+        );
+        assert_eq!(
+            request(&mut stub, &mut client, &mut cpu, "mc000,6"),
+            "a942a2078510"
+        );
+
+        // Single step over LDA #$42
+        assert_eq!(resume(&mut stub, &mut client, &mut cpu, "s"), STOP_TRAP);
+        let status = cpu.get_status();
+        assert_eq!(status.program_counter, 0xc002);
+        assert_eq!(status.accumulator, 0x42);
+        assert_eq!(
+            request(&mut stub, &mut client, &mut cpu, "g"),
+            format!(
+                "420000{:02x}{:02x}02c0",
+                status.status_register, status.stack_pointer
+            )
+        );
+
+        // Continue to a breakpoint after STA $10
+        assert_eq!(request(&mut stub, &mut client, &mut cpu, "Z0,c006,1"), "OK");
+        assert_eq!(resume(&mut stub, &mut client, &mut cpu, "c"), STOP_TRAP);
+        let status = cpu.get_status();
+        assert_eq!(status.program_counter, 0xc006);
+        assert_eq!(status.x_register, 0x07);
+        assert_eq!(request(&mut stub, &mut client, &mut cpu, "m10,1"), "42");
+    }
+
+    #[test]
+    fn encode_packets() {
+        assert_eq!(encode_packet("OK"), "$OK#9a");
+        assert_eq!(encode_packet(""), "$#00");
+        assert_eq!(encode_packet("S05"), "$S05#b8");
+    }
+
+    #[test]
+    fn parse_incoming_data() {
+        let mut buffer = b"+$g#67\x03$m0,2#fb$bad#00$qSupp".to_vec();
+        assert_eq!(
+            parse_incoming(&mut buffer),
+            vec![
+                Incoming::Packet(String::from("g")),
+                Incoming::Interrupt,
+                Incoming::Packet(String::from("m0,2")),
+                Incoming::InvalidChecksum,
+            ]
+        );
+        assert_eq!(buffer, b"$qSupp".to_vec());
+    }
+
+    #[test]
+    fn decode_arguments() {
+        assert_eq!(parse_range("c000,10"), Some((0xc000, 0x10)));
+        assert_eq!(parse_range("c000"), None);
+        assert_eq!(decode_hex_bytes("a9ff"), Some(vec![0xa9, 0xff]));
+        assert_eq!(decode_hex_bytes("a9f"), None);
+    }
+}
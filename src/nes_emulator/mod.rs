@@ -1,21 +1,33 @@
 //! Emulator main engine
 mod clock;
-use log::info;
+mod debugger;
+mod gdb;
+mod ppu_viewer;
+pub mod regression;
+mod rom_database;
+mod tracer;
+use log::{error, info, warn};
 use regex::Regex;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
 use std::cell::RefCell;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::io::BufWriter;
-use std::io::Write;
 use std::rc::Rc;
 
+pub use crate::apu::{Channel, RecordMode};
 use crate::cartridge::Cartridge;
-use crate::cpu::instructions::INSTRUCTION_TABLE;
-use crate::cpu::opcodes::OPCODES;
 use crate::cpu::Cpu;
+use crate::ppu::events_to_csv;
+pub use crate::ppu::{NtscPreset, PpuEvent, PpuEventKind};
+pub use crate::region::Region;
+pub use clock::SyncMode;
+use debugger::{Breakpoint, Debugger, DebuggerAction};
+use gdb::{GdbAction, GdbStub};
+use ppu_viewer::PpuViewer;
+use rom_database::RomDatabase;
+pub use tracer::{TraceConfig, TraceFormat};
 
 pub struct NesEmulator<'a> {
     pause: bool,
@@ -26,9 +38,15 @@ pub struct NesEmulator<'a> {
     cpu: Cpu<'a>,
     lines: Vec<String>,
     line_index: usize,
-    parity: bool,
-    log_activated: bool,
-    log_file: Option<std::io::BufWriter<std::fs::File>>,
+    region: Region,
+    master_clock_remainder: u32, // Master clock cycles not yet consumed by the PPU
+    tracer: Option<tracer::Tracer>,
+    debugger: Option<Debugger>,
+    gdb_stub: Option<GdbStub>,
+    is_overscan_cropped: bool,
+    is_powered_on: bool,
+    ppu_viewer: Option<PpuViewer>,
+    audio_record_mode: RecordMode, // Used by the recording hotkey
 }
 
 impl NesEmulator<'_> {
@@ -39,8 +57,9 @@ impl NesEmulator<'_> {
 
         let _cartridge = Rc::new(RefCell::new(Cartridge::new(rom_file)));
         let _cpu = Cpu::new(_sdl_context.clone(), _cartridge.clone());
+        let region = _cartridge.borrow().get_region().unwrap_or(Region::Ntsc);
 
-        NesEmulator {
+        let mut emulator = NesEmulator {
             pause: false,
             is_test_mode: false,
             sdl_context: _sdl_context,
@@ -49,222 +68,647 @@ impl NesEmulator<'_> {
             cpu: _cpu,
             lines: vec![],
             line_index: 0,
-            parity: false,
-            log_activated: false,
-            log_file: None,
-        }
+            region,
+            master_clock_remainder: 0,
+            tracer: None,
+            debugger: None,
+            gdb_stub: None,
+            is_overscan_cropped: false,
+            is_powered_on: false,
+            ppu_viewer: None,
+            audio_record_mode: RecordMode::Mix,
+        };
+        emulator.set_region(region);
+        emulator.clock.set_sync_mode(SyncMode::Audio);
+        emulator
     }
 
-    /// Starts and runs the Emulator execution
-    pub fn start(&mut self, entry_point: Option<u16>) {
-        self.log_activated = false;
-        if self.log_activated {
-            self.log_file = Some(BufWriter::new(
-                File::create(r#"C:\Users\lvromman\Documents\GitHub\nes_emu_rust\log.txt"#)
-                    .expect("Cannot create message"),
-            ));
-        }
+    /// Instantiate an Emulator without window, sound output nor frame rate limit, for automated runs.
+    /// SDL audio is never initialized, so no sound device nor audio driver is needed
+    pub fn new_headless(rom_file: String) -> NesEmulator<'static> {
+        let mut emulator = NesEmulator::new(rom_file);
+        emulator.cpu.bus.ppu.set_headless();
+        emulator.cpu.bus.apu.set_headless();
+        emulator.clock.set_throttle(false);
+        emulator
+    }
+
+    /// Power the console on
+    fn power_on(&mut self, entry_point: Option<u16>) {
         self.cpu.bus.apu.start();
+        if self.clock.get_sync_mode() == SyncMode::Audio && !self.cpu.bus.apu.is_audio_started() {
+            info!("No audio device, falling back to video sync");
+            self.clock.set_sync_mode(SyncMode::Video);
+        }
         self.cpu.bus.ppu.start();
         self.cpu.start(entry_point);
         self.cpu.bus.ppu.next();
         self.cpu.bus.ppu.next();
         self.cpu.bus.ppu.next();
+        self.is_powered_on = true;
+    }
+
+    /// Run the given number of frames, without handling window events. Powers the console on first if needed
+    pub fn run_frames(&mut self, count: u128) {
+        if !self.is_powered_on {
+            self.power_on(None);
+        }
+        let last_frame = self.clock.get_clock_count() + count;
+        while self.clock.get_clock_count() < last_frame {
+            self.next_cycle();
+        }
+    }
+
+    /// Get the number of frames run since power on
+    pub fn get_frame_count(&self) -> u128 {
+        self.clock.get_clock_count()
+    }
+
+    /// Set controller 1 buttons : A, B, Select, Start, Up, Down, Left and Right from bit 0 to 7
+    pub fn set_controller_1(&mut self, buttons: u8) {
+        self.cpu.bus.controller_1.set_status(buttons);
+    }
+
+    /// Starts and runs the Emulator execution
+    pub fn start(&mut self, entry_point: Option<u16>) {
+        self.power_on(entry_point);
 
         let mut continuer: bool = true;
+        if self.debugger.is_some() {
+            println!("{}", self.get_status_log());
+            debugger::print_prompt();
+        }
 
         while continuer {
             if !self.pause {
-                if self.cpu.bus.interrupt.borrow_mut().check_and_clear_nmi() {
-                    self.cpu.nmi();
+                self.next_cycle();
+            }
+            continuer =
+                self.handle_debugger_commands() && self.handle_gdb_stub() && self.handle_events();
+        }
+        if let Err(error) = self.stop_audio_recording() {
+            error!("Cannot complete audio recording : {}", error);
+        }
+    }
+
+    /// Executes one CPU cycle, with the matching PPU and APU cycles
+    fn next_cycle(&mut self) {
+        if self.cpu.get_remaining_cycles() == 0 {
+            if let Some(debugger) = self.debugger.as_mut() {
+                debugger.before_instruction(&self.cpu);
+            }
+            self.cpu.bus.clear_access_log();
+
+            // Interrupts are polled between instructions
+            if self.cpu.bus.interrupt.borrow_mut().check_and_clear_nmi() {
+                self.cpu.nmi();
+                if let Some(debugger) = self.debugger.as_mut() {
+                    debugger.on_nmi();
                 }
-                if self.cpu.bus.interrupt.borrow_mut().check_and_clear_irq()
-                    && self.cpu.get_interrupt_flag()
-                {
-                    self.cpu.irq();
+            }
+            if self.cpu.bus.interrupt.borrow().is_irq() && self.cpu.irq() {
+                if let Some(debugger) = self.debugger.as_mut() {
+                    debugger.on_irq();
                 }
-                if self.parity {
-                    self.cpu.bus.apu.next();
+            }
+        }
+        let stolen_cycles = self.cpu.bus.next_apu_cycle();
+        self.cpu.stall(stolen_cycles);
+        self.cpu.next();
+        // 3 PPU dots per CPU cycle on NTSC and Dendy, 3.2 on PAL
+        self.master_clock_remainder += self.region.get_cpu_clock_divider();
+        while self.master_clock_remainder >= self.region.get_ppu_clock_divider() {
+            self.master_clock_remainder -= self.region.get_ppu_clock_divider();
+            self.cpu.bus.ppu.next();
+        }
+
+        if self.is_test_mode && self.cpu.get_remaining_cycles() == 0 {
+            let cpu_status = self.cpu.get_status();
+            let ppu_status = self.cpu.bus.ppu.get_status();
+            self.check_test(cpu_status, ppu_status);
+        }
+
+        if self.cpu.get_remaining_cycles() == 0 {
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&self.cpu, self.clock.get_clock_count());
+                if tracer.is_stopped() {
+                    info!("Trace stop condition reached, trace file closed");
+                    self.tracer = None;
                 }
-                self.cpu.next();
-                self.cpu.bus.ppu.next();
-                self.cpu.bus.ppu.next();
-                self.cpu.bus.ppu.next();
-
-                // Odd or even cycle. Needed to trigger the apu one every two cpu cycles.
-                self.parity = !self.parity;
-
-                if self.is_test_mode && self.cpu.get_remaining_cycles() == 0 {
-                    let cpu_status = self.cpu.get_status();
-                    let ppu_status = self.cpu.bus.ppu.get_status();
-                    self.check_test(cpu_status, ppu_status);
+            }
+        }
+
+        if self
+            .cpu
+            .bus
+            .interrupt
+            .borrow_mut()
+            .check_and_clear_frame_updated()
+        {
+            // Without real time limit, the output is not adjusted to the audio device
+            if self.clock.is_throttled() && self.cpu.bus.apu.is_audio_started() {
+                if self.clock.get_sync_mode() == SyncMode::Audio {
+                    self.cpu.bus.apu.wait_for_audio();
                 }
+                self.cpu.bus.apu.update_rate_control();
+            }
+            self.clock.tick();
+            info!("FPS : {}", self.clock.get_fps());
+            if let Some(debugger) = self.debugger.as_mut() {
+                debugger.on_frame();
+            }
+            if let Some(ppu_viewer) = self.ppu_viewer.as_mut() {
+                ppu_viewer.update(&self.cpu.bus.ppu);
+            }
+        }
 
-                if self.log_activated
-                    && self.log_file.is_some()
-                    && self.cpu.get_remaining_cycles() == 0
-                {
-                    let log = self.get_status_log();
-                    //self.log("{}", log);
-                    self.log_file
-                        .as_mut()
-                        .unwrap()
-                        .write_all(log.as_bytes())
-                        .unwrap();
-                    self.log_file.as_mut().unwrap().write_all(b"\n").unwrap();
-
-                    //if self.cpu.get_total_cycles() > 87529 {
-                    if self.clock.get_clock_count() > 10 {
-                        self.cpu.bus.ppu.print_primary_oam();
-                        self.cpu.bus.ppu.print_secondary_oam();
-                        std::process::exit(0);
-                    }
+        if self.cpu.get_remaining_cycles() == 0 {
+            let reason = match self.debugger.as_mut() {
+                Some(debugger) => debugger.check(&self.cpu),
+                None => None,
+            };
+            if let Some(reason) = reason {
+                self.pause = true;
+                println!("{}", reason);
+                println!("{}", self.get_status_log());
+                debugger::print_prompt();
+            }
+            if let Some(gdb_stub) = self.gdb_stub.as_mut() {
+                if gdb_stub.check(&self.cpu) {
+                    self.pause = true;
                 }
+            }
+        }
+    }
 
-                if self
-                    .cpu
-                    .bus
-                    .interrupt
-                    .borrow_mut()
-                    .check_and_clear_frame_updated()
-                {
-                    self.clock.tick();
-                    info!("FPS : {}", self.clock.get_fps());
+    /// Executes the packets sent by the GDB client. Returns false when the client kills the emulation
+    fn handle_gdb_stub(&mut self) -> bool {
+        let Some(gdb_stub) = self.gdb_stub.as_mut() else {
+            return true;
+        };
+        match gdb_stub.poll(&mut self.cpu) {
+            GdbAction::Halt => self.pause = true,
+            GdbAction::Resume => self.pause = false,
+            GdbAction::Quit => return false,
+            GdbAction::None => (),
+        }
+        true
+    }
+
+    /// Executes the commands typed in the debugger. Returns false when the user quits
+    fn handle_debugger_commands(&mut self) -> bool {
+        let Some(debugger) = self.debugger.as_mut() else {
+            return true;
+        };
+        while let Some(command) = debugger.poll_command() {
+            let (action, output) = debugger.execute(&command, &mut self.cpu);
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            match action {
+                DebuggerAction::Resume => {
+                    self.pause = false;
+                    return true;
                 }
+                DebuggerAction::Quit => return false,
+                DebuggerAction::None => debugger::print_prompt(),
             }
+        }
+        true
+    }
 
-            let mut event_pump = self.sdl_context.borrow_mut().event_pump().unwrap();
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Q),
-                        ..
-                    } => continuer = false,
-                    Event::KeyDown {
-                        keycode: Some(Keycode::P),
-                        ..
-                    } => self.toggle_pause(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::S),
-                        ..
-                    } => println!("{}", self.get_status_log()),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Up),
-                        ..
-                    } => self.cpu.bus.controller_1.set_up(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Up),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_up(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Down),
-                        ..
-                    } => self.cpu.bus.controller_1.set_down(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Down),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_down(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Left),
-                        ..
-                    } => self.cpu.bus.controller_1.set_left(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Left),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_left(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Right),
-                        ..
-                    } => self.cpu.bus.controller_1.set_right(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Right),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_right(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => self.cpu.bus.controller_1.set_select(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_select(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Return),
-                        ..
-                    } => self.cpu.bus.controller_1.set_start(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Return),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_start(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::Space),
-                        ..
-                    } => self.cpu.bus.controller_1.set_a(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::Space),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_a(),
-                    Event::KeyDown {
-                        keycode: Some(Keycode::LCtrl),
-                        ..
-                    } => self.cpu.bus.controller_1.set_b(),
-                    Event::KeyUp {
-                        keycode: Some(Keycode::LCtrl),
-                        ..
-                    } => self.cpu.bus.controller_1.clear_b(),
-                    _ => (),
+    /// Handles SDL events. Returns false when the user quits
+    fn handle_events(&mut self) -> bool {
+        let mut continuer = true;
+        let mut event_pump = self.sdl_context.borrow_mut().event_pump().unwrap();
+        for event in event_pump.poll_iter() {
+            if let Some(ppu_viewer) = self.ppu_viewer.as_mut() {
+                if ppu_viewer.handle_event(&event, &self.cpu.bus.ppu) {
+                    continue;
+                }
+            }
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Q),
+                    ..
+                } => continuer = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => self.toggle_pause(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    ..
+                } => println!("{}", self.get_status_log()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => self.take_screenshot(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => self.take_ppu_dump(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    let is_enabled = !self.cpu.bus.ppu.is_event_overlay_enabled();
+                    self.set_event_overlay(is_enabled);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => self.take_event_log(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => self.toggle_audio_recording(),
+                Event::KeyDown {
+                    keycode:
+                        Some(
+                            keycode @ (Keycode::Num1
+                            | Keycode::Num2
+                            | Keycode::Num3
+                            | Keycode::Num4
+                            | Keycode::Num5),
+                        ),
+                    keymod,
+                    ..
+                } => {
+                    let channel = Channel::ALL[keycode as usize - Keycode::Num1 as usize];
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        let is_soloed = !self.is_channel_soloed(channel);
+                        self.set_channel_soloed(channel, is_soloed);
+                    } else {
+                        let is_muted = !self.is_channel_muted(channel);
+                        self.set_channel_muted(channel, is_muted);
+                    }
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Num0),
+                    ..
+                } => self.reset_channel_controls(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Up),
+                    ..
+                } => self.cpu.bus.controller_1.set_up(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Up),
+                    ..
+                } => self.cpu.bus.controller_1.clear_up(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Down),
+                    ..
+                } => self.cpu.bus.controller_1.set_down(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Down),
+                    ..
+                } => self.cpu.bus.controller_1.clear_down(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Left),
+                    ..
+                } => self.cpu.bus.controller_1.set_left(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Left),
+                    ..
+                } => self.cpu.bus.controller_1.clear_left(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Right),
+                    ..
+                } => self.cpu.bus.controller_1.set_right(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Right),
+                    ..
+                } => self.cpu.bus.controller_1.clear_right(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => self.cpu.bus.controller_1.set_select(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => self.cpu.bus.controller_1.clear_select(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    ..
+                } => self.cpu.bus.controller_1.set_start(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Return),
+                    ..
+                } => self.cpu.bus.controller_1.clear_start(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Space),
+                    ..
+                } => self.cpu.bus.controller_1.set_a(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Space),
+                    ..
+                } => self.cpu.bus.controller_1.clear_a(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::LCtrl),
+                    ..
+                } => self.cpu.bus.controller_1.set_b(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::LCtrl),
+                    ..
+                } => self.cpu.bus.controller_1.clear_b(),
+                _ => (),
             }
         }
+        continuer
     }
 
     fn get_status_log(&mut self) -> String {
-        let cpu_status = self.cpu.get_status();
-        let ppu_status = self.cpu.bus.ppu.get_status();
         let ppu_full_status = self.cpu.bus.ppu.get_ppustatus_as_string();
-        let opcode = self.cpu.bus.read_rom(cpu_status.program_counter);
-        let mut opcode_arg_1 = "  ".to_string();
-        let mut opcode_arg_2 = "  ".to_string();
-        if OPCODES[&opcode].len > 1 {
-            opcode_arg_1 = format!(
-                "{:02x}",
-                self.cpu.bus.read_rom(cpu_status.program_counter + 1)
-            );
-        }
-        if OPCODES[&opcode].len > 2 {
-            opcode_arg_2 = format!(
-                "{:02x}",
-                self.cpu.bus.read_rom(cpu_status.program_counter + 2)
-            );
-        }
-
         let zero_page_xor = self.cpu.bus.xor_zero_page();
-
-        format!("{:x}  {:02x} {} {}  {:30}  A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{},{} CYC:{}, ZeroPage:{:02x},{}",
-            cpu_status.program_counter,
-            opcode,
-            opcode_arg_1,
-            opcode_arg_2,
-            INSTRUCTION_TABLE[opcode as usize].get_syntax(&mut self.cpu),
-            cpu_status.accumulator,
-            cpu_status.x_register,
-            cpu_status.y_register,
-            cpu_status.status_register,
-            cpu_status.stack_pointer,
-            ppu_status.line,
-            ppu_status.col,
-            cpu_status.total_cycles,
+        format!(
+            "{} ZeroPage:{:02x},{}",
+            tracer::format_line(
+                TraceFormat::Nintendulator,
+                &self.cpu,
+                self.clock.get_clock_count()
+            ),
             zero_page_xor,
             ppu_full_status,
         )
     }
 
+    /// Save the last frame to a PNG file named after the ROM and the frame number
+    fn take_screenshot(&self) {
+        let file_name = format!("{}.png", self.get_capture_name());
+        match self.save_screenshot(&file_name, self.is_overscan_cropped) {
+            Ok(()) => info!("Screenshot saved to {}", file_name),
+            Err(error) => error!("Cannot save screenshot {} : {}", file_name, error),
+        }
+    }
+
+    /// Save the PPU views next to the screenshots, in a `<rom>_<frame>_ppu` directory
+    fn take_ppu_dump(&self) {
+        let directory = format!("{}_ppu", self.get_capture_name());
+        let pattern_palette = self
+            .ppu_viewer
+            .as_ref()
+            .map_or(0, |viewer| viewer.get_pattern_palette());
+        match self.dump_ppu_views(&directory, pattern_palette) {
+            Ok(()) => info!("PPU views saved to {}", directory),
+            Err(error) => error!("Cannot save PPU views to {} : {}", directory, error),
+        }
+    }
+
+    /// Save the events of the last frame next to the screenshots, to `<rom>_<frame>_events.csv`
+    fn take_event_log(&mut self) {
+        self.cpu.bus.ppu.enable_event_log();
+        let file_name = format!("{}_events.csv", self.get_capture_name());
+        match self.save_event_log(&file_name) {
+            Ok(()) => info!("PPU events saved to {}", file_name),
+            Err(error) => error!("Cannot save PPU events {} : {}", file_name, error),
+        }
+    }
+
+    /// Start recording audio next to the screenshots, or stop the running recording
+    fn toggle_audio_recording(&mut self) {
+        if self.is_audio_recording() {
+            match self.stop_audio_recording() {
+                Ok(()) => info!("Audio recording stopped"),
+                Err(error) => error!("Cannot complete audio recording : {}", error),
+            }
+            return;
+        }
+        let file_name = format!("{}.wav", self.get_capture_name());
+        match self.start_audio_recording(&file_name, self.audio_record_mode) {
+            Ok(()) => info!("Recording audio to {}", file_name),
+            Err(error) => error!("Cannot record audio to {} : {}", file_name, error),
+        }
+    }
+
+    /// Get the ROM file stem followed by the frame number, to name captures
+    fn get_capture_name(&self) -> String {
+        let rom_name = self._cartridge.borrow().file_name.clone();
+        let rom_name = std::path::Path::new(&rom_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("screenshot")
+            .to_string();
+        format!("{}_{}", rom_name, self.clock.get_clock_count())
+    }
+
+    /// Save the last complete frame to a PNG file, 256x240 or 256x224 without the overscan lines
+    pub fn save_screenshot(&self, file_name: &str, crop_overscan: bool) -> std::io::Result<()> {
+        self.cpu.bus.ppu.save_screenshot(file_name, crop_overscan)
+    }
+
+    /// Open the PPU viewer windows : nametables, pattern tables, OAM and palette
+    pub fn open_ppu_viewer(&mut self) {
+        self.ppu_viewer = Some(PpuViewer::new(&self.sdl_context.borrow()));
+    }
+
+    /// Save the PPU views as PNG files and the OAM sprite list as text into a directory
+    pub fn dump_ppu_views(&self, directory: &str, pattern_palette: u8) -> std::io::Result<()> {
+        ppu_viewer::dump_views(&self.cpu.bus.ppu, directory, pattern_palette)
+    }
+
+    /// Record PPU register writes, OAM DMA, mapper writes and interrupts with their scanline and dot
+    pub fn enable_event_log(&mut self) {
+        self.cpu.bus.ppu.enable_event_log();
+    }
+
+    /// Draw the logged events over the frame, one colored marker per event
+    pub fn set_event_overlay(&mut self, is_enabled: bool) {
+        self.cpu.bus.ppu.set_event_overlay(is_enabled);
+    }
+
+    /// Get the events of the last complete frame, empty until the event log is enabled
+    pub fn get_frame_events(&self) -> &[PpuEvent] {
+        self.cpu.bus.ppu.get_frame_events()
+    }
+
+    /// Save the events of the last complete frame as CSV : frame, scanline, dot, event, address and value
+    pub fn save_event_log(&self, file_name: &str) -> std::io::Result<()> {
+        std::fs::write(file_name, events_to_csv(self.get_frame_events()))
+    }
+
+    /// Record the audio output to WAV files : the final mix, or one file per channel named `<stem>_<channel>.wav`.
+    /// Recording runs at the emulation speed, so headless runs record faster than real time
+    pub fn start_audio_recording(
+        &mut self,
+        file_name: &str,
+        mode: RecordMode,
+    ) -> std::io::Result<()> {
+        self.audio_record_mode = mode;
+        self.cpu.bus.apu.start_recording(file_name, mode)
+    }
+
+    /// Stop the audio recording and complete the WAV files
+    pub fn stop_audio_recording(&mut self) -> std::io::Result<()> {
+        self.cpu.bus.apu.stop_recording()
+    }
+
+    /// Return true while audio is recorded
+    pub fn is_audio_recording(&self) -> bool {
+        self.cpu.bus.apu.is_recording()
+    }
+
+    /// Select what the recording hotkey records
+    pub fn set_audio_record_mode(&mut self, mode: RecordMode) {
+        self.audio_record_mode = mode;
+    }
+
+    /// Mute an APU channel. Unlike the $4015 enable flags, this does not change what the game sees
+    pub fn set_channel_muted(&mut self, channel: Channel, is_muted: bool) {
+        info!("{:?} muted : {}", channel, is_muted);
+        self.cpu.bus.apu.set_channel_muted(channel, is_muted);
+    }
+
+    /// Return true if an APU channel is muted
+    pub fn is_channel_muted(&self, channel: Channel) -> bool {
+        self.cpu.bus.apu.is_channel_muted(channel)
+    }
+
+    /// Solo an APU channel. While some channels are soloed, the other ones are silent
+    pub fn set_channel_soloed(&mut self, channel: Channel, is_soloed: bool) {
+        info!("{:?} soloed : {}", channel, is_soloed);
+        self.cpu.bus.apu.set_channel_soloed(channel, is_soloed);
+    }
+
+    /// Return true if an APU channel is soloed
+    pub fn is_channel_soloed(&self, channel: Channel) -> bool {
+        self.cpu.bus.apu.is_channel_soloed(channel)
+    }
+
+    /// Set the volume of an APU channel, from 0.0 to 1.0, before the non-linear mix
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        self.cpu.bus.apu.set_channel_volume(channel, volume);
+    }
+
+    /// Unmute, unsolo and restore the volume of every APU channel
+    pub fn reset_channel_controls(&mut self) {
+        info!("Channel controls reset");
+        self.cpu.bus.apu.reset_channel_controls();
+    }
+
+    /// Get the output level of each APU channel from 0.0 to 1.0, indexed like `Channel::ALL` : peak over the last
+    /// frame, after mute, solo and volume
+    pub fn get_channel_levels(&self) -> [f32; 5] {
+        self.cpu.bus.apu.get_channel_levels()
+    }
+
+    /// Crop the overscan lines from screenshots taken with the hotkey
+    pub fn set_overscan_crop(&mut self, is_cropped: bool) {
+        self.is_overscan_cropped = is_cropped;
+    }
+
+    /// Get the last complete 256x240 frame, as 9 bits values : emphasis bits then 6 bits palette index
+    pub fn get_frame_buffer(&self) -> &[u16] {
+        self.cpu.bus.ppu.get_frame()
+    }
+
+    /// Get the last complete 256x240 frame as RGB24 pixels, with the current palette
+    pub fn get_frame_rgb(&self) -> Vec<u8> {
+        self.cpu.bus.ppu.get_frame_rgb()
+    }
+
     /// Toggles pause on the emulator execution
     pub fn toggle_pause(&mut self) {
         self.pause = !self.pause;
     }
 
+    /// Activate the trace logger with the given configuration
+    pub fn set_tracer(&mut self, config: TraceConfig) {
+        self.tracer = Some(tracer::Tracer::new(config));
+    }
+
+    /// Activate the interactive debugger. The execution starts paused
+    pub fn set_debug_mode(&mut self) {
+        self.cpu.bus.enable_access_log();
+        self.debugger = Some(Debugger::new(debugger::read_stdin_commands()));
+        println!("Debugger started, type h for help");
+        self.pause = true;
+    }
+
+    /// Start a GDB remote serial protocol server on a local TCP port
+    pub fn set_gdb_stub(&mut self, port: u16) {
+        self.cpu.bus.enable_access_log();
+        self.gdb_stub = Some(GdbStub::new(port));
+    }
+
+    /// Add an execution breakpoint, optionally restricted to a PRG bank
+    pub fn add_breakpoint(&mut self, address: u16, bank: Option<usize>) {
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.add_breakpoint(Breakpoint { address, bank });
+        }
+    }
+
+    /// Set the console region, overriding the one found in the ROM header
+    pub fn set_region(&mut self, region: Region) {
+        info!("Region : {:?}", region);
+        self.region = region;
+        self.master_clock_remainder = 0;
+        self.clock.set_target_framerate(region.get_frame_rate());
+        self.cpu.bus.ppu.set_region(region);
+        self.cpu.bus.apu.set_region(region);
+    }
+
+    /// Set the palette : 2c02, composite, 2c03, 2c05 or a 192 or 1536 bytes .pal file
+    pub fn set_palette(&mut self, name: &str) -> Result<(), String> {
+        self.cpu.bus.ppu.set_palette(name)
+    }
+
+    /// Enable the NTSC video filter with a preset, or disable it
+    pub fn set_ntsc_filter(&mut self, preset: Option<NtscPreset>) {
+        self.cpu.bus.ppu.set_ntsc_filter(preset);
+    }
+
+    /// Select the SDL audio driver, such as "pulseaudio", "alsa" or "dummy", instead of the SDL default one.
+    /// Must be called before start, the SDL_AUDIODRIVER environment variable works too
+    pub fn set_audio_driver(&mut self, audio_driver: &str) {
+        self.cpu.bus.apu.set_audio_driver(audio_driver);
+    }
+
+    /// Set the audio output volume, from 0.0 to 1.0
+    pub fn set_volume(&mut self, volume: f32) {
+        self.cpu.bus.apu.set_volume(volume);
+    }
+
+    /// Set the audio sample rate requested to the sound device, 44100 or 48000 Hz usually.
+    /// Rates out of 8000 to 192000 Hz are rejected
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), String> {
+        self.cpu.bus.apu.set_sample_rate(sample_rate)
+    }
+
+    /// Select the frame pacing : audio (default) follows the sound device, video sleeps to the region frame rate.
+    /// Audio sync falls back to video sync without sound device
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.clock.set_sync_mode(sync_mode);
+    }
+
+    /// Look the ROM up in a ROM database file, and apply the settings found
+    pub fn load_rom_database(&mut self, file_name: &str) -> Result<(), String> {
+        let mut database = RomDatabase::new();
+        database.load(file_name)?;
+        let crc32 = self._cartridge.borrow().get_crc32();
+        match database.find(crc32) {
+            Some(entry) => {
+                info!("ROM found in database : {}", entry.name);
+                if let Some(region) = entry.region {
+                    self.set_region(region);
+                }
+                if let Some(palette) = entry.palette.as_deref() {
+                    if let Err(error) = self.set_palette(palette) {
+                        warn!("ROM database palette ignored : {}", error);
+                    }
+                }
+            }
+            None => info!("ROM {:08x} not found in database", crc32),
+        }
+        Ok(())
+    }
+
+    /// Enable or lift the 8 sprites per line limit
+    pub fn set_sprite_limit(&mut self, is_enabled: bool) {
+        self.cpu.bus.ppu.set_sprite_limit(is_enabled);
+    }
+
     /// Activate test mode and set the execution reference file
     pub fn set_test_mode(&mut self, file_name: &str) {
         self.is_test_mode = true;
@@ -284,38 +728,15 @@ impl NesEmulator<'_> {
         let current_line = self.lines[self.line_index].clone();
         self.line_index += 1;
 
-        let opcode = self.cpu.bus.read_rom(cpu_status.program_counter);
-        let mut opcode_arg_1 = "  ".to_string();
-        let mut opcode_arg_2 = "  ".to_string();
-        if OPCODES[&opcode].len > 1 {
-            opcode_arg_1 = format!(
-                "{:02x}",
-                self.cpu.bus.read_rom(cpu_status.program_counter + 1)
-            );
-        }
-        if OPCODES[&opcode].len > 2 {
-            opcode_arg_2 = format!(
-                "{:02x}",
-                self.cpu.bus.read_rom(cpu_status.program_counter + 2)
-            );
-        }
-
         let log_status = LogFileLine::new(current_line.as_str());
         info!("{}", current_line);
-        info!("{:x}  {:02x} {} {}  {:30}  A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{},{} CYC:{}",
-            cpu_status.program_counter,
-            opcode,
-            opcode_arg_1,
-            opcode_arg_2,
-            INSTRUCTION_TABLE[opcode as usize].get_syntax(&mut self.cpu),
-            cpu_status.accumulator,
-            cpu_status.x_register,
-            cpu_status.y_register,
-            cpu_status.status_register,
-            cpu_status.stack_pointer,
-            ppu_status.line,
-            ppu_status.col,
-            cpu_status.total_cycles,
+        info!(
+            "{}",
+            tracer::format_line(
+                TraceFormat::Nintendulator,
+                &self.cpu,
+                self.clock.get_clock_count()
+            )
         );
 
         assert_eq!(cpu_status.program_counter, log_status.program_counter);
@@ -367,3 +788,36 @@ impl LogFileLine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{lock_sdl, write_test_rom};
+
+    #[test]
+    fn rom_database_region() {
+        let _sdl_lock = lock_sdl();
+        let mut emulator = NesEmulator::new_headless(write_test_rom("rom_database", &[]));
+        assert_eq!(emulator.region, Region::Ntsc);
+
+        let crc32 = emulator._cartridge.borrow().get_crc32();
+        let database =
+            std::env::temp_dir().join(format!("rom_database-{}.yaml", std::process::id()));
+        std::fs::write(
+            &database,
+            format!(
+                "- crc32: 0x{:08x}\n  name: Test ROM\n  region: pal\n  palette: missing.pal\n",
+                crc32
+            ),
+        )
+        .unwrap();
+        // An invalid palette is skipped, the region still applies
+        assert!(emulator
+            .load_rom_database(database.to_str().unwrap())
+            .is_ok());
+        assert_eq!(emulator.region, Region::Pal);
+        assert!(emulator
+            .load_rom_database("missing_rom_database.yaml")
+            .is_err());
+    }
+}
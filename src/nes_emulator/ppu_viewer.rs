@@ -0,0 +1,113 @@
+//! PPU viewer windows
+//! One window per PPU debug view, refreshed once per frame. Clicking shows what is under the mouse,
+//! right clicking the pattern tables selects the next palette
+
+use log::info;
+use sdl2::event::Event;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::surface::Surface;
+use sdl2::video::Window;
+
+use crate::ppu::{encode_png, Ppu, PpuView};
+
+/// Window size factor
+const SCALE: u32 = 2;
+
+/// Debug windows showing the PPU state
+pub struct PpuViewer {
+    windows: Vec<(PpuView, Canvas<Window>)>,
+    pattern_palette: u8,
+}
+
+impl PpuViewer {
+    /// Open the viewer windows
+    pub fn new(sdl_context: &sdl2::Sdl) -> PpuViewer {
+        let video_subsystem = sdl_context.video().unwrap();
+        let windows = PpuView::ALL
+            .iter()
+            .map(|view| {
+                let (width, height) = view.get_size();
+                let window = video_subsystem
+                    .window(view.get_name(), SCALE * width, SCALE * height)
+                    .build()
+                    .unwrap();
+                (*view, window.into_canvas().build().unwrap())
+            })
+            .collect();
+        PpuViewer {
+            windows,
+            pattern_palette: 0,
+        }
+    }
+
+    /// Refresh every window from the PPU state
+    pub fn update(&mut self, ppu: &Ppu) {
+        for (view, canvas) in self.windows.iter_mut() {
+            let (width, height) = view.get_size();
+            let mut pixels = ppu.render_view(*view, self.pattern_palette);
+            let surface = Surface::from_data(
+                &mut pixels,
+                width,
+                height,
+                3 * width,
+                PixelFormatEnum::RGB24,
+            )
+            .unwrap();
+            let creator = canvas.texture_creator();
+            let texture = surface.as_texture(&creator).unwrap();
+            canvas.clear();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+        }
+    }
+
+    /// Get the palette used to draw the pattern tables
+    pub fn get_pattern_palette(&self) -> u8 {
+        self.pattern_palette
+    }
+
+    /// Handle mouse clicks in the viewer windows. Returns true if the event was for a viewer window
+    pub fn handle_event(&mut self, event: &Event, ppu: &Ppu) -> bool {
+        let Event::MouseButtonDown {
+            window_id,
+            mouse_btn,
+            x,
+            y,
+            ..
+        } = event
+        else {
+            return false;
+        };
+        let Some((view, _)) = self
+            .windows
+            .iter()
+            .find(|(_, canvas)| canvas.window().id() == *window_id)
+        else {
+            return false;
+        };
+        if *view == PpuView::PatternTables && *mouse_btn == MouseButton::Right {
+            self.pattern_palette = (self.pattern_palette + 1) % 8;
+            info!("Pattern tables palette {}", self.pattern_palette);
+        } else if *view == PpuView::Oam && *mouse_btn == MouseButton::Right {
+            info!("OAM :\n{}", ppu.get_oam_as_string());
+        } else if let Some(description) =
+            ppu.describe_view_position(*view, *x as u32 / SCALE, *y as u32 / SCALE)
+        {
+            info!("{}", description);
+        }
+        true
+    }
+}
+
+/// Save every PPU view as a PNG file in a directory, and the OAM sprite list as text
+pub fn dump_views(ppu: &Ppu, directory: &str, pattern_palette: u8) -> std::io::Result<()> {
+    std::fs::create_dir_all(directory)?;
+    for view in PpuView::ALL {
+        let (width, height) = view.get_size();
+        let png = encode_png(width, height, &ppu.render_view(view, pattern_palette));
+        std::fs::write(format!("{}/{}.png", directory, view.get_name()), png)?;
+    }
+    std::fs::write(format!("{}/oam.txt", directory), ppu.get_oam_as_string())
+}
@@ -0,0 +1,350 @@
+//! Screenshot regression harness
+//! Boots each ROM of a manifest headlessly, plays a scripted input sequence for a number of frames,
+//! then compares a hash of the framebuffer against a golden value
+//!
+//! The manifest is a YAML list :
+//! ```yaml
+//! - name: game_title_screen
+//!   rom: roms/game.nes
+//!   frames: 120
+//!   input:                # Buttons are held from the given frame until the next entry
+//!     - frame: 60
+//!       buttons: [start]
+//!     - frame: 62
+//!       buttons: []
+//!   hash: 0123456789abcdef  # A case without hash fails until recorded
+//! ```
+//! Golden frames are kept in the `golden` directory next to the manifest, to draw diff images on failure.
+//! Setting `NES_REGRESSION_UPDATE=1` records the golden frames and writes the hashes into the manifest.
+
+use log::info;
+use std::path::Path;
+use yaml_rust::{Yaml, YamlLoader};
+
+use super::NesEmulator;
+use crate::ppu::encode_png;
+
+const FRAME_WIDTH: u32 = 256;
+const FRAME_HEIGHT: u32 = 240;
+/// Environment variable enabling golden values recording
+const UPDATE_VARIABLE: &str = "NES_REGRESSION_UPDATE";
+
+/// One ROM run of the manifest
+pub struct RegressionCase {
+    pub name: String,
+    pub rom: String,
+    pub frames: u128,
+    pub input: Vec<(u128, u8)>, // Frame and controller 1 buttons
+    pub hash: Option<u64>,
+}
+
+/// Outcome of a regression case
+pub struct RegressionReport {
+    pub name: String,
+    pub hash: u64,
+    pub expected_hash: Option<u64>,
+    pub different_pixels: Option<usize>, // Known when a golden frame is available
+}
+
+impl RegressionReport {
+    /// Return true if the frame differs from the golden one, or if there is no golden hash
+    pub fn is_failure(&self) -> bool {
+        self.expected_hash != Some(self.hash)
+    }
+}
+
+/// Load the cases of a manifest file
+pub fn load_manifest(file_name: &str) -> Vec<RegressionCase> {
+    let content = std::fs::read_to_string(file_name).expect("Cannot open regression manifest");
+    parse_manifest(&content)
+}
+
+/// Parse the YAML content of a manifest
+fn parse_manifest(content: &str) -> Vec<RegressionCase> {
+    let documents = YamlLoader::load_from_str(content).expect("Invalid regression manifest");
+    let mut cases = vec![];
+    for entry in documents.iter().filter_map(Yaml::as_vec).flatten() {
+        let rom = entry["rom"].as_str().expect("Regression case without rom");
+        let name = entry["name"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                Path::new(rom)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or(rom)
+                    .to_string()
+            });
+        let input = entry["input"]
+            .as_vec()
+            .map(|input| {
+                input
+                    .iter()
+                    .map(|step| {
+                        let frame = step["frame"].as_i64().unwrap_or(0) as u128;
+                        let buttons: Vec<&str> = step["buttons"]
+                            .as_vec()
+                            .map(|buttons| buttons.iter().filter_map(Yaml::as_str).collect())
+                            .unwrap_or_default();
+                        (frame, parse_buttons(&buttons))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let hash = match &entry["hash"] {
+            Yaml::String(hash) => u64::from_str_radix(hash, 16).ok(),
+            Yaml::Integer(hash) => Some(*hash as u64),
+            _ => None,
+        };
+        cases.push(RegressionCase {
+            name,
+            rom: rom.to_string(),
+            frames: entry["frames"].as_i64().unwrap_or(60) as u128,
+            input,
+            hash,
+        });
+    }
+    cases
+}
+
+/// Convert button names to the controller bit field
+fn parse_buttons(buttons: &[&str]) -> u8 {
+    const NAMES: [&str; 8] = ["a", "b", "select", "start", "up", "down", "left", "right"];
+    buttons
+        .iter()
+        .filter_map(|button| {
+            NAMES
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(button))
+        })
+        .fold(0, |status, bit| status | (1 << bit))
+}
+
+/// FNV-1a hash of a frame of 9 bits pixel values
+pub fn hash_frame(frame: &[u16]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in frame.iter().flat_map(|pixel| pixel.to_le_bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Run every case of a manifest, saving images of failures into the output directory
+pub fn run_manifest(file_name: &str, output_dir: &str) -> Vec<RegressionReport> {
+    let golden_dir = Path::new(file_name)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("golden");
+    let is_update = std::env::var(UPDATE_VARIABLE).is_ok_and(|value| value == "1");
+    std::fs::create_dir_all(output_dir).expect("Cannot create regression output directory");
+    let reports: Vec<RegressionReport> = load_manifest(file_name)
+        .iter()
+        .map(|case| run_case(case, &golden_dir, Path::new(output_dir), is_update))
+        .collect();
+    if is_update {
+        let content = std::fs::read_to_string(file_name).expect("Cannot open regression manifest");
+        let hashes: Vec<u64> = reports.iter().map(|report| report.hash).collect();
+        std::fs::write(file_name, update_manifest(&content, &hashes))
+            .expect("Cannot write regression manifest");
+    }
+    reports
+}
+
+/// Write the hashes of the cases into the manifest content, replacing the previous ones.
+/// Comments and formatting are kept, the hash goes after the last key of each case
+fn update_manifest(content: &str, hashes: &[u64]) -> String {
+    // Lines before the first case, then the lines of each case
+    let mut entries: Vec<Vec<&str>> = vec![vec![]];
+    for line in content.lines() {
+        if line.starts_with("- ") {
+            entries.push(vec![]);
+        }
+        if !line.starts_with("  hash:") {
+            entries.last_mut().unwrap().push(line);
+        }
+    }
+
+    let mut output = String::new();
+    for (index, lines) in entries.iter().enumerate() {
+        let hash = index.checked_sub(1).and_then(|case| hashes.get(case));
+        // Trailing blank and comment lines stay after the hash
+        let end = match hash {
+            Some(_) => lines
+                .iter()
+                .rposition(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+                .map_or(0, |position| position + 1),
+            None => lines.len(),
+        };
+        for line in lines[..end].iter() {
+            output.push_str(line);
+            output.push('\n');
+        }
+        if let Some(hash) = hash {
+            output.push_str(&format!("  hash: {:016x}\n", hash));
+        }
+        for line in lines[end..].iter() {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// Run one case and compare its last frame to the golden one
+pub fn run_case(
+    case: &RegressionCase,
+    golden_dir: &Path,
+    output_dir: &Path,
+    is_update: bool,
+) -> RegressionReport {
+    let mut emulator = NesEmulator::new_headless(case.rom.clone());
+    for frame in 0..case.frames {
+        if let Some((_, buttons)) = case.input.iter().rev().find(|(start, _)| *start <= frame) {
+            emulator.set_controller_1(*buttons);
+        }
+        emulator.run_frames(1);
+    }
+    let frame = emulator.get_frame_buffer().to_vec();
+    let hash = hash_frame(&frame);
+    let golden_frame_file = golden_dir.join(format!("{}.frame", case.name));
+
+    let mut report = RegressionReport {
+        name: case.name.clone(),
+        hash,
+        expected_hash: case.hash,
+        different_pixels: None,
+    };
+    if is_update {
+        std::fs::create_dir_all(golden_dir).expect("Cannot create golden directory");
+        let bytes: Vec<u8> = frame.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+        std::fs::write(&golden_frame_file, bytes).expect("Cannot write golden frame");
+        save_image(&emulator, &golden_dir.join(format!("{}.png", case.name)));
+        println!("{} : hash {:016x} recorded", case.name, hash);
+        report.expected_hash = Some(hash);
+    }
+
+    if !report.is_failure() {
+        info!("{} : hash {:016x} OK", case.name, hash);
+        return report;
+    }
+    let Some(expected_hash) = case.hash else {
+        println!(
+            "{} : hash {:016x} without golden value, record it with {}=1",
+            case.name, hash, UPDATE_VARIABLE
+        );
+        return report;
+    };
+
+    save_image(
+        &emulator,
+        &output_dir.join(format!("{}_actual.png", case.name)),
+    );
+    if let Ok(bytes) = std::fs::read(&golden_frame_file) {
+        let golden_frame: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pixel| u16::from_le_bytes([pixel[0], pixel[1]]))
+            .collect();
+        let (diff, different_pixels) = draw_diff(&frame, &golden_frame, &emulator.get_frame_rgb());
+        let diff_file = output_dir.join(format!("{}_diff.png", case.name));
+        std::fs::write(diff_file, encode_png(FRAME_WIDTH, FRAME_HEIGHT, &diff))
+            .expect("Cannot write diff image");
+        report.different_pixels = Some(different_pixels);
+    }
+    println!(
+        "{} : hash {:016x} instead of {:016x}, {} different pixels, images in {}",
+        case.name,
+        hash,
+        expected_hash,
+        report
+            .different_pixels
+            .map_or("unknown".to_string(), |count| count.to_string()),
+        output_dir.display()
+    );
+    report
+}
+
+/// Save the last frame of an emulator
+fn save_image(emulator: &NesEmulator, file_name: &Path) {
+    emulator
+        .save_screenshot(&file_name.to_string_lossy(), false)
+        .expect("Cannot write regression image");
+}
+
+/// Draw the differences between two frames : differing pixels in red over the dimmed actual frame
+fn draw_diff(frame: &[u16], golden_frame: &[u16], rgb: &[u8]) -> (Vec<u8>, usize) {
+    let mut diff = Vec::with_capacity(rgb.len());
+    let mut different_pixels = 0;
+    for (index, pixel) in frame.iter().enumerate() {
+        if golden_frame.get(index) == Some(pixel) {
+            diff.extend(rgb[3 * index..3 * index + 3].iter().map(|value| value / 3));
+        } else {
+            diff.extend_from_slice(&[0xff, 0, 0]);
+            different_pixels += 1;
+        }
+    }
+    (diff, different_pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest() {
+        let cases = parse_manifest(
+            "- rom: roms/game.nes\n  frames: 10\n  input:\n    - frame: 2\n      buttons: [start, a]\n    - frame: 4\n      buttons: []\n  hash: 00000000000000ff\n\
+             - name: other\n  rom: other.nes\n",
+        );
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].name, "game");
+        assert_eq!(cases[0].frames, 10);
+        assert_eq!(cases[0].input, vec![(2, 0b1001), (4, 0)]);
+        assert_eq!(cases[0].hash, Some(0xff));
+        assert_eq!(cases[1].name, "other");
+        assert_eq!(cases[1].hash, None);
+    }
+
+    #[test]
+    fn missing_hash_fails() {
+        let mut report = RegressionReport {
+            name: String::from("case"),
+            hash: 0xff,
+            expected_hash: None,
+            different_pixels: None,
+        };
+        assert!(report.is_failure());
+        report.expected_hash = Some(0xfe);
+        assert!(report.is_failure());
+        report.expected_hash = Some(0xff);
+        assert!(!report.is_failure());
+    }
+
+    #[test]
+    fn manifest_update() {
+        let content = "# Header\n\
+                       - name: first\n  rom: a.nes\n  hash: 0000000000000001\n  frames: 5\n\n\
+                       # Second case\n\
+                       - rom: b.nes\n  input:\n    - frame: 2\n      buttons: [a]\n";
+        let updated = update_manifest(content, &[0xab, 0xcd]);
+        assert_eq!(
+            updated,
+            "# Header\n\
+             - name: first\n  rom: a.nes\n  frames: 5\n  hash: 00000000000000ab\n\n\
+             # Second case\n\
+             - rom: b.nes\n  input:\n    - frame: 2\n      buttons: [a]\n  hash: 00000000000000cd\n"
+        );
+        let cases = parse_manifest(&updated);
+        assert_eq!(cases[0].hash, Some(0xab));
+        assert_eq!(cases[1].hash, Some(0xcd));
+        assert_eq!(update_manifest(&updated, &[0xab, 0xcd]), updated);
+    }
+
+    #[test]
+    fn frame_diff() {
+        assert_ne!(hash_frame(&[0, 1]), hash_frame(&[1, 0]));
+        let (diff, different_pixels) = draw_diff(&[1, 2], &[1, 3], &[30, 60, 90, 30, 60, 90]);
+        assert_eq!(different_pixels, 1);
+        assert_eq!(diff, vec![10, 20, 30, 0xff, 0, 0]);
+    }
+}
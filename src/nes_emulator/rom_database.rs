@@ -0,0 +1,113 @@
+//! ROM database
+//! Per game settings, looked up by the CRC32 of PRG and CHR ROM
+//!
+//! The database is a YAML list :
+//! ```yaml
+//! - crc32: 0x1234abcd
+//!   name: Game title
+//!   region: pal
+//!   palette: smooth
+//! ```
+
+use log::{info, warn};
+use std::collections::HashMap;
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::region::Region;
+
+/// Known settings of a game
+pub struct RomEntry {
+    pub name: String,
+    pub region: Option<Region>,
+    /// Built-in palette name or .pal file
+    pub palette: Option<String>,
+}
+
+/// Game settings indexed by ROM CRC32
+pub struct RomDatabase {
+    entries: HashMap<u32, RomEntry>,
+}
+
+impl RomDatabase {
+    /// Instantiate an empty database
+    pub fn new() -> RomDatabase {
+        RomDatabase {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Load database entries from a YAML file
+    pub fn load(&mut self, file_name: &str) -> Result<(), String> {
+        info!("Loading ROM database from {}", file_name);
+        let content = std::fs::read_to_string(file_name)
+            .map_err(|error| format!("Cannot open ROM database {} : {}", file_name, error))?;
+        self.load_from_str(&content)
+            .map_err(|error| format!("Invalid ROM database {} : {}", file_name, error))
+    }
+
+    /// Load database entries from YAML content. Invalid entries are skipped
+    fn load_from_str(&mut self, content: &str) -> Result<(), String> {
+        let documents = YamlLoader::load_from_str(content).map_err(|error| error.to_string())?;
+        for entry in documents.iter().filter_map(Yaml::as_vec).flatten() {
+            let crc32 = match &entry["crc32"] {
+                Yaml::Integer(value) => Some(*value as u32),
+                Yaml::String(value) => u32::from_str_radix(value.trim_start_matches("0x"), 16).ok(),
+                _ => None,
+            };
+            let Some(crc32) = crc32 else {
+                warn!("ROM database entry without a valid crc32 skipped");
+                continue;
+            };
+            let region = entry["region"]
+                .as_str()
+                .and_then(|region| region.parse::<Region>().ok());
+            let name = entry["name"].as_str().unwrap_or_default().to_string();
+            let palette = entry["palette"].as_str().map(String::from);
+            self.entries.insert(
+                crc32,
+                RomEntry {
+                    name,
+                    region,
+                    palette,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Find the entry of a ROM
+    pub fn find(&self, crc32: u32) -> Option<&RomEntry> {
+        self.entries.get(&crc32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_entries() {
+        let mut database = RomDatabase::new();
+        database
+            .load_from_str(
+                "- crc32: 0x1234abcd\n  name: Test game\n  region: pal\n  palette: fbx\n\
+             - crc32: \"0000ffff\"\n  region: dendy\n\
+             - name: No crc\n",
+            )
+            .unwrap();
+        let entry = database.find(0x1234abcd).unwrap();
+        assert_eq!(entry.name, "Test game");
+        assert_eq!(entry.region, Some(Region::Pal));
+        assert_eq!(entry.palette.as_deref(), Some("fbx"));
+        assert_eq!(database.find(0xffff).unwrap().region, Some(Region::Dendy));
+        assert_eq!(database.find(0xffff).unwrap().palette, None);
+        assert!(database.find(0).is_none());
+    }
+
+    #[test]
+    fn load_errors() {
+        let mut database = RomDatabase::new();
+        assert!(database.load("missing_rom_database.yaml").is_err());
+        assert!(database.load_from_str("- crc32: [0x1234").is_err());
+    }
+}
@@ -0,0 +1,484 @@
+//! Execution trace logger
+//! Writes one line per executed instruction, in a format matching other emulators so traces can be diffed
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::cpu::instructions::{InstructionCode, InstructionMode, INSTRUCTION_TABLE};
+use crate::cpu::Cpu;
+use crate::disassembler;
+
+/// Supported trace line formats
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TraceFormat {
+    /// Nintendulator format, as used by nestest.log
+    Nintendulator,
+    /// FCEUX trace logger format
+    Fceux,
+    /// Mesen trace logger format
+    Mesen,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<TraceFormat, String> {
+        match value.to_lowercase().as_str() {
+            "nintendulator" | "nestest" => Ok(TraceFormat::Nintendulator),
+            "fceux" => Ok(TraceFormat::Fceux),
+            "mesen" => Ok(TraceFormat::Mesen),
+            _ => Err(format!("Unknown trace format : {}", value)),
+        }
+    }
+}
+
+/// Trace logger configuration
+///
+/// Tracing starts once every start condition is met and stops as soon as one stop condition is met
+pub struct TraceConfig {
+    pub file_name: String,
+    pub format: TraceFormat,
+    pub start_frame: Option<u128>,
+    pub stop_frame: Option<u128>,
+    pub start_pc: Option<u16>,
+    pub stop_pc: Option<u16>,
+    pub start_cycle: Option<u32>,
+    pub stop_cycle: Option<u32>,
+    pub with_ppu_registers: bool,
+    pub with_apu_registers: bool,
+}
+
+impl TraceConfig {
+    /// Instantiate a configuration tracing the whole execution in the given format
+    pub fn new(file_name: String, format: TraceFormat) -> TraceConfig {
+        TraceConfig {
+            file_name,
+            format,
+            start_frame: None,
+            stop_frame: None,
+            start_pc: None,
+            stop_pc: None,
+            start_cycle: None,
+            stop_cycle: None,
+            with_ppu_registers: false,
+            with_apu_registers: false,
+        }
+    }
+}
+
+/// Trace logger component
+pub struct Tracer {
+    config: TraceConfig,
+    writer: BufWriter<File>,
+    is_started: bool,
+    is_stopped: bool,
+}
+
+impl Tracer {
+    /// Instantiate the tracer and create the output file
+    pub fn new(config: TraceConfig) -> Tracer {
+        let writer =
+            BufWriter::new(File::create(&config.file_name).expect("Cannot create trace file"));
+        Tracer {
+            config,
+            writer,
+            is_started: false,
+            is_stopped: false,
+        }
+    }
+
+    /// Check if a stop condition has been met. A stopped tracer never restarts
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped
+    }
+
+    /// Write the trace line of the instruction about to be executed, if start and stop conditions allow it
+    pub fn trace(&mut self, cpu: &Cpu, frame: u128) {
+        if self.is_stopped {
+            return;
+        }
+        let cpu_status = cpu.get_status();
+        if self.is_stop_condition_met(cpu_status.program_counter, cpu_status.total_cycles, frame) {
+            self.is_stopped = true;
+            self.writer.flush().expect("Cannot write trace file");
+            return;
+        }
+        if !self.is_started {
+            self.is_started = self.is_start_condition_met(
+                cpu_status.program_counter,
+                cpu_status.total_cycles,
+                frame,
+            );
+            if !self.is_started {
+                return;
+            }
+        }
+
+        let line = format_line(self.config.format, cpu, frame);
+        self.writer
+            .write_all(line.as_bytes())
+            .expect("Cannot write trace file");
+        if self.config.with_ppu_registers {
+            let ppu_registers = cpu.bus.ppu.get_registers_as_string();
+            self.writer
+                .write_all(format!(" {}", ppu_registers).as_bytes())
+                .expect("Cannot write trace file");
+        }
+        if self.config.with_apu_registers {
+            let apu_registers = cpu.bus.apu.get_registers_as_string();
+            self.writer
+                .write_all(format!(" {}", apu_registers).as_bytes())
+                .expect("Cannot write trace file");
+        }
+        self.writer
+            .write_all(b"\n")
+            .expect("Cannot write trace file");
+    }
+
+    /// All start conditions must be reached
+    fn is_start_condition_met(&self, program_counter: u16, cycle: u32, frame: u128) -> bool {
+        self.config.start_frame.is_none_or(|start| frame >= start)
+            && self.config.start_cycle.is_none_or(|start| cycle >= start)
+            && self
+                .config
+                .start_pc
+                .is_none_or(|start| program_counter == start)
+    }
+
+    /// Any stop condition is enough
+    fn is_stop_condition_met(&self, program_counter: u16, cycle: u32, frame: u128) -> bool {
+        self.config.stop_frame.is_some_and(|stop| frame >= stop)
+            || self.config.stop_cycle.is_some_and(|stop| cycle >= stop)
+            || (self.is_started && self.config.stop_pc == Some(program_counter))
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Build the trace line for the instruction at the current program counter
+pub fn format_line(format: TraceFormat, cpu: &Cpu, frame: u128) -> String {
+    let cpu_status = cpu.get_status();
+    let ppu_status = cpu.bus.ppu.get_status();
+    let program_counter = cpu_status.program_counter;
+    let bytes: Vec<u8> = (0..3)
+        .map(|i| cpu.bus.peek(program_counter.wrapping_add(i)))
+        .collect();
+    let instruction = disassembler::decode(&bytes, program_counter, None)
+        .expect("Three bytes always decode to an instruction");
+    let instruction_bytes: Vec<String> = instruction
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let prefix = if instruction.is_unofficial { "*" } else { " " };
+    let disassembly = format!(
+        "{}{}",
+        instruction.get_syntax(),
+        format_annotation(format, cpu, bytes[0])
+    );
+
+    match format {
+        TraceFormat::Nintendulator => format!(
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            program_counter,
+            instruction_bytes.join(" "),
+            prefix,
+            disassembly,
+            cpu_status.accumulator,
+            cpu_status.x_register,
+            cpu_status.y_register,
+            cpu_status.status_register,
+            cpu_status.stack_pointer,
+            ppu_status.line,
+            ppu_status.col,
+            cpu_status.total_cycles,
+        ),
+        TraceFormat::Fceux => format!(
+            "f{:<6} c{:<10} A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}:{:<8} {}{}",
+            frame,
+            cpu_status.total_cycles,
+            cpu_status.accumulator,
+            cpu_status.x_register,
+            cpu_status.y_register,
+            cpu_status.stack_pointer,
+            format_flags(cpu_status.status_register),
+            program_counter,
+            instruction_bytes.join(" "),
+            prefix,
+            disassembly,
+        ),
+        TraceFormat::Mesen => format!(
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{} V:{:<3} H:{:<3} Fr:{} Cyc:{}",
+            program_counter,
+            instruction_bytes.join(" "),
+            prefix,
+            disassembly,
+            cpu_status.accumulator,
+            cpu_status.x_register,
+            cpu_status.y_register,
+            cpu_status.stack_pointer,
+            format_flags(cpu_status.status_register),
+            ppu_status.line,
+            ppu_status.col,
+            frame,
+            cpu_status.total_cycles,
+        ),
+    }
+}
+
+/// Render the status register as NV-BDIZC letters, uppercase when set
+fn format_flags(status_register: u8) -> String {
+    "nvubdizc"
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| {
+            if (status_register >> (7 - i)) & 1 != 0 {
+                flag.to_ascii_uppercase()
+            } else {
+                flag
+            }
+        })
+        .collect()
+}
+
+/// Format the value read at an address
+fn format_value(format: TraceFormat, value: u8) -> String {
+    match format {
+        TraceFormat::Nintendulator => format!(" = {:02X}", value),
+        TraceFormat::Fceux => format!(" = #${:02X}", value),
+        TraceFormat::Mesen => format!(" = ${:02X}", value),
+    }
+}
+
+/// Format the effective address of an indexed or indirect operand
+fn format_effective_address(format: TraceFormat, address: u16, is_zero_page: bool) -> String {
+    match (format, is_zero_page) {
+        (TraceFormat::Nintendulator, true) => format!(" @ {:02X}", address),
+        (TraceFormat::Nintendulator, false) => format!(" @ {:04X}", address),
+        (TraceFormat::Fceux, _) => format!(" @ ${:04X}", address),
+        (TraceFormat::Mesen, _) => format!(" [${:04X}]", address),
+    }
+}
+
+/// Resolve the operand of the instruction at the current program counter against the CPU state
+///
+/// Returns the effective address and memory value annotations appended to the disassembly
+fn format_annotation(format: TraceFormat, cpu: &Cpu, opcode: u8) -> String {
+    let instruction = &INSTRUCTION_TABLE[opcode as usize];
+    let cpu_status = cpu.get_status();
+    let program_counter = cpu_status.program_counter;
+    let byte_1 = cpu.bus.peek(program_counter.wrapping_add(1));
+    let byte_2 = cpu.bus.peek(program_counter.wrapping_add(2));
+    let word = byte_1 as u16 | ((byte_2 as u16) << 8);
+
+    match instruction.mode {
+        InstructionMode::ZeroPage => {
+            let value = cpu.bus.peek(byte_1 as u16);
+            format_value(format, value)
+        }
+        InstructionMode::ZeroPageX | InstructionMode::ZeroPageY => {
+            let index = if instruction.mode == InstructionMode::ZeroPageX {
+                cpu_status.x_register
+            } else {
+                cpu_status.y_register
+            };
+            let address = byte_1.wrapping_add(index) as u16;
+            let value = cpu.bus.peek(address);
+            format!(
+                "{}{}",
+                format_effective_address(format, address, true),
+                format_value(format, value)
+            )
+        }
+        InstructionMode::Absolute => {
+            if instruction.name == InstructionCode::JMP || instruction.name == InstructionCode::JSR
+            {
+                String::new()
+            } else {
+                let value = cpu.bus.peek(word);
+                format_value(format, value)
+            }
+        }
+        InstructionMode::AbsoluteX | InstructionMode::AbsoluteY => {
+            let index = if instruction.mode == InstructionMode::AbsoluteX {
+                cpu_status.x_register
+            } else {
+                cpu_status.y_register
+            };
+            let address = word.wrapping_add(index as u16);
+            let value = cpu.bus.peek(address);
+            format!(
+                "{}{}",
+                format_effective_address(format, address, false),
+                format_value(format, value)
+            )
+        }
+        InstructionMode::Indirect => {
+            // JMP indirect never crosses page when reading the target address
+            let low = cpu.bus.peek(word) as u16;
+            let high =
+                cpu.bus
+                    .peek((word & 0xff00) | (word.wrapping_add(1) & 0xff)) as u16;
+            let target = low | (high << 8);
+            match format {
+                TraceFormat::Nintendulator => format!(" = {:04X}", target),
+                TraceFormat::Fceux | TraceFormat::Mesen => format!(" = ${:04X}", target),
+            }
+        }
+        InstructionMode::IndirectX => {
+            let pointer = byte_1.wrapping_add(cpu_status.x_register);
+            let low = cpu.bus.peek(pointer as u16) as u16;
+            let high = cpu.bus.peek(pointer.wrapping_add(1) as u16) as u16;
+            let address = low | (high << 8);
+            let value = cpu.bus.peek(address);
+            match format {
+                TraceFormat::Nintendulator => format!(
+                    " @ {:02X} = {:04X}{}",
+                    pointer,
+                    address,
+                    format_value(format, value)
+                ),
+                TraceFormat::Fceux | TraceFormat::Mesen => format!(
+                    "{}{}",
+                    format_effective_address(format, address, false),
+                    format_value(format, value)
+                ),
+            }
+        }
+        InstructionMode::IndirectY => {
+            let low = cpu.bus.peek(byte_1 as u16) as u16;
+            let high = cpu.bus.peek(byte_1.wrapping_add(1) as u16) as u16;
+            let base = low | (high << 8);
+            let address = base.wrapping_add(cpu_status.y_register as u16);
+            let value = cpu.bus.peek(address);
+            match format {
+                TraceFormat::Nintendulator => format!(
+                    " = {:04X} @ {:04X}{}",
+                    base,
+                    address,
+                    format_value(format, value)
+                ),
+                TraceFormat::Fceux | TraceFormat::Mesen => format!(
+                    "{}{}",
+                    format_effective_address(format, address, false),
+                    format_value(format, value)
+                ),
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes_emulator::NesEmulator;
+    use crate::test_utils::{lock_sdl, write_test_rom};
+
+    /// Address of the idle loop written after the nestest instructions
+    const LOOP_ADDRESS: u16 = 0xc78a;
+
+    /// First lines of nestest.log, covering its first subroutine
+    fn nestest_lines() -> Vec<String> {
+        std::fs::read_to_string("tests/nestest/nestest.log")
+            .expect("Cannot open nestest.log")
+            .lines()
+            .take_while(|line| !line.starts_with(&format!("{:04X}", LOOP_ADDRESS)))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Test ROM with the instructions of the nestest.log lines at their address, then an idle loop
+    fn write_nestest_rom() -> String {
+        let mut code = vec![0xea; 0x3ffa];
+        for line in nestest_lines() {
+            let address = u16::from_str_radix(&line[..4], 16).unwrap() as usize - 0xc000;
+            for (index, byte) in line[6..14].split_whitespace().enumerate() {
+                code[address + index] = u8::from_str_radix(byte, 16).unwrap();
+            }
+        }
+        let loop_offset = (LOOP_ADDRESS - 0xc000) as usize;
+        code[loop_offset..loop_offset + 3].copy_from_slice(&[0x4c, 0x8a, 0xc7]); // JMP LOOP_ADDRESS
+        write_test_rom("nestest", &code)
+    }
+
+    /// Run the nestest ROM for some frames with a tracer, returning the trace lines
+    fn run_trace(frames: u128, set_config: impl Fn(&mut TraceConfig)) -> Vec<String> {
+        let _sdl_lock = lock_sdl();
+        let rom_file = write_nestest_rom();
+        let file_name = format!("{}.trace", rom_file);
+        let mut config = TraceConfig::new(file_name.clone(), TraceFormat::Nintendulator);
+        set_config(&mut config);
+        {
+            let mut emulator = NesEmulator::new_headless(rom_file);
+            emulator.set_tracer(config);
+            emulator.run_frames(frames);
+        }
+        let trace = std::fs::read_to_string(&file_name).expect("Cannot read trace file");
+        trace.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn nintendulator_format() {
+        let lines = nestest_lines();
+        let trace = run_trace(1, |_| ());
+        assert_eq!(trace[..lines.len()], lines[..]);
+    }
+
+    #[test]
+    fn fceux_and_mesen_formats() {
+        let trace = run_trace(1, |config| {
+            config.format = TraceFormat::Fceux;
+            config.stop_pc = Some(0xc5f9);
+        });
+        assert_eq!(
+            trace[2],
+            "f0      c12         A:00 X:00 Y:00 S:FD P:nvUbdIZc  $C5F7:86 00     STX $00 = #$00"
+        );
+
+        let trace = run_trace(1, |config| {
+            config.format = TraceFormat::Mesen;
+            config.stop_pc = Some(0xc72f);
+        });
+        assert_eq!(
+            trace[5],
+            "C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 S:FD P:nvUbdIZc V:0   H:63  Fr:0 Cyc:21"
+        );
+    }
+
+    #[test]
+    fn start_and_stop_conditions() {
+        let lines = nestest_lines();
+
+        // Program counter : from the first start address, until the stop address is reached again
+        let trace = run_trace(1, |config| {
+            config.start_pc = Some(0xc72d);
+            config.stop_pc = Some(0xc740);
+        });
+        assert_eq!(trace[..], lines[6..13]);
+
+        // Cycle : start included, stop excluded
+        let trace = run_trace(1, |config| {
+            config.start_cycle = Some(10);
+            config.stop_cycle = Some(21);
+        });
+        assert_eq!(trace[..], lines[1..5]);
+
+        // Frame : only the idle loop of the second frame
+        let trace = run_trace(3, |config| {
+            config.format = TraceFormat::Mesen;
+            config.start_frame = Some(1);
+            config.stop_frame = Some(2);
+        });
+        assert!(!trace.is_empty());
+        assert!(trace
+            .iter()
+            .all(|line| line.starts_with("C78A  4C 8A C7  JMP $C78A") && line.contains(" Fr:1 ")));
+    }
+}
@@ -0,0 +1,124 @@
+//! PPU event log
+//! Register writes, OAM DMA, mapper writes and interrupts of a frame, stamped with the scanline and dot they
+//! happened on, to debug raster effects like split scrolling and scanline IRQs
+
+use super::Status;
+use sdl2::pixels::Color;
+
+/// Kind of a logged event
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PpuEventKind {
+    /// CPU write to a PPU register, $2000 to $2007
+    RegisterWrite(u16, u8),
+    /// OAM DMA through $4014, with the source page
+    OamDma(u8),
+    /// CPU write to the cartridge mapper registers
+    MapperWrite(u16, u8),
+    /// NMI raised by the PPU
+    Nmi,
+    /// IRQ raised by the APU or the mapper
+    Irq,
+}
+
+/// Event with the PPU position it happened at
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct PpuEvent {
+    pub frame: u64,
+    pub status: Status,
+    pub kind: PpuEventKind,
+}
+
+impl PpuEventKind {
+    /// Get the event name, as used in the CSV export
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            PpuEventKind::RegisterWrite(address, _) => match address {
+                0x2000 => "PPUCTRL",
+                0x2001 => "PPUMASK",
+                0x2002 => "PPUSTATUS",
+                0x2003 => "OAMADDR",
+                0x2004 => "OAMDATA",
+                0x2005 => "PPUSCROLL",
+                0x2006 => "PPUADDR",
+                _ => "PPUDATA",
+            },
+            PpuEventKind::OamDma(_) => "OAMDMA",
+            PpuEventKind::MapperWrite(_, _) => "MAPPER",
+            PpuEventKind::Nmi => "NMI",
+            PpuEventKind::Irq => "IRQ",
+        }
+    }
+
+    /// Get the color of the event marker in the overlay
+    pub fn get_color(&self) -> Color {
+        match self {
+            PpuEventKind::RegisterWrite(0x2000, _) => Color::RGB(255, 0, 0),
+            PpuEventKind::RegisterWrite(0x2001, _) => Color::RGB(255, 255, 0),
+            PpuEventKind::RegisterWrite(0x2005, _) => Color::RGB(0, 255, 0),
+            PpuEventKind::RegisterWrite(0x2006, _) => Color::RGB(0, 128, 255),
+            PpuEventKind::RegisterWrite(0x2007, _) => Color::RGB(0, 255, 255),
+            PpuEventKind::RegisterWrite(_, _) => Color::RGB(255, 128, 0),
+            PpuEventKind::OamDma(_) => Color::RGB(255, 255, 255),
+            PpuEventKind::MapperWrite(_, _) => Color::RGB(255, 0, 255),
+            PpuEventKind::Nmi => Color::RGB(128, 128, 128),
+            PpuEventKind::Irq => Color::RGB(128, 255, 128),
+        }
+    }
+
+    /// Get the address and value of a write
+    fn get_write(&self) -> Option<(u16, u8)> {
+        match *self {
+            PpuEventKind::RegisterWrite(address, value)
+            | PpuEventKind::MapperWrite(address, value) => Some((address, value)),
+            PpuEventKind::OamDma(page) => Some((0x4014, page)),
+            PpuEventKind::Nmi | PpuEventKind::Irq => None,
+        }
+    }
+}
+
+/// Export events as CSV, one event per line
+pub fn events_to_csv(events: &[PpuEvent]) -> String {
+    let mut csv = String::from("frame,scanline,dot,event,address,value\n");
+    for event in events {
+        let (address, value) = match event.kind.get_write() {
+            Some((address, value)) => (format!("{:04x}", address), format!("{:02x}", value)),
+            None => (String::new(), String::new()),
+        };
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            event.frame,
+            event.status.line,
+            event.status.col,
+            event.kind.get_name(),
+            address,
+            value
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_export() {
+        let event = |line, col, kind| PpuEvent {
+            frame: 3,
+            status: Status { col, line },
+            kind,
+        };
+        let csv = events_to_csv(&[
+            event(31, 260, PpuEventKind::RegisterWrite(0x2005, 0x80)),
+            event(241, 1, PpuEventKind::Nmi),
+            event(241, 20, PpuEventKind::MapperWrite(0xc000, 0x1f)),
+        ]);
+        assert_eq!(
+            csv,
+            "frame,scanline,dot,event,address,value\n\
+             3,31,260,PPUSCROLL,2005,80\n\
+             3,241,1,NMI,,\n\
+             3,241,20,MAPPER,c000,1f\n"
+        );
+    }
+}
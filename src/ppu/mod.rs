@@ -1,18 +1,43 @@
 //! PPU Component of the NES
-//! NSTC implementation
+//! NTSC 2C02 implementation, with PAL and Dendy frame timings
+mod event_log;
+mod ntsc_filter;
+mod palette;
+mod png;
 mod screen;
+mod viewer;
 use crate::bus::interrupt::Interrupt;
+use crate::bus::memory::MemoryAccess;
 use crate::cartridge::Cartridge;
+use crate::region::Region;
 use log::info;
+use sdl2::pixels::Color;
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
+pub use event_log::{events_to_csv, PpuEvent, PpuEventKind};
+pub use ntsc_filter::NtscPreset;
+pub use png::encode_png;
+pub use viewer::PpuView;
+
+/// Number of frames before an open bus bit decays to 0, about 600 ms
+const OPEN_BUS_DECAY_FRAMES: u64 = 36;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub struct Status {
     pub col: u16,
     pub line: u16,
 }
 
+/// Sprite evaluation state, see nesdev PPU_sprite_evaluation
+#[derive(PartialEq, Clone, Copy)]
+enum SpriteEvaluation {
+    Copy,     // Copy in range sprites to secondary OAM
+    Overflow, // 8 sprites found, look for a 9th one with the hardware bug
+    Done,     // All sprites evaluated
+}
+
 pub struct Ppu<'a> {
     screen: screen::Screen<'a>,
     interrupt_bus: Rc<RefCell<Interrupt>>,
@@ -26,8 +51,12 @@ pub struct Ppu<'a> {
 
     // Sprite registers
     primary_oam: [u8; 0x100],
-    secondary_oam: [u8; 0x40],
-    sprite_count: u8,
+    secondary_oam: [u8; 0x100],
+    sprite_count: u8,      // n, index of the evaluated sprite in primary OAM
+    sprite_byte_index: u8, // m, index of the evaluated byte in the sprite
+    sprite_evaluation: SpriteEvaluation,
+    is_sprite_limit_enabled: bool,
+    is_sprite_0_evaluated: bool,
     sprite_fetcher_count: usize,
     secondary_oam_pointer: usize,
     is_first_sprite_0: bool,
@@ -36,6 +65,9 @@ pub struct Ppu<'a> {
     col: u16,
     line: u16,
     is_odd_frame: bool,
+    is_vblank_suppressed: bool, // PPUSTATUS read one dot before vblank, the flag is not set for this frame
+    frame_count: u64,
+    region: Region,
 
     // general registers
     ppuctrl: u8,
@@ -44,21 +76,37 @@ pub struct Ppu<'a> {
     oamaddr: u8,
     ppuscroll: u16,
     ppuaddr: u16,
-    ppudata: u8,
+    ppudata: u8,                       // $2007 read buffer
+    open_bus: u8,                      // Data bus latch, returned when reading write only registers
+    open_bus_refresh_frames: [u64; 8], // Frame where each bit of the latch was last set to 1
     vram: [u8; 0x2000],
     palette_vram: [u8; 0x20],
 
     // Pixel generator part
-    // Start with two empty tiles
-    bg_low_byte_table_register: VecDeque<u8>,
-    bg_high_byte_table_register: VecDeque<u8>,
-    bg_attribute_table_register: VecDeque<u8>,
-    bg_nt_table_register: VecDeque<u8>,
-
-    sprite_low_byte_table_register: VecDeque<u8>,
+    // Background latches, loaded into the shift registers every 8 cycles
+    nt_byte: u8,
+    at_byte: u8, // Palette of the fetched tile, 2 bits
+    low_bg_tile_byte: u8,
+    high_bg_tile_byte: u8,
+    // Background shift registers, high byte is the current tile and low byte the next one
+    bg_low_shift_register: u16,
+    bg_high_shift_register: u16,
+    bg_attribute_low_shift_register: u16,
+    bg_attribute_high_shift_register: u16,
+
+    sprite_low_byte_table_register: VecDeque<u8>, // Shifted once per pixel when the sprite is active
     sprite_high_byte_table_register: VecDeque<u8>,
     sprite_attribute_table_register: VecDeque<u8>,
-    sprite_x_coordinate_table_register: VecDeque<u8>,
+    sprite_x_counter_register: VecDeque<u8>, // Decremented once per pixel until the sprite is active
+
+    // Debugging
+    is_access_log_enabled: bool,
+    access_log: Vec<MemoryAccess>,
+    is_event_log_enabled: bool,
+    is_event_overlay_enabled: bool,
+    events: Vec<PpuEvent>,       // Events of the current frame
+    frame_events: Vec<PpuEvent>, // Events of the last complete frame
+    irq_count: u32,              // IRQ assertions already logged
 }
 
 impl Ppu<'_> {
@@ -81,8 +129,12 @@ impl Ppu<'_> {
 
             // Sprite registers
             primary_oam: [0; 0x100],
-            secondary_oam: [0; 0x40],
+            secondary_oam: [0; 0x100], // Only 0x20 bytes on hardware, more when sprite limit is lifted
             sprite_count: 0,
+            sprite_byte_index: 0,
+            sprite_evaluation: SpriteEvaluation::Copy,
+            is_sprite_limit_enabled: true,
+            is_sprite_0_evaluated: false,
             sprite_fetcher_count: 0,
             secondary_oam_pointer: 0,
             is_first_sprite_0: false,
@@ -91,6 +143,9 @@ impl Ppu<'_> {
             col: 0,
             line: 0,
             is_odd_frame: true,
+            is_vblank_suppressed: false,
+            frame_count: 0,
+            region: Region::Ntsc,
 
             // general registers
             ppuctrl: 0,
@@ -100,19 +155,33 @@ impl Ppu<'_> {
             ppuscroll: 0,
             ppuaddr: 0,
             ppudata: 0,
+            open_bus: 0,
+            open_bus_refresh_frames: [0; 8],
             vram: [0; 0x2000],
             palette_vram: [0; 0x20],
 
             // Pixel generator variables
-            bg_low_byte_table_register: VecDeque::from([0, 0]),
-            bg_high_byte_table_register: VecDeque::from([0, 0]),
-            bg_attribute_table_register: VecDeque::from([0, 0]),
-            bg_nt_table_register: VecDeque::from([0, 0]),
+            nt_byte: 0,
+            at_byte: 0,
+            low_bg_tile_byte: 0,
+            high_bg_tile_byte: 0,
+            bg_low_shift_register: 0,
+            bg_high_shift_register: 0,
+            bg_attribute_low_shift_register: 0,
+            bg_attribute_high_shift_register: 0,
 
             sprite_low_byte_table_register: VecDeque::new(),
             sprite_high_byte_table_register: VecDeque::new(),
             sprite_attribute_table_register: VecDeque::new(),
-            sprite_x_coordinate_table_register: VecDeque::new(),
+            sprite_x_counter_register: VecDeque::new(),
+
+            is_access_log_enabled: false,
+            access_log: vec![],
+            is_event_log_enabled: false,
+            is_event_overlay_enabled: false,
+            events: vec![],
+            frame_events: vec![],
+            irq_count: 0,
         }
     }
 
@@ -122,7 +191,7 @@ impl Ppu<'_> {
 
         for i in 1..25 {
             for j in 1..25 {
-                self.screen.update_pixel(110 + i, 105 + j, 5);
+                self.screen.update_pixel(110 + i, 105 + j, 0x05);
             }
         }
         self.screen.present();
@@ -135,33 +204,33 @@ impl Ppu<'_> {
         match self.line {
             0..=239u16 => {
                 if self.col > 0 && self.col < 257 {
-                    if self.is_bg_rendering_enabled() {
-                        let pixel_color = self.compute_next_pixel();
-                        self.screen.update_pixel(
-                            (self.col - 1) as u8,
-                            self.line as u8,
-                            pixel_color,
-                        );
-                    }
+                    let pixel_color = self.compute_next_pixel();
+                    self.screen
+                        .update_pixel((self.col - 1) as u8, self.line as u8, pixel_color);
+                    self.shift_sprite_registers();
                 }
                 // Nothing happens during Vblank
                 self.next_background_evaluation();
                 self.next_sprite_evaluation();
             }
-            241u16 => {
+            line if line == self.region.get_vblank_start_line() => {
                 if self.col == 1 {
+                    if self.is_event_overlay_enabled {
+                        self.screen.set_overlay(get_event_markers(&self.events));
+                    }
                     self.screen.present();
-                    self.set_vblank();
-                    if self.is_nmi_bit_set() {
-                        self.interrupt_bus.borrow_mut().raise_nmi();
+                    if !std::mem::take(&mut self.is_vblank_suppressed) {
+                        self.set_vblank();
+                        if self.is_nmi_bit_set() {
+                            self.raise_nmi();
+                        }
                     }
                 }
             }
-            261u16 => {
-                if self.col > 0 && self.col < 257 {
-                    self.next_background_evaluation();
-                    self.next_sprite_evaluation();
-                }
+            line if line == self.region.get_pre_render_line() => {
+                // Pre-render line fetches the first two tiles of next frame
+                self.next_background_evaluation();
+                self.next_sprite_evaluation();
                 if self.col == 1 {
                     self.clear_vblank();
                     self.clear_sprite0_hit();
@@ -172,14 +241,26 @@ impl Ppu<'_> {
             _ => (),
         }
 
+        if self.is_event_log_enabled {
+            let irq_count = self.interrupt_bus.borrow().get_irq_count();
+            if irq_count != self.irq_count {
+                self.irq_count = irq_count;
+                self.log_event(PpuEventKind::Irq);
+            }
+        }
+
         self.col = (self.col + 1) % 341;
         if self.col == 0 {
             // End of scan line
-            self.line = (self.line + 1) % 262;
+            self.line = (self.line + 1) % self.region.get_scanline_count();
         }
         if (self.col, self.line) == (0, 0) {
             self.interrupt_bus.borrow_mut().set_frame_updated();
-            if self.is_odd_frame {
+            self.frame_events = std::mem::take(&mut self.events);
+            self.frame_count += 1;
+            // The idle dot is skipped only when rendering, the pre-render line fetches ending one dot earlier
+            if self.is_odd_frame && self.region.has_odd_frame_skip() && self.is_rendering_enabled()
+            {
                 self.col = 1;
             }
             self.is_odd_frame = !self.is_odd_frame;
@@ -188,19 +269,17 @@ impl Ppu<'_> {
 
     /// Execute next instruction
     pub fn next_background_evaluation(&mut self) {
-        if self.line < 240 || self.line == 261 {
-            // Normal line
-            if self.col > 0 && self.col < 257 {
-                self.load_tile_data();
-            }
-            if self.col == 257 {
-                self.copy_hor_t_to_hor_v();
-            }
-            if self.col > 320 && self.col < 337 {
-                self.load_tile_data();
-            }
+        if !self.is_rendering_enabled() || !self.is_rendering_line() {
+            return;
+        }
+        if (self.col > 0 && self.col < 257) || (self.col > 320 && self.col < 337) {
+            self.shift_registers();
+            self.load_tile_data();
         }
-        if self.is_rendering_enabled() && self.line == 261 && self.col > 279 && self.col < 305 {
+        if self.col == 257 {
+            self.copy_hor_t_to_hor_v();
+        }
+        if self.line == self.region.get_pre_render_line() && self.col > 279 && self.col < 305 {
             self.copy_vert_t_to_vert_v();
         }
     }
@@ -221,13 +300,15 @@ impl Ppu<'_> {
                     | ((self.register_v >> 4) & 0x38)
                     | ((self.register_v >> 2) & 0x07);
                 let at_byte = self.read_ppu_memory(attribute_address);
-                self.set_at_byte(at_byte);
+                // Select the 2x2 tiles quadrant from coarse X bit 1 and coarse Y bit 1
+                let shift = ((self.register_v >> 4) & 4) | (self.register_v & 2);
+                self.set_at_byte((at_byte >> shift) & 0b11);
             }
             5 => {
                 // read low BG Tile Byte for N+2 tile
                 let chr_bank = ((self.ppuctrl as u16 >> 4) & 1) * 0x1000;
                 let fine_y = self.register_v >> 12;
-                let tile_address = *self.bg_nt_table_register.back().unwrap() as u16;
+                let tile_address = self.nt_byte as u16;
                 let low_bg_tile_byte = self.read_ppu_memory(chr_bank + 16 * tile_address + fine_y);
                 self.set_low_bg_tile_byte(low_bg_tile_byte);
             }
@@ -235,13 +316,13 @@ impl Ppu<'_> {
                 // read high BG Tile Byte for N+2 tile
                 let chr_bank = ((self.ppuctrl as u16 >> 4) & 1) * 0x1000;
                 let fine_y = self.register_v >> 12;
-                let tile_address = *self.bg_nt_table_register.back().unwrap() as u16;
+                let tile_address = self.nt_byte as u16;
                 let high_bg_tile_byte =
                     self.read_ppu_memory(chr_bank + 16 * tile_address + 8 + fine_y);
                 self.set_high_bg_tile_byte(high_bg_tile_byte);
             }
             0 => {
-                self.shift_registers();
+                self.load_shift_registers();
                 if self.col == 256 {
                     self.inc_vert_v();
                 } else {
@@ -260,117 +341,253 @@ impl Ppu<'_> {
         }
         if self.col == 64 {
             self.sprite_count = 0;
+            self.sprite_byte_index = 0;
             self.secondary_oam_pointer = 0;
+            self.sprite_evaluation = SpriteEvaluation::Copy;
+            self.is_sprite_0_evaluated = false;
         }
 
-        if self.secondary_oam_pointer > 7 {
-            self.set_sprite_overflow();
-            return; // Maximum 8 sprites found per frame
-        }
-        if self.col > 64 && self.col < 256 && self.sprite_count < 64 {
-            // During those cycles, sprites are actually evaluated
-            // Fetch next sprite first byte (y coordinate)
-            let sprite_y_coordinate = self.primary_oam[(4 * self.sprite_count) as usize];
-            self.secondary_oam[self.secondary_oam_pointer * 4] = sprite_y_coordinate;
-            let sprite_y_coordinate = sprite_y_coordinate as u16;
-            if self.line >= sprite_y_coordinate && self.line < sprite_y_coordinate + 8 {
-                // If first sprite in primary OAM, then sprite 0 to eventually raise in pixel generator
-                if self.sprite_count == 0 {
-                    self.is_first_sprite_0 = true;
-                }
-                // Le sprite traverse la scanline, on le copy dans  le secondary oam
-                self.secondary_oam[self.secondary_oam_pointer * 4 + 1] =
-                    self.primary_oam[(4 * self.sprite_count + 1) as usize];
-                self.secondary_oam[self.secondary_oam_pointer * 4 + 2] =
-                    self.primary_oam[(4 * self.sprite_count + 2) as usize];
-                self.secondary_oam[self.secondary_oam_pointer * 4 + 3] =
-                    self.primary_oam[(4 * self.sprite_count + 3) as usize];
-                self.secondary_oam_pointer += 1;
-            }
-            self.sprite_count += 1;
+        // During those cycles, sprites are actually evaluated : odd cycles read primary OAM, even cycles write secondary OAM
+        if self.col > 64
+            && self.col < 256
+            && self.col % 2 == 1
+            && self.line < 240
+            && (self.is_bg_rendering_enabled() || self.is_sprite_rendering_enabled())
+        {
+            self.next_sprite_evaluation_step();
+        }
+
+        if self.col == 256 && !self.is_sprite_limit_enabled {
+            self.evaluate_extra_sprites();
         }
 
         if self.col == 256 {
             self.sprite_fetcher_count = 0;
+            self.is_first_sprite_0 = self.is_sprite_0_evaluated;
             self.clear_sprite_registers();
         }
 
-        if self.sprite_fetcher_count < self.secondary_oam_pointer
-            && self.col > 256
-            && self.col < 321
-        {
-            // During those cycles sprites are actually fetched for rendering in the next line
+        if self.col > 256 && self.col < 321 {
+            // During those cycles sprites are actually fetched for rendering in the next line, 8 cycles per sprite
+            let is_sprite_found = self.sprite_fetcher_count < self.secondary_oam_pointer;
             match self.col % 8 {
-                // TODO : Split in 1, 3, 5, 7 to closer respect the real NES process
-                1 => (),
-                3 => {
-                    let attribute = self.secondary_oam[self.sprite_fetcher_count * 4 + 2];
-                    self.sprite_attribute_table_register.push_back(attribute);
+                // 1 : garbage nametable fetch
+                3 if is_sprite_found => self.fetch_sprite_attributes(),
+                5 if is_sprite_found => self.fetch_sprite_low_byte(),
+                7 if is_sprite_found => {
+                    self.fetch_sprite_high_byte();
+                    self.sprite_fetcher_count += 1;
                 }
-                5 => {
-                    // Fetch sprite low and high byte at the same time on 7 instead of spreading over 8 cycles
-                    let y_coordinate = self.secondary_oam[self.sprite_fetcher_count * 4] as u16;
-                    let tile_address = self.secondary_oam[self.sprite_fetcher_count * 4 + 1] as u16;
-                    let attribute = *self.sprite_attribute_table_register.back().unwrap();
-                    let x_coordinate = self.secondary_oam[self.sprite_fetcher_count * 4 + 3];
-
-                    let mut fine_y = self.line - y_coordinate;
-
-                    // Flipping
-                    let flip_horizontally = (attribute >> 6) & 1 != 0;
-                    let flip_vertically = (attribute >> 7) & 1 != 0;
-
-                    let mut flipping_offset: u16 = 0;
-                    if flip_vertically {
-                        flipping_offset = 8;
+                _ => (),
+            }
+        }
+
+        if self.col == 320 {
+            // Sprites above the 8 sprites limit are fetched at once
+            while self.sprite_fetcher_count < self.secondary_oam_pointer {
+                self.fetch_sprite_attributes();
+                self.fetch_sprite_low_byte();
+                self.fetch_sprite_high_byte();
+                self.sprite_fetcher_count += 1;
+            }
+        }
+    }
+
+    /// One read and write step of the sprite evaluation, following the hardware state machine
+    ///
+    /// Implementation based on nesdev PPU_sprite_evaluation, including the sprite overflow bug
+    fn next_sprite_evaluation_step(&mut self) {
+        if matches!(self.sprite_evaluation, SpriteEvaluation::Done) {
+            return;
+        }
+        let value =
+            self.primary_oam[4 * self.sprite_count as usize + self.sprite_byte_index as usize];
+        match self.sprite_evaluation {
+            SpriteEvaluation::Copy => {
+                self.secondary_oam
+                    [self.secondary_oam_pointer * 4 + self.sprite_byte_index as usize] = value;
+                if self.sprite_byte_index == 0 {
+                    if self.is_sprite_in_range(value) {
+                        // If first sprite in primary OAM, then sprite 0 to eventually raise in pixel generator
+                        if self.sprite_count == 0 {
+                            self.is_sprite_0_evaluated = true;
+                        }
+                        self.sprite_byte_index = 1;
+                    } else {
+                        self.next_evaluated_sprite();
                     }
-                    if flip_horizontally {
-                        fine_y = 7 - fine_y;
+                } else if self.sprite_byte_index == 3 {
+                    // Le sprite traverse la scanline, il est copié dans le secondary oam
+                    self.secondary_oam_pointer += 1;
+                    self.next_evaluated_sprite();
+                } else {
+                    self.sprite_byte_index += 1;
+                }
+            }
+            SpriteEvaluation::Overflow => {
+                if self.is_sprite_in_range(value) {
+                    self.set_sprite_overflow();
+                    self.sprite_evaluation = SpriteEvaluation::Done;
+                } else {
+                    // Hardware bug : m is incremented along with n, so the wrong bytes are compared as Y
+                    self.sprite_byte_index = (self.sprite_byte_index + 1) & 3;
+                    self.sprite_count += 1;
+                    if self.sprite_count == 64 {
+                        self.sprite_evaluation = SpriteEvaluation::Done;
                     }
+                }
+            }
+            SpriteEvaluation::Done => (),
+        }
+    }
 
-                    let chr_bank = ((self.ppuctrl as u16 >> 3) & 1) * 0x1000;
-                    let low_sprite_tile_byte = self.read_ppu_memory(
-                        chr_bank + 16u16 * tile_address + fine_y + flipping_offset,
-                    );
+    /// Move sprite evaluation to the next primary OAM sprite
+    fn next_evaluated_sprite(&mut self) {
+        self.sprite_byte_index = 0;
+        self.sprite_count += 1;
+        if self.sprite_count == 64 {
+            self.sprite_evaluation = SpriteEvaluation::Done;
+        } else if self.secondary_oam_pointer == 8 {
+            self.sprite_evaluation = SpriteEvaluation::Overflow;
+        }
+    }
+
+    /// Check if a sprite with this Y coordinate is visible on the next line
+    fn is_sprite_in_range(&self, y_coordinate: u8) -> bool {
+        let y_coordinate = y_coordinate as u16;
+        self.line >= y_coordinate && self.line < y_coordinate + self.get_sprite_height()
+    }
 
-                    self.sprite_x_coordinate_table_register
-                        .push_back(x_coordinate);
-                    self.sprite_low_byte_table_register
-                        .push_back(low_sprite_tile_byte);
+    /// Copy every sprite above the 8 sprites limit to secondary OAM. Sprite overflow flag is left to the hardware evaluation
+    fn evaluate_extra_sprites(&mut self) {
+        if self.secondary_oam_pointer < 8 {
+            return;
+        }
+        let mut found = 0;
+        for sprite in 0..64 {
+            if self.is_sprite_in_range(self.primary_oam[sprite * 4]) {
+                if found >= 8 {
+                    let pointer = self.secondary_oam_pointer * 4;
+                    self.secondary_oam[pointer..pointer + 4]
+                        .copy_from_slice(&self.primary_oam[sprite * 4..sprite * 4 + 4]);
+                    self.secondary_oam_pointer += 1;
                 }
-                7 => {
-                    // Fetch sprite low and high byte at the same time on 7 instead of spreading over 8 cycles
-                    let y_coordinate = self.secondary_oam[self.sprite_fetcher_count * 4] as u16;
-                    let tile_address = self.secondary_oam[self.sprite_fetcher_count * 4 + 1] as u16;
-                    let attribute = *self.sprite_attribute_table_register.back().unwrap();
+                found += 1;
+            }
+        }
+    }
 
-                    let mut fine_y = self.line - y_coordinate;
+    /// Set the palette, a built-in palette name or a .pal file
+    pub fn set_palette(&mut self, name: &str) -> Result<(), String> {
+        self.screen.set_palette(palette::load_palette(name)?);
+        Ok(())
+    }
 
-                    // Flipping
-                    let flip_horizontally = (attribute >> 6) & 1 != 0;
-                    let flip_vertically = (attribute >> 7) & 1 != 0;
+    /// Render frames in memory only, without window. Must be called before start
+    pub fn set_headless(&mut self) {
+        self.screen.set_headless();
+    }
 
-                    if flip_horizontally {
-                        fine_y = 7 - fine_y;
-                    }
+    /// Get the last complete frame, as 9 bits emphasis and color index values
+    pub fn get_frame(&self) -> &[u16] {
+        self.screen.get_frame()
+    }
 
-                    let chr_bank = ((self.ppuctrl as u16 >> 3) & 1) * 0x1000;
+    /// Get the last complete frame as RGB24 pixels
+    pub fn get_frame_rgb(&self) -> Vec<u8> {
+        self.screen.get_frame_rgb()
+    }
 
-                    let mut flipping_offset: u16 = 8;
-                    if flip_vertically {
-                        flipping_offset = 0;
-                    }
+    /// Save the last complete frame to a PNG file, optionally without the overscan lines
+    pub fn save_screenshot(&self, file_name: &str, crop_overscan: bool) -> std::io::Result<()> {
+        self.screen.save_screenshot(file_name, crop_overscan)
+    }
 
-                    let high_sprite_tile_byte = self.read_ppu_memory(
-                        chr_bank + 16u16 * tile_address + fine_y + flipping_offset,
-                    );
-                    self.sprite_high_byte_table_register
-                        .push_back(high_sprite_tile_byte);
+    /// Enable the NTSC video filter with a preset, or disable it
+    pub fn set_ntsc_filter(&mut self, preset: Option<NtscPreset>) {
+        self.screen.set_ntsc_filter(preset);
+    }
 
-                    self.sprite_fetcher_count += 1;
-                }
-                _ => (),
+    /// Set the region driving the frame timings
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Lift the 8 sprites per line limit to reduce flickering. Sprite overflow flag is not affected
+    pub fn set_sprite_limit(&mut self, is_enabled: bool) {
+        self.is_sprite_limit_enabled = is_enabled;
+    }
+
+    /// Fetch attribute and X coordinate of the current sprite
+    fn fetch_sprite_attributes(&mut self) {
+        let attribute = self.secondary_oam[self.sprite_fetcher_count * 4 + 2];
+        let x_coordinate = self.secondary_oam[self.sprite_fetcher_count * 4 + 3];
+        self.sprite_attribute_table_register.push_back(attribute);
+        self.sprite_x_counter_register.push_back(x_coordinate);
+    }
+
+    /// Fetch low pattern byte of the current sprite
+    fn fetch_sprite_low_byte(&mut self) {
+        let address = self.get_sprite_pattern_address(self.sprite_fetcher_count);
+        let low_sprite_tile_byte = self.read_sprite_pattern(address, self.sprite_fetcher_count);
+        self.sprite_low_byte_table_register
+            .push_back(low_sprite_tile_byte);
+    }
+
+    /// Fetch high pattern byte of the current sprite
+    fn fetch_sprite_high_byte(&mut self) {
+        let address = self.get_sprite_pattern_address(self.sprite_fetcher_count) + 8;
+        let high_sprite_tile_byte = self.read_sprite_pattern(address, self.sprite_fetcher_count);
+        self.sprite_high_byte_table_register
+            .push_back(high_sprite_tile_byte);
+    }
+
+    /// Sprite height in pixels, depending on PPUCTRL bit 5
+    fn get_sprite_height(&self) -> u16 {
+        if (self.ppuctrl >> 5) & 1 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Address of the low pattern byte of a secondary OAM sprite for the current line
+    ///
+    /// 8x8 sprites use the pattern table selected by PPUCTRL bit 3, 8x16 sprites select it with tile bit 0
+    fn get_sprite_pattern_address(&self, sprite: usize) -> u16 {
+        let y_coordinate = self.secondary_oam[sprite * 4] as u16;
+        let tile_index = self.secondary_oam[sprite * 4 + 1] as u16;
+        let attribute = self.secondary_oam[sprite * 4 + 2];
+
+        let height = self.get_sprite_height();
+        let mut row = (self.line - y_coordinate) % height;
+        if (attribute >> 7) & 1 != 0 {
+            // Vertical flipping
+            row = height - 1 - row;
+        }
+
+        if height == 16 {
+            let chr_bank = (tile_index & 1) * 0x1000;
+            let mut tile_index = tile_index & 0xfe;
+            if row > 7 {
+                tile_index += 1;
+                row -= 8;
             }
+            chr_bank + 16 * tile_index + row
+        } else {
+            let chr_bank = ((self.ppuctrl as u16 >> 3) & 1) * 0x1000;
+            chr_bank + 16 * tile_index + row
+        }
+    }
+
+    /// Read a sprite pattern byte, reversing it for horizontal flipping
+    fn read_sprite_pattern(&mut self, address: u16, sprite: usize) -> u8 {
+        let value = self.read_ppu_memory(address);
+        let attribute = self.secondary_oam[sprite * 4 + 2];
+        if (attribute >> 6) & 1 != 0 {
+            value.reverse_bits()
+        } else {
+            value
         }
     }
 
@@ -384,15 +601,17 @@ impl Ppu<'_> {
     fn read_ppu_memory(&mut self, address: u16) -> u8 {
         match address {
             0..=0x1fff => self.cartridge.borrow_mut().read_chr_rom(address),
+            _ => self.peek_ppu_memory(address),
+        }
+    }
+
+    /// Read PPU memory without side effects, see read_ppu_memory
+    fn peek_ppu_memory(&self, address: u16) -> u8 {
+        match address {
+            0..=0x1fff => self.cartridge.borrow().peek_chr_rom(address),
             0x2000..=0x2fff => self.vram[(address - 0x2000) as usize],
             0x3000..=0x3eff => self.vram[(address - 0x3000) as usize],
-            0x3f00..=0x3fff => {
-                let mut palette_address = 0;
-                if address % 4 != 0 {
-                    palette_address = address % 0x20;
-                }
-                self.palette_vram[palette_address as usize]
-            }
+            0x3f00..=0x3fff => self.palette_vram[get_palette_address(address)],
             _ => panic!("Out of PPU memory range, address : {:04x}", address),
         }
     }
@@ -409,41 +628,170 @@ impl Ppu<'_> {
             0..=0x1fff => self.cartridge.borrow_mut().write_chr_rom(address, value),
             0x2000..=0x2fff => self.vram[(address - 0x2000) as usize] = value,
             0x3000..=0x3eff => self.vram[(address - 0x3000) as usize] = value,
-            0x3f00..=0x3fff => {
-                let mut palette_address = 0;
-                if address % 4 != 0 {
-                    palette_address = address % 0x20;
+            0x3f00..=0x3fff => self.palette_vram[get_palette_address(address)] = value,
+            _ => panic!("Out of PPU memory range, address : {:04x}", address),
+        }
+    }
+
+    /// Read PPU memory without side effects, for debugging purpose
+    pub fn peek_vram(&self, address: u16) -> u8 {
+        self.peek_ppu_memory(address % 0x4000)
+    }
+
+    /// Read PPU register without side effects, for debugging purpose
+    ///
+    /// Returns what a CPU read would return, without clearing vblank or moving VRAM address
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address {
+            0x2002 => (self.ppustatus & 0xe0) | (self.read_open_bus() & 0x1f),
+            0x2004 => self.primary_oam[self.oamaddr as usize],
+            0x2007 => {
+                if self.ppuaddr % 0x4000 < 0x3f00 {
+                    self.ppudata
+                } else {
+                    self.read_palette_for_cpu()
                 }
-                self.palette_vram[palette_address as usize] = value
             }
-            _ => panic!("Out of PPU memory range, address : {:04x}", address),
+            _ => self.read_open_bus(), // Write only registers
         }
     }
 
+    /// Read the PPU data bus latch, with decayed bits cleared
+    pub fn read_open_bus(&self) -> u8 {
+        let mut value = self.open_bus;
+        for bit in 0..8 {
+            if self.frame_count - self.open_bus_refresh_frames[bit] > OPEN_BUS_DECAY_FRAMES {
+                value &= !(1 << bit);
+            }
+        }
+        value
+    }
+
+    /// Drive the data bus latch with a value, only for the bits in mask
+    ///
+    /// Every CPU write to a PPU register drives the whole latch, reads only drive the bits they return
+    pub fn refresh_open_bus(&mut self, value: u8, mask: u8) {
+        self.open_bus = (self.read_open_bus() & !mask) | (value & mask);
+        for bit in 0..8 {
+            if (mask & value) >> bit & 1 != 0 {
+                self.open_bus_refresh_frames[bit] = self.frame_count;
+            }
+        }
+    }
+
+    /// Palette value returned to the CPU : 6 bits color, grayscale applied, and open bus high bits
+    fn read_palette_for_cpu(&self) -> u8 {
+        let mut value = self.peek_ppu_memory(self.ppuaddr % 0x4000) & 0x3f;
+        if self.ppumask & 1 != 0 {
+            value &= 0x30;
+        }
+        value | (self.read_open_bus() & 0xc0)
+    }
+
+    /// Write PPU memory for debugging purpose
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        self.write_ppu_memory(address % 0x4000, value);
+    }
+
+    /// Record PPU memory accesses made by the CPU through 0x2007, for watchpoints
+    pub fn enable_access_log(&mut self) {
+        self.is_access_log_enabled = true;
+    }
+
+    /// Get PPU memory accesses recorded since last clear
+    pub fn get_access_log(&self) -> &[MemoryAccess] {
+        &self.access_log
+    }
+
+    /// Clear recorded PPU memory accesses
+    pub fn clear_access_log(&mut self) {
+        self.access_log.clear();
+    }
+
+    /// Record register writes and interrupts with the scanline and dot they happen on
+    pub fn enable_event_log(&mut self) {
+        self.is_event_log_enabled = true;
+    }
+
+    /// Draw a marker on the frame for each event of the visible lines, enabling the event log
+    pub fn set_event_overlay(&mut self, is_enabled: bool) {
+        self.is_event_overlay_enabled = is_enabled;
+        if is_enabled {
+            self.enable_event_log();
+        } else {
+            self.screen.set_overlay(vec![]);
+        }
+    }
+
+    /// Return true if events are drawn over the frame
+    pub fn is_event_overlay_enabled(&self) -> bool {
+        self.is_event_overlay_enabled
+    }
+
+    /// Record an event at the current PPU position, if the event log is enabled
+    pub fn log_event(&mut self, kind: PpuEventKind) {
+        if self.is_event_log_enabled {
+            self.events.push(PpuEvent {
+                frame: self.frame_count,
+                status: self.get_status(),
+                kind,
+            });
+        }
+    }
+
+    /// Get the events of the last complete frame
+    pub fn get_frame_events(&self) -> &[PpuEvent] {
+        &self.frame_events
+    }
+
     /// Update PPU internal register when CPU read 0x2002 memory address
+    ///
+    /// Reading one dot before vblank starts prevents the flag and NMI for this frame,
+    /// reading on the dot the flag is set or the next one returns it but cancels the NMI
     pub fn read_0x2002(&mut self) -> u8 {
+        match self.get_vblank_dot() {
+            Some(1) => self.is_vblank_suppressed = true,
+            Some(2..=3) => self.interrupt_bus.borrow_mut().cancel_nmi(),
+            _ => (),
+        }
         self.register_w = false;
         self.ppuaddr = 0;
-        let value = self.ppustatus;
-        self.ppustatus = value & 0b1111111;
+        // Only the 3 status bits are driven, others come from open bus
+        let value = (self.ppustatus & 0xe0) | (self.read_open_bus() & 0x1f);
+        self.refresh_open_bus(value, 0xe0);
+        self.ppustatus &= 0b1111111;
         value
     }
 
     /// Read PPU internal register at 0x2004 memory address - read OAM at oamaddr
-    pub fn read_0x2004(&self) -> u8 {
-        self.primary_oam[self.oamaddr as usize]
+    pub fn read_0x2004(&mut self) -> u8 {
+        let value = self.primary_oam[self.oamaddr as usize];
+        self.refresh_open_bus(value, 0xff);
+        value
     }
 
     /// Read PPU internal register at 0x2007 memory address
     pub fn read_0x2007(&mut self) -> u8 {
-        let value: u8;
-        if self.ppuaddr % 0x4000 < 0x3f00 {
+        let address = self.ppuaddr % 0x4000; // Address above 0x3fff are mirrored down
+        let value = if address < 0x3f00 {
             // Delayed buffering requiring dummy read
-            value = self.ppudata;
-            self.ppudata = self.read_ppu_memory(self.ppuaddr % 0x4000); // Address above 0x3fff are mirrored down
+            let value = self.ppudata;
+            self.ppudata = self.read_ppu_memory(address);
+            self.refresh_open_bus(value, 0xff);
+            value
         } else {
-            self.ppudata = self.read_ppu_memory(self.ppuaddr % 0x4000); // Address above 0x3fff are mirrored down
-            value = self.ppudata;
+            // Palette is returned immediately, the buffer is filled with the nametable byte "below" the palette
+            let value = self.read_palette_for_cpu();
+            self.ppudata = self.read_ppu_memory(address - 0x1000);
+            self.refresh_open_bus(value, 0x3f);
+            value
+        };
+        if self.is_access_log_enabled {
+            self.access_log.push(MemoryAccess {
+                address,
+                value,
+                is_write: false,
+            });
         }
         self.read_or_write_0x2007();
         self.ppuaddr += self.get_ram_step_forward();
@@ -451,8 +799,18 @@ impl Ppu<'_> {
     }
 
     /// Update PPU internal register when CPU write 0x2000 memory address
+    ///
+    /// Enabling NMI during vblank raises it immediately, disabling it right as vblank starts cancels it
     pub fn write_0x2000(&mut self, value: u8) {
+        let was_nmi_enabled = self.is_nmi_bit_set();
         self.ppuctrl = value;
+        if !was_nmi_enabled && self.is_nmi_bit_set() && self.is_vblank() {
+            self.raise_nmi();
+        } else if was_nmi_enabled && !self.is_nmi_bit_set() {
+            if let Some(2..=3) = self.get_vblank_dot() {
+                self.interrupt_bus.borrow_mut().cancel_nmi();
+            }
+        }
         let t = self.register_t & 0b111001111111111;
         self.register_t = t | ((value as u16 & 0b11) << 10);
     }
@@ -504,12 +862,19 @@ impl Ppu<'_> {
     /// Write PPU internal register at 0x2007 memory address
     pub fn write_0x2007(&mut self, value: u8) {
         self.write_ppu_memory(self.ppuaddr % 0x4000, value); // Address above 0x3fff are mirrored down
+        if self.is_access_log_enabled {
+            self.access_log.push(MemoryAccess {
+                address: self.ppuaddr % 0x4000,
+                value,
+                is_write: true,
+            });
+        }
         self.read_or_write_0x2007();
         self.ppuaddr += self.get_ram_step_forward();
     }
 
     fn read_or_write_0x2007(&mut self) {
-        if !self.is_rendering_enabled() {
+        if !self.is_rendering_enabled() || !self.is_rendering_line() {
             self.register_v += self.get_ram_step_forward();
         } else {
             self.inc_vert_v();
@@ -572,7 +937,12 @@ impl Ppu<'_> {
 
     /// Return 1 is rendering is enabled, 0 otherwise
     fn is_rendering_enabled(&self) -> bool {
-        self.is_bg_rendering_enabled() && self.is_sprite_rendering_enabled()
+        self.is_bg_rendering_enabled() || self.is_sprite_rendering_enabled()
+    }
+
+    /// Return true on visible and pre-render lines, where the PPU fetches memory when rendering is enabled
+    fn is_rendering_line(&self) -> bool {
+        self.line < 240 || self.line == self.region.get_pre_render_line()
     }
 
     /// Return 1 is rendering is enabled, 0 otherwise
@@ -608,6 +978,22 @@ impl Ppu<'_> {
         self.ppustatus |= 0b10000000;
     }
 
+    /// Return true if vblank bit is set in ppustatus register
+    fn is_vblank(&self) -> bool {
+        self.ppustatus & 0b10000000 != 0
+    }
+
+    /// Raise an NMI to the CPU, and log it
+    fn raise_nmi(&mut self) {
+        self.interrupt_bus.borrow_mut().raise_nmi();
+        self.log_event(PpuEventKind::Nmi);
+    }
+
+    /// Get the next dot to render if it is on the vblank start line, to detect races with vblank start at dot 1
+    fn get_vblank_dot(&self) -> Option<u16> {
+        (self.line == self.region.get_vblank_start_line()).then_some(self.col)
+    }
+
     /// Clear vblank bit in ppustatus register
     fn clear_vblank(&mut self) {
         self.ppustatus &= 0b01111111;
@@ -641,59 +1027,62 @@ impl Ppu<'_> {
         }
     }
 
-    // Return a color_index which is a palette index
-    pub fn compute_next_pixel(&mut self) -> u8 {
+    // Return a 9 bits pixel value : emphasis bits then color index
+    pub fn compute_next_pixel(&mut self) -> u16 {
         let (bg_color_code, bg_color_palette) = self.compute_bg_pixel();
         let (sprite_color_code, sprite_color_palette, priority, sprite_id) =
             self.compute_sprite_pixel();
 
-        self.multiplexer_decision(
+        let mut color_index = self.multiplexer_decision(
             bg_color_code,
             bg_color_palette,
             sprite_color_code,
             sprite_color_palette,
             priority,
             sprite_id == 0,
-        )
+        ) & 0x3f;
+        if self.ppumask & 1 != 0 {
+            // Grayscale : keep the gray column only
+            color_index &= 0x30;
+        }
+        ((self.ppumask as u16 >> 5) << 6) | color_index as u16
+    }
+
+    /// Return true if background or sprites are hidden on the current pixel by left column clipping
+    fn is_left_clipped(&self, mask_bit: u8) -> bool {
+        self.col < 9 && (self.ppumask >> mask_bit) & 1 == 0
     }
 
     /// Compute the elements for the bg pixel
     fn compute_bg_pixel(&mut self) -> (u8, u8) {
-        let mut fine_x = (self.col - 1) % 8 + self.register_x as u16; // Pixel 0 is outputed at col == 1
-        let mut register_level = 0;
-        if fine_x > 7 {
-            register_level += 1;
-            fine_x -= 8;
+        if !self.is_bg_rendering_enabled() || self.is_left_clipped(1) {
+            return (0, 0);
         }
-
-        let bit1 = (self.bg_low_byte_table_register[register_level] >> (7 - fine_x)) & 1;
-        let bit2 = (self.bg_high_byte_table_register[register_level] >> (7 - fine_x)) & 1;
-        let bg_color_code = bit1 | (bit2 << 1);
-
-        let attribute = self.bg_attribute_table_register[register_level];
-
-        // la position réelle x et y dépendent du coin en haut à gauche défini par register_t + fine x ou y  + la position réelle sur l'écran
-        let shift_x = (self.register_t & 0x1f) + (self.col - 1) + self.register_x as u16;
-        let shift_y =
-            ((self.register_t & 0x3e0) >> 5) + self.line + ((self.register_t & 0x7000) >> 12);
-
-        // Compute which zone to select in the attribute byte
-        let shift =
-            ((if shift_x % 32 > 15 { 1 } else { 0 }) + (if shift_y % 32 > 15 { 2 } else { 0 })) * 2;
-        let bg_color_palette = (attribute >> shift) & 0b11;
+        // Fine X selects the bit in the current tile
+        let bit = 15 - self.register_x as u16;
+        let bit1 = (self.bg_low_shift_register >> bit) & 1;
+        let bit2 = (self.bg_high_shift_register >> bit) & 1;
+        let bg_color_code = (bit1 | (bit2 << 1)) as u8;
+
+        let bit1 = (self.bg_attribute_low_shift_register >> bit) & 1;
+        let bit2 = (self.bg_attribute_high_shift_register >> bit) & 1;
+        let bg_color_palette = (bit1 | (bit2 << 1)) as u8;
         (bg_color_code, bg_color_palette)
     }
 
     /// Compute the elements for the sprite pixel if there is one at that position
     fn compute_sprite_pixel(&mut self) -> (u8, u8, u8, u8) {
-        for i in 0..(self.sprite_x_coordinate_table_register.len()) {
-            let sprite_x = self.sprite_x_coordinate_table_register[i] as u16;
-            // TODO : self.col must only wrok where no scrolling, use register_v instead ?
-            if self.col >= sprite_x && self.col < sprite_x + 8 {
-                let x_offset = self.col % 8;
-                let bit1 = (self.sprite_low_byte_table_register[i] >> (7 - x_offset)) & 1;
-                let bit2 = (self.sprite_high_byte_table_register[i] >> (7 - x_offset)) & 1;
+        if !self.is_sprite_rendering_enabled() || self.is_left_clipped(2) {
+            return (0, 0, 1, 10);
+        }
+        for i in 0..(self.sprite_x_counter_register.len()) {
+            if self.sprite_x_counter_register[i] == 0 {
+                let bit1 = (self.sprite_low_byte_table_register[i] >> 7) & 1;
+                let bit2 = (self.sprite_high_byte_table_register[i] >> 7) & 1;
                 let sprite_color_code = bit1 | (bit2 << 1);
+                if sprite_color_code == 0 {
+                    continue; // Transparent pixel, next sprite may be visible
+                }
 
                 let attribute = self.sprite_attribute_table_register[i];
                 let priority = (attribute >> 5) & 0x1;
@@ -729,9 +1118,9 @@ impl Ppu<'_> {
             return self.palette_vram[(bg_palette_address + bg_color_code) as usize];
             // bg color
         }
-        // BG color > 0 and Sprite color > 0 --> Set sprite_0
-        // TODO : check that we are on sprite 0 : is it the right method ?
-        if is_sprite_0 && self.is_first_sprite_0 {
+        // BG color > 0 and Sprite color > 0 --> Set sprite_0, except on last pixel of the line
+        // Left column clipping is already applied on both colors
+        if is_sprite_0 && self.is_first_sprite_0 && self.col != 256 {
             self.set_sprite0_hit();
         }
 
@@ -741,12 +1130,38 @@ impl Ppu<'_> {
         self.palette_vram[(bg_palette_address + bg_color_code) as usize] // bg color
     }
 
-    /// Shift registers every 8 cycles
+    /// Shift background registers, once per pixel
     pub fn shift_registers(&mut self) {
-        self.bg_low_byte_table_register.pop_front();
-        self.bg_high_byte_table_register.pop_front();
-        self.bg_attribute_table_register.pop_front();
-        self.bg_nt_table_register.pop_front();
+        self.bg_low_shift_register <<= 1;
+        self.bg_high_shift_register <<= 1;
+        self.bg_attribute_low_shift_register <<= 1;
+        self.bg_attribute_high_shift_register <<= 1;
+    }
+
+    /// Load the fetched tile into the low byte of background registers, every 8 cycles
+    pub fn load_shift_registers(&mut self) {
+        self.bg_low_shift_register =
+            (self.bg_low_shift_register & 0xff00) | self.low_bg_tile_byte as u16;
+        self.bg_high_shift_register =
+            (self.bg_high_shift_register & 0xff00) | self.high_bg_tile_byte as u16;
+        let attribute_low = if self.at_byte & 1 != 0 { 0xff } else { 0 };
+        let attribute_high = if self.at_byte & 2 != 0 { 0xff } else { 0 };
+        self.bg_attribute_low_shift_register =
+            (self.bg_attribute_low_shift_register & 0xff00) | attribute_low;
+        self.bg_attribute_high_shift_register =
+            (self.bg_attribute_high_shift_register & 0xff00) | attribute_high;
+    }
+
+    /// Decrement sprite X counters, or shift sprite registers of active sprites, once per pixel
+    pub fn shift_sprite_registers(&mut self) {
+        for i in 0..self.sprite_x_counter_register.len() {
+            if self.sprite_x_counter_register[i] > 0 {
+                self.sprite_x_counter_register[i] -= 1;
+            } else {
+                self.sprite_low_byte_table_register[i] <<= 1;
+                self.sprite_high_byte_table_register[i] <<= 1;
+            }
+        }
     }
 
     /// Reset the sprite registers
@@ -754,28 +1169,27 @@ impl Ppu<'_> {
         self.sprite_low_byte_table_register.clear();
         self.sprite_high_byte_table_register.clear();
         self.sprite_attribute_table_register.clear();
-        self.sprite_x_coordinate_table_register.clear();
+        self.sprite_x_counter_register.clear();
     }
 
     /// Set nt_byte into registers
     pub fn set_nt_byte(&mut self, nt_byte: u8) {
-        self.bg_nt_table_register.push_back(nt_byte);
+        self.nt_byte = nt_byte;
     }
 
     /// Set at_byte into registers
     pub fn set_at_byte(&mut self, at_byte: u8) {
-        self.bg_attribute_table_register.push_back(at_byte);
+        self.at_byte = at_byte;
     }
 
     /// Set low_bg_tile_byte into registers
     pub fn set_low_bg_tile_byte(&mut self, low_bg_tile_byte: u8) {
-        self.bg_low_byte_table_register.push_back(low_bg_tile_byte);
+        self.low_bg_tile_byte = low_bg_tile_byte;
     }
 
     /// Set high_bg_tile_byte into registers
     pub fn set_high_bg_tile_byte(&mut self, high_bg_tile_byte: u8) {
-        self.bg_high_byte_table_register
-            .push_back(high_bg_tile_byte);
+        self.high_bg_tile_byte = high_bg_tile_byte;
     }
 
     pub fn get_ppustatus_as_string(&self) -> String {
@@ -786,6 +1200,21 @@ impl Ppu<'_> {
         )
     }
 
+    /// PPU registers summary, used by the trace logger
+    pub fn get_registers_as_string(&self) -> String {
+        format!(
+            "CTRL:{:02X} MASK:{:02X} STATUS:{:02X} OAMADDR:{:02X} v:{:04X} t:{:04X} x:{} w:{}",
+            self.ppuctrl,
+            self.ppumask,
+            self.ppustatus,
+            self.oamaddr,
+            self.register_v,
+            self.register_t,
+            self.register_x,
+            self.register_w as u8,
+        )
+    }
+
     fn get_xor_primary_oam(&self) -> u8 {
         let mut xor = 0;
         for i in self.primary_oam.iter() {
@@ -801,52 +1230,314 @@ impl Ppu<'_> {
         }
         xor
     }
+}
+
+/// Get the overlay markers of events : position on the frame and color
+///
+/// Events of the horizontal blanking are drawn on the edges of their line, vertical blanking ones are not drawn
+fn get_event_markers(events: &[PpuEvent]) -> Vec<(u8, u8, Color)> {
+    events
+        .iter()
+        .filter(|event| event.status.line < 240)
+        .map(|event| {
+            let x = event.status.col.clamp(1, 256) - 1;
+            (x as u8, event.status.line as u8, event.kind.get_color())
+        })
+        .collect()
+}
+
+/// Palette RAM index of a 0x3f00 to 0x3fff address
+///
+/// Sprite palette entries 0x3f10, 0x3f14, 0x3f18 and 0x3f1c mirror 0x3f00, 0x3f04, 0x3f08 and 0x3f0c
+fn get_palette_address(address: u16) -> usize {
+    let palette_address = (address % 0x20) as usize;
+    if palette_address >= 0x10 && palette_address.is_multiple_of(4) {
+        palette_address - 0x10
+    } else {
+        palette_address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{lock_sdl, write_test_rom};
+
+    /// Headless PPU with a test NROM inserted, the given dot being the next one to render
+    fn new_ppu(line: u16, col: u16) -> (Ppu<'static>, Rc<RefCell<Interrupt>>) {
+        let sdl_context = Rc::new(RefCell::new(sdl2::init().unwrap()));
+        let cartridge = Rc::new(RefCell::new(Cartridge::new(write_test_rom("ppu", &[]))));
+        let interrupt_bus = Rc::new(RefCell::new(Interrupt::new()));
+        let mut ppu = Ppu::new(sdl_context, cartridge, interrupt_bus.clone());
+        ppu.set_headless();
+        ppu.ppustatus = 0;
+        (ppu.line, ppu.col) = (line, col);
+        (ppu, interrupt_bus)
+    }
+
+    /// Read PPUSTATUS once the given dot of the vblank start line is rendered, NMI enabled.
+    /// Returns the vblank flag read and whether the NMI is still pending at the end of the line
+    fn read_status_at_vblank_dot(dot: u16) -> (bool, bool) {
+        let (mut ppu, interrupt_bus) = new_ppu(241, 0);
+        ppu.write_0x2000(0x80);
+        for _ in 0..=dot {
+            ppu.next();
+        }
+        let is_vblank_read = ppu.read_0x2002() & 0x80 != 0;
+        while ppu.line == 241 {
+            ppu.next();
+        }
+        let is_nmi = interrupt_bus.borrow_mut().check_and_clear_nmi();
+        (is_vblank_read, is_nmi)
+    }
+
+    #[test]
+    fn vblank_status_read_races() {
+        let _sdl_lock = lock_sdl();
+        // Dot 0 : flag not set for this frame, no NMI
+        assert_eq!(read_status_at_vblank_dot(0), (false, false));
+        {
+            let (mut ppu, _) = new_ppu(241, 0);
+            ppu.next();
+            ppu.read_0x2002();
+            ppu.next();
+            assert!(!ppu.is_vblank());
+        }
 
-    pub fn print_primary_oam(&self) {
-        println!("Primary OAM");
-    }
-
-    pub fn print_secondary_oam(&self) {
-        println!("Secondary OAM");
-        self.sub_print_oam(0);
-        self.sub_print_oam(0x20);
-    }
-    fn sub_print_oam(&self, address: usize) {
-        println!("{:04x}:{:04x}    {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x}",
-            address,
-            address + 0x1f,
-            self.secondary_oam[address],
-            self.secondary_oam[address+1],
-            self.secondary_oam[address+2],
-            self.secondary_oam[address+3],
-            self.secondary_oam[address+4],
-            self.secondary_oam[address+5],
-            self.secondary_oam[address+6],
-            self.secondary_oam[address+7],
-            self.secondary_oam[address+8],
-            self.secondary_oam[address+9],
-            self.secondary_oam[address+10],
-            self.secondary_oam[address+11],
-            self.secondary_oam[address+12],
-            self.secondary_oam[address+13],
-            self.secondary_oam[address+14],
-            self.secondary_oam[address+15],
-            self.secondary_oam[address+16],
-            self.secondary_oam[address+17],
-            self.secondary_oam[address+18],
-            self.secondary_oam[address+19],
-            self.secondary_oam[address+20],
-            self.secondary_oam[address+21],
-            self.secondary_oam[address+22],
-            self.secondary_oam[address+23],
-            self.secondary_oam[address+24],
-            self.secondary_oam[address+25],
-            self.secondary_oam[address+26],
-            self.secondary_oam[address+27],
-            self.secondary_oam[address+28],
-            self.secondary_oam[address+29],
-            self.secondary_oam[address+30],
-            self.secondary_oam[address+31],
+        // Dots 1 and 2 : flag read, NMI cancelled
+        assert_eq!(read_status_at_vblank_dot(1), (true, false));
+        assert_eq!(read_status_at_vblank_dot(2), (true, false));
+
+        // Later reads do not cancel the NMI
+        assert_eq!(read_status_at_vblank_dot(3), (true, true));
+    }
+
+    #[test]
+    fn nmi_enable_during_vblank() {
+        let _sdl_lock = lock_sdl();
+        let (mut ppu, interrupt_bus) = new_ppu(250, 100);
+        ppu.set_vblank();
+        ppu.write_0x2000(0x80);
+        assert!(interrupt_bus.borrow_mut().check_and_clear_nmi());
+
+        // Only enabling raises an NMI
+        ppu.write_0x2000(0x80);
+        assert!(!interrupt_bus.borrow_mut().check_and_clear_nmi());
+        ppu.write_0x2000(0x00);
+        ppu.write_0x2000(0x80);
+        assert!(interrupt_bus.borrow_mut().check_and_clear_nmi());
+
+        // Not during vblank, or once the flag has been read
+        ppu.write_0x2000(0x00);
+        ppu.read_0x2002();
+        ppu.write_0x2000(0x80);
+        assert!(!interrupt_bus.borrow_mut().check_and_clear_nmi());
+    }
+
+    #[test]
+    fn odd_frame_skip() {
+        let _sdl_lock = lock_sdl();
+        // Returns the first dot rendered by the next frame
+        let next_frame_dot = |region: Region, is_odd_frame: bool, ppumask: u8| {
+            let (mut ppu, _) = new_ppu(0, 0);
+            ppu.set_region(region);
+            (ppu.line, ppu.col) = (region.get_pre_render_line(), 340);
+            ppu.is_odd_frame = is_odd_frame;
+            ppu.write_0x2001(ppumask);
+            ppu.next();
+            assert_eq!(ppu.line, 0);
+            ppu.col
+        };
+        assert_eq!(next_frame_dot(Region::Ntsc, true, 0x08), 1);
+        assert_eq!(next_frame_dot(Region::Ntsc, true, 0x10), 1);
+        assert_eq!(next_frame_dot(Region::Ntsc, true, 0x00), 0);
+        assert_eq!(next_frame_dot(Region::Ntsc, false, 0x18), 0);
+        assert_eq!(next_frame_dot(Region::Pal, true, 0x18), 0);
+    }
+
+    #[test]
+    fn event_log() {
+        let _sdl_lock = lock_sdl();
+        let (mut ppu, _) = new_ppu(240, 300);
+        ppu.enable_event_log();
+        // Register writes are logged by the bus before reaching the PPU
+        ppu.log_event(PpuEventKind::RegisterWrite(0x2000, 0x80));
+        ppu.write_0x2000(0x80);
+        for _ in 0..10 {
+            ppu.next();
+        }
+        ppu.log_event(PpuEventKind::RegisterWrite(0x2001, 0x1e));
+        ppu.write_0x2001(0x1e);
+
+        // Events move to the frame events at the end of the frame only
+        while ppu.line != 242 {
+            ppu.next();
+        }
+        assert!(ppu.get_frame_events().is_empty());
+        while ppu.line != 0 {
+            ppu.next();
+        }
+        let events: Vec<(u16, u16, PpuEventKind)> = ppu
+            .get_frame_events()
+            .iter()
+            .map(|event| (event.status.line, event.status.col, event.kind))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (240, 300, PpuEventKind::RegisterWrite(0x2000, 0x80)),
+                (240, 310, PpuEventKind::RegisterWrite(0x2001, 0x1e)),
+                (241, 1, PpuEventKind::Nmi),
+            ]
         );
+        assert!(ppu.get_frame_events().iter().all(|event| event.frame == 0));
+    }
+
+    /// Run the sprite evaluation of a line, rendering enabled, with the given primary OAM
+    fn evaluate_sprites(ppu: &mut Ppu, line: u16, oam: &[u8]) {
+        ppu.write_0x2001(0x18);
+        ppu.primary_oam = [0xff; 0x100];
+        ppu.primary_oam[..oam.len()].copy_from_slice(oam);
+        ppu.line = line;
+        for col in 0..=256 {
+            ppu.col = col;
+            ppu.next_sprite_evaluation();
+        }
+    }
+
+    /// Sprites with the given Y coordinates, tile, attributes and X being the sprite index
+    fn sprites(y_coordinates: &[u8]) -> Vec<u8> {
+        y_coordinates
+            .iter()
+            .enumerate()
+            .flat_map(|(index, y)| [*y, index as u8, 0, index as u8])
+            .collect()
+    }
+
+    #[test]
+    fn sprite_overflow() {
+        let _sdl_lock = lock_sdl();
+        let (mut ppu, _) = new_ppu(0, 0);
+
+        // 8 sprites on the line
+        evaluate_sprites(&mut ppu, 10, &sprites(&[5; 8]));
+        assert_eq!(ppu.secondary_oam_pointer, 8);
+        assert_eq!(ppu.ppustatus & 0x20, 0);
+
+        // 9 sprites on the line
+        evaluate_sprites(&mut ppu, 10, &sprites(&[5; 9]));
+        assert_eq!(ppu.secondary_oam_pointer, 8);
+        assert_eq!(ppu.ppustatus & 0x20, 0x20);
+        ppu.clear_sprite_overflow();
+
+        // False positive : after sprite 8, the tile byte of sprite 9 is compared as Y
+        let mut oam = sprites(&[5, 5, 5, 5, 5, 5, 5, 5, 100, 200]);
+        oam[9 * 4 + 1] = 8;
+        evaluate_sprites(&mut ppu, 10, &oam);
+        assert_eq!(ppu.ppustatus & 0x20, 0x20);
+        ppu.clear_sprite_overflow();
+
+        // False negative : sprite 9 is on the line, but its tile byte is compared instead
+        let mut oam = sprites(&[5, 5, 5, 5, 5, 5, 5, 5, 100, 5]);
+        oam[9 * 4 + 1] = 200;
+        evaluate_sprites(&mut ppu, 10, &oam);
+        assert_eq!(ppu.ppustatus & 0x20, 0);
+    }
+
+    #[test]
+    fn sprite_limit() {
+        let _sdl_lock = lock_sdl();
+        let (mut ppu, _) = new_ppu(0, 0);
+        let oam = sprites(&[5, 5, 5, 5, 5, 100, 5, 5, 5, 5, 5]);
+
+        evaluate_sprites(&mut ppu, 10, &oam);
+        assert_eq!(ppu.secondary_oam_pointer, 8);
+        assert_eq!(ppu.secondary_oam[7 * 4..8 * 4], oam[8 * 4..9 * 4]);
+        assert_eq!(ppu.secondary_oam[8 * 4], 0xff);
+
+        // Extra sprites are copied after the 8 first ones, in OAM order
+        ppu.clear_sprite_overflow();
+        ppu.set_sprite_limit(false);
+        evaluate_sprites(&mut ppu, 10, &oam);
+        assert_eq!(ppu.secondary_oam_pointer, 10);
+        assert_eq!(ppu.secondary_oam[8 * 4..10 * 4], oam[9 * 4..11 * 4]);
+        assert_eq!(ppu.ppustatus & 0x20, 0x20);
+
+        // Nothing is copied below the limit
+        evaluate_sprites(&mut ppu, 10, &sprites(&[5; 3]));
+        assert_eq!(ppu.secondary_oam_pointer, 3);
+    }
+
+    #[test]
+    fn sprite_pattern_address() {
+        let _sdl_lock = lock_sdl();
+        let (mut ppu, _) = new_ppu(13, 0);
+        let address = |ppu: &mut Ppu, ppuctrl: u8, tile: u8, attribute: u8| {
+            ppu.ppuctrl = ppuctrl;
+            ppu.secondary_oam[..4].copy_from_slice(&[10, tile, attribute, 0]);
+            ppu.get_sprite_pattern_address(0)
+        };
+
+        // 8x8 : pattern table from PPUCTRL, row 3 of the sprite
+        assert_eq!(address(&mut ppu, 0x00, 0x05, 0x00), 0x0053);
+        assert_eq!(address(&mut ppu, 0x08, 0x05, 0x00), 0x1053);
+        assert_eq!(address(&mut ppu, 0x00, 0x05, 0x80), 0x0054);
+
+        // 8x16 : pattern table from tile bit 0, bottom tile from row 8
+        assert_eq!(address(&mut ppu, 0x20, 0x05, 0x00), 0x1043);
+        assert_eq!(address(&mut ppu, 0x28, 0x04, 0x00), 0x0043);
+        assert_eq!(address(&mut ppu, 0x20, 0x05, 0x80), 0x1054);
+        ppu.line = 21;
+        assert_eq!(address(&mut ppu, 0x20, 0x05, 0x00), 0x1053);
+        assert_eq!(address(&mut ppu, 0x20, 0x05, 0x80), 0x1044);
+
+        // Horizontal flipping reverses the pattern byte
+        ppu.cartridge
+            .borrow_mut()
+            .write_chr_rom(0x0053, 0b1100_0001);
+        ppu.secondary_oam[..4].copy_from_slice(&[10, 0x05, 0x40, 0]);
+        assert_eq!(ppu.read_sprite_pattern(0x0053, 0), 0b1000_0011);
+        ppu.secondary_oam[2] = 0x00;
+        assert_eq!(ppu.read_sprite_pattern(0x0053, 0), 0b1100_0001);
+    }
+
+    #[test]
+    fn background_fine_x() {
+        let _sdl_lock = lock_sdl();
+        let (mut ppu, _) = new_ppu(0, 20);
+        ppu.write_0x2001(0x0a);
+        ppu.bg_low_shift_register = 0b1001_0000_1000_0000;
+        ppu.bg_high_shift_register = 0b0001_0000_1000_0000;
+        ppu.bg_attribute_low_shift_register = 0b0001_0000_0000_0000;
+        ppu.bg_attribute_high_shift_register = 0b0011_0000_0000_0000;
+
+        let pixels: Vec<(u8, u8)> = (0..8)
+            .map(|fine_x| {
+                ppu.register_x = fine_x;
+                ppu.compute_bg_pixel()
+            })
+            .collect();
+        assert_eq!(pixels[0], (1, 0));
+        assert_eq!(pixels[2], (0, 2));
+        assert_eq!(pixels[3], (3, 3));
+        assert_eq!(pixels[7], (0, 0));
+
+        // Left column clipping
+        ppu.col = 5;
+        ppu.register_x = 3;
+        assert_eq!(ppu.compute_bg_pixel(), (3, 3));
+        ppu.write_0x2001(0x08);
+        assert_eq!(ppu.compute_bg_pixel(), (0, 0));
+    }
+
+    #[test]
+    fn palette_mirroring() {
+        assert_eq!(get_palette_address(0x3f00), 0x00);
+        assert_eq!(get_palette_address(0x3f04), 0x04);
+        assert_eq!(get_palette_address(0x3f11), 0x11);
+        assert_eq!(get_palette_address(0x3f10), 0x00);
+        assert_eq!(get_palette_address(0x3f1c), 0x0c);
+        assert_eq!(get_palette_address(0x3f3c), 0x0c);
+        assert_eq!(get_palette_address(0x3fe7), 0x07);
     }
 }
@@ -0,0 +1,218 @@
+//! NTSC video filter
+//! Encodes the indexed frame into the composite signal of the PPU and decodes it back like a TV would.
+//! Luma and chroma sharing the same signal give dot crawl, color fringing and the blending some games rely on
+
+use super::palette;
+use sdl2::pixels::Color;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Signal samples per NES pixel, at the 21.48 MHz master clock
+const SAMPLES_PER_PIXEL: usize = 8;
+/// Signal samples per color subcarrier cycle
+const SAMPLES_PER_CYCLE: usize = 12;
+/// Subcarrier phase shift between two scanlines of 341 dots
+const LINE_PHASE_SHIFT: usize = 341 * SAMPLES_PER_PIXEL % SAMPLES_PER_CYCLE;
+/// Width of the filtered output, 2.5 output pixels per NES pixel
+pub const NTSC_WIDTH: u32 = 640;
+
+/// Video connection presets
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum NtscPreset {
+    /// Luma and chroma mixed in one signal : dot crawl and color artifacts
+    Composite,
+    /// Luma and chroma on separate wires : sharp luma, blurred chroma, no artifacts
+    SVideo,
+    /// Palette colors scaled to the NTSC output width, without signal artifacts
+    Rgb,
+}
+
+impl FromStr for NtscPreset {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<NtscPreset, String> {
+        match value.to_lowercase().as_str() {
+            "composite" => Ok(NtscPreset::Composite),
+            "svideo" | "s-video" => Ok(NtscPreset::SVideo),
+            "rgb" => Ok(NtscPreset::Rgb),
+            _ => Err(format!("Unknown NTSC filter preset : {}", value)),
+        }
+    }
+}
+
+/// NTSC filter, with the signal of every pixel value precomputed
+pub struct NtscFilter {
+    preset: NtscPreset,
+    signal_table: Vec<[f64; SAMPLES_PER_CYCLE]>, // Signal of each 9 bits pixel value for each phase
+    luma_table: Vec<f64>,                        // Average signal of each 9 bits pixel value
+    cos_table: [f64; SAMPLES_PER_CYCLE],
+    sin_table: [f64; SAMPLES_PER_CYCLE],
+    signal: Vec<f64>, // Samples of the current line
+    luma: Vec<f64>,   // S-Video luma samples of the current line
+}
+
+impl NtscFilter {
+    /// Instantiate a filter for a preset
+    pub fn new(preset: NtscPreset) -> NtscFilter {
+        let signal_table: Vec<[f64; SAMPLES_PER_CYCLE]> = (0..palette::PALETTE_SIZE as u16)
+            .map(|pixel| {
+                let mut samples = [0.0; SAMPLES_PER_CYCLE];
+                for (phase, sample) in samples.iter_mut().enumerate() {
+                    *sample = palette::get_composite_signal(pixel, phase as u16);
+                }
+                samples
+            })
+            .collect();
+        let luma_table = signal_table
+            .iter()
+            .map(|samples| samples.iter().sum::<f64>() / SAMPLES_PER_CYCLE as f64)
+            .collect();
+        let angle = |phase: usize| PI * (phase as f64 + palette::COMPOSITE_HUE_OFFSET) / 6.0;
+        NtscFilter {
+            preset,
+            signal_table,
+            luma_table,
+            cos_table: std::array::from_fn(|phase| angle(phase).cos()),
+            sin_table: std::array::from_fn(|phase| angle(phase).sin()),
+            signal: vec![],
+            luma: vec![],
+        }
+    }
+
+    /// Filter a frame of 9 bits pixels into RGB24 rows of NTSC_WIDTH pixels
+    ///
+    /// The frame phase alternates between frames to reproduce dot crawl
+    pub fn apply(
+        &mut self,
+        frame: &[u16],
+        width: usize,
+        frame_phase: usize,
+        palette: &[Color],
+        output: &mut [u8],
+        pitch: usize,
+    ) {
+        let sample_count = width * SAMPLES_PER_PIXEL;
+        for (y, line) in frame.chunks_exact(width).enumerate() {
+            let row = &mut output[y * pitch..y * pitch + 3 * NTSC_WIDTH as usize];
+            if self.preset == NtscPreset::Rgb {
+                for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+                    let color = palette[line[x * width / NTSC_WIDTH as usize] as usize];
+                    rgb.copy_from_slice(&[color.r, color.g, color.b]);
+                }
+                continue;
+            }
+
+            let line_phase = (frame_phase + y * LINE_PHASE_SHIFT) % SAMPLES_PER_CYCLE;
+            self.encode_line(line, line_phase);
+            for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+                let center = (2 * x + 1) * sample_count / (2 * NTSC_WIDTH as usize);
+                let color = self.decode_sample(center, line_phase);
+                rgb.copy_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+    }
+
+    /// Generate the signal samples of a line
+    fn encode_line(&mut self, line: &[u16], line_phase: usize) {
+        self.signal.clear();
+        self.luma.clear();
+        for (x, pixel) in line.iter().enumerate() {
+            let pixel = *pixel as usize % palette::PALETTE_SIZE;
+            for sample in 0..SAMPLES_PER_PIXEL {
+                let phase = (x * SAMPLES_PER_PIXEL + sample + line_phase) % SAMPLES_PER_CYCLE;
+                self.signal.push(self.signal_table[pixel][phase]);
+                self.luma.push(self.luma_table[pixel]);
+            }
+        }
+    }
+
+    /// Decode the color around a sample of the current line
+    fn decode_sample(&self, center: usize, line_phase: usize) -> Color {
+        let last_window = self.signal.len() - SAMPLES_PER_CYCLE;
+        let start = center
+            .saturating_sub(SAMPLES_PER_CYCLE / 2)
+            .min(last_window);
+        let window = start..start + SAMPLES_PER_CYCLE;
+
+        let y = if self.preset == NtscPreset::Composite {
+            // A whole subcarrier cycle is averaged : chroma cancels out on flat colors only
+            self.signal[window.clone()].iter().sum::<f64>() / SAMPLES_PER_CYCLE as f64
+        } else {
+            // Luma has its own wire and keeps its sharpness
+            let luma_start = center.saturating_sub(2).min(self.luma.len() - 4);
+            self.luma[luma_start..luma_start + 4].iter().sum::<f64>() / 4.0
+        };
+
+        let (mut i, mut q) = (0.0, 0.0);
+        for index in window {
+            let mut sample = self.signal[index];
+            if self.preset == NtscPreset::SVideo {
+                sample -= self.luma[index]; // Chroma only wire
+            }
+            let phase = (index + line_phase) % SAMPLES_PER_CYCLE;
+            i += sample * self.cos_table[phase];
+            q += sample * self.sin_table[phase];
+        }
+        let saturation = palette::COMPOSITE_SATURATION / 6.0;
+        palette::yiq_to_rgb(y, i * saturation, q * saturation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_color_matches_composite_palette() {
+        // A flat color has no artifacts and decodes to the generated composite palette
+        let colors = palette::get_builtin_palette(palette::BuiltinPalette::Composite);
+        for preset in [NtscPreset::Composite, NtscPreset::SVideo] {
+            let mut filter = NtscFilter::new(preset);
+            for pixel in [0x16u16, 0x2a, 0x12, (0b101 << 6) | 0x21] {
+                let frame = vec![pixel; 256 * 2];
+                let mut output = vec![0; 3 * NTSC_WIDTH as usize * 2];
+                filter.apply(
+                    &frame,
+                    256,
+                    0,
+                    &colors,
+                    &mut output,
+                    3 * NTSC_WIDTH as usize,
+                );
+                let expected = colors[pixel as usize];
+                let offset = 3 * (NTSC_WIDTH as usize + 320);
+                for (value, expected) in output[offset..offset + 3]
+                    .iter()
+                    .zip([expected.r, expected.g, expected.b])
+                {
+                    assert!((*value as i16 - expected as i16).abs() <= 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn composite_artifacts() {
+        // Alternating luma columns create colors on composite only
+        let colors = palette::get_builtin_palette(palette::BuiltinPalette::Composite);
+        let frame: Vec<u16> = (0..256)
+            .map(|x| if x % 2 == 0 { 0x0f } else { 0x30 })
+            .collect();
+        let is_gray = |preset: NtscPreset| {
+            let mut filter = NtscFilter::new(preset);
+            let mut output = vec![0; 3 * NTSC_WIDTH as usize];
+            filter.apply(
+                &frame,
+                256,
+                0,
+                &colors,
+                &mut output,
+                3 * NTSC_WIDTH as usize,
+            );
+            let rgb = &output[3 * 320..3 * 321];
+            rgb[0].abs_diff(rgb[1]) < 8 && rgb[1].abs_diff(rgb[2]) < 8
+        };
+        assert!(!is_gray(NtscPreset::Composite));
+        assert!(is_gray(NtscPreset::SVideo));
+    }
+}
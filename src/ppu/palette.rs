@@ -0,0 +1,417 @@
+//! NES palettes
+//! A pixel is a 9 bits value : emphasis bits (PPUMASK bits 5 to 7) then the 6 bits color index
+
+use sdl2::pixels::Color;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Number of colors in an emphasis-aware palette
+pub const PALETTE_SIZE: usize = 512;
+
+/// Attenuation of the color channels that are not emphasized
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+
+/// Composite signal voltages for the 4 luma levels, low then high part of the wave
+const SIGNAL_LEVELS: [f64; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f64 = 0.518;
+const SIGNAL_WHITE: f64 = 1.962;
+/// Decoder settings giving colors close to the default palette
+pub const COMPOSITE_HUE_OFFSET: f64 = 4.0; // In 1/12th of color burst cycle
+pub const COMPOSITE_SATURATION: f64 = 0.7;
+const COMPOSITE_GAMMA: f64 = 2.2 / 1.8;
+
+/// Built-in palettes
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BuiltinPalette {
+    /// Default palette of the 2C02 NTSC PPU
+    Ntsc2C02,
+    /// 2C02G palette computed from the signal levels measured on hardware
+    Measured2C02,
+    /// 2C02 palette generated by decoding the composite signal
+    Composite,
+    /// FirebrandX Smooth palette
+    Smooth,
+    /// FirebrandX NES Classic palette
+    Fbx,
+    /// 2C03 RGB PPU, used by PlayChoice-10 and some Vs. System boards
+    Rgb2C03,
+    /// RP2C04-0001 RGB PPU of Vs. System boards, with its scrambled palette
+    Rgb2C04Variant1,
+    /// RP2C04-0003 RGB PPU of Vs. System boards, with its scrambled palette
+    Rgb2C04Variant3,
+    /// 2C05 RGB PPU, used by some Vs. System boards. It has the 2C03 colors, only its $2000/$2001 registers are swapped
+    Rgb2C05,
+}
+
+impl FromStr for BuiltinPalette {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<BuiltinPalette, String> {
+        match value.to_lowercase().as_str() {
+            "2c02" | "default" => Ok(BuiltinPalette::Ntsc2C02),
+            "2c02-measured" => Ok(BuiltinPalette::Measured2C02),
+            "composite" => Ok(BuiltinPalette::Composite),
+            "smooth" => Ok(BuiltinPalette::Smooth),
+            "fbx" => Ok(BuiltinPalette::Fbx),
+            "2c03" => Ok(BuiltinPalette::Rgb2C03),
+            "2c04-0001" => Ok(BuiltinPalette::Rgb2C04Variant1),
+            "2c04-0003" => Ok(BuiltinPalette::Rgb2C04Variant3),
+            "2c05" => Ok(BuiltinPalette::Rgb2C05),
+            _ => Err(format!("Unknown palette : {}", value)),
+        }
+    }
+}
+
+/// Load a 512 colors palette, either a built-in one by name or a .pal file
+pub fn load_palette(name: &str) -> Result<Vec<Color>, String> {
+    if let Ok(palette) = name.parse::<BuiltinPalette>() {
+        return Ok(get_builtin_palette(palette));
+    }
+    let bytes = std::fs::read(name).map_err(|error| format!("Cannot read {} : {}", name, error))?;
+    parse_palette_file(&bytes)
+}
+
+/// Get the 512 colors of a built-in palette
+pub fn get_builtin_palette(palette: BuiltinPalette) -> Vec<Color> {
+    match palette {
+        BuiltinPalette::Ntsc2C02 => build_emphasis_palette(&PALETTE),
+        BuiltinPalette::Measured2C02 => build_emphasis_palette(&to_colors(&MEASURED_2C02_PALETTE)),
+        BuiltinPalette::Composite => (0..PALETTE_SIZE as u16).map(decode_composite).collect(),
+        BuiltinPalette::Smooth => build_emphasis_palette(&to_colors(&SMOOTH_PALETTE)),
+        BuiltinPalette::Fbx => build_emphasis_palette(&to_colors(&FBX_PALETTE)),
+        BuiltinPalette::Rgb2C03 => build_rgb_palette(&RGB_2C03_PALETTE_LEVELS),
+        BuiltinPalette::Rgb2C04Variant1 => build_rgb_palette(&RGB_2C04_0001_PALETTE_LEVELS),
+        BuiltinPalette::Rgb2C04Variant3 => build_rgb_palette(&RGB_2C04_0003_PALETTE_LEVELS),
+        BuiltinPalette::Rgb2C05 => build_rgb_palette(&RGB_2C03_PALETTE_LEVELS),
+    }
+}
+
+/// Convert 0xRRGGBB values to colors
+fn to_colors(palette: &[u32; 64]) -> [Color; 64] {
+    palette.map(|rgb| Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// Build the 512 colors palette of an RGB PPU from its 3 bits per channel levels
+fn build_rgb_palette(levels: &[u16; 64]) -> Vec<Color> {
+    let scale = |level: u16| ((level & 0b111) * 255 / 7) as u8;
+    let palette =
+        levels.map(|levels| Color::RGB(scale(levels >> 6), scale(levels >> 3), scale(levels)));
+    build_rgb_emphasis_palette(&palette)
+}
+
+/// Parse a .pal file : 64 colors, or 512 colors including emphasis, 3 bytes RGB each
+pub fn parse_palette_file(bytes: &[u8]) -> Result<Vec<Color>, String> {
+    let colors: Vec<Color> = bytes
+        .chunks_exact(3)
+        .map(|rgb| Color::RGB(rgb[0], rgb[1], rgb[2]))
+        .collect();
+    match bytes.len() {
+        192 => Ok(build_emphasis_palette(&colors.try_into().unwrap())),
+        1536 => Ok(colors),
+        length => Err(format!(
+            "Invalid palette file size {}, expecting 192 or 1536 bytes",
+            length
+        )),
+    }
+}
+
+/// Build the 512 colors palette from the 64 base colors
+///
+/// Emphasis bit 0 emphasizes red, bit 1 green and bit 2 blue, by darkening the other channels
+pub fn build_emphasis_palette(palette: &[Color; 64]) -> Vec<Color> {
+    let mut colors = Vec::with_capacity(PALETTE_SIZE);
+    for emphasis in 0..8u8 {
+        for (index, color) in palette.iter().enumerate() {
+            // Black columns are not affected by emphasis
+            if emphasis == 0 || index & 0x0f >= 0x0e {
+                colors.push(*color);
+                continue;
+            }
+            let attenuate = |value: u8, bit: u8| {
+                if emphasis & bit == 0 {
+                    (value as f64 * EMPHASIS_ATTENUATION).round() as u8
+                } else {
+                    value
+                }
+            };
+            colors.push(Color::RGB(
+                attenuate(color.r, 0b001),
+                attenuate(color.g, 0b010),
+                attenuate(color.b, 0b100),
+            ));
+        }
+    }
+    colors
+}
+
+/// Build the 512 colors palette of an RGB PPU. Emphasis bits set their channel to full brightness
+fn build_rgb_emphasis_palette(palette: &[Color; 64]) -> Vec<Color> {
+    let mut colors = Vec::with_capacity(PALETTE_SIZE);
+    for emphasis in 0..8u8 {
+        for color in palette.iter() {
+            let emphasize = |value: u8, bit: u8| if emphasis & bit != 0 { 0xff } else { value };
+            colors.push(Color::RGB(
+                emphasize(color.r, 0b001),
+                emphasize(color.g, 0b010),
+                emphasize(color.b, 0b100),
+            ));
+        }
+    }
+    colors
+}
+
+/// Composite signal level of a pixel at one of the 12 phases of the color burst cycle, 0 being black and 1 white
+pub fn get_composite_signal(pixel: u16, phase: u16) -> f64 {
+    let color = pixel & 0x0f;
+    let emphasis = pixel >> 6;
+    let level = if color > 0x0d { 1 } else { (pixel >> 4) & 0b11 } as usize;
+    let mut low = SIGNAL_LEVELS[level];
+    let mut high = SIGNAL_LEVELS[4 + level];
+    if color == 0 {
+        low = high;
+    } else if color > 0x0c {
+        high = low;
+    }
+    let is_in_color_phase = |color: u16| (color + phase) % 12 < 6;
+    let mut signal = if is_in_color_phase(color) { high } else { low };
+    if (emphasis & 0b001 != 0 && is_in_color_phase(0))
+        || (emphasis & 0b010 != 0 && is_in_color_phase(4))
+        || (emphasis & 0b100 != 0 && is_in_color_phase(8))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+/// Decode the composite signal of a pixel into an RGB color, through the YIQ color space
+fn decode_composite(pixel: u16) -> Color {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let signal = get_composite_signal(pixel, phase);
+        let angle = PI * (phase as f64 + COMPOSITE_HUE_OFFSET) / 6.0;
+        y += signal / 12.0;
+        i += signal * angle.cos() * COMPOSITE_SATURATION / 6.0;
+        q += signal * angle.sin() * COMPOSITE_SATURATION / 6.0;
+    }
+    yiq_to_rgb(y, i, q)
+}
+
+/// Convert a decoded YIQ color to RGB, with the TV gamma
+pub fn yiq_to_rgb(y: f64, i: f64, q: f64) -> Color {
+    let to_byte = |value: f64| {
+        (255.0 * value.max(0.0).powf(COMPOSITE_GAMMA))
+            .min(255.0)
+            .round() as u8
+    };
+    Color::RGB(
+        to_byte(y + 0.946882 * i + 0.623557 * q),
+        to_byte(y - 0.274788 * i - 0.635691 * q),
+        to_byte(y - 1.108545 * i + 1.709007 * q),
+    )
+}
+
+/// 2C03 RGB PPU palette, 3 bits per channel as octal digits
+const RGB_2C03_PALETTE_LEVELS: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022,
+    0o000, 0o000, 0o000, 0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140,
+    0o040, 0o053, 0o044, 0o000, 0o000, 0o000, 0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740,
+    0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000, 0o777, 0o567, 0o657, 0o757,
+    0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+/// RP2C04-0001 RGB PPU palette, 3 bits per channel as octal digits
+const RGB_2C04_0001_PALETTE_LEVELS: [u16; 64] = [
+    0o755, 0o637, 0o700, 0o447, 0o044, 0o120, 0o222, 0o704, 0o777, 0o333, 0o750, 0o503, 0o403,
+    0o660, 0o320, 0o777, 0o357, 0o653, 0o310, 0o360, 0o467, 0o657, 0o764, 0o027, 0o760, 0o276,
+    0o000, 0o200, 0o666, 0o444, 0o707, 0o014, 0o003, 0o567, 0o757, 0o070, 0o077, 0o022, 0o053,
+    0o507, 0o000, 0o420, 0o747, 0o510, 0o407, 0o006, 0o740, 0o000, 0o000, 0o140, 0o555, 0o031,
+    0o572, 0o326, 0o770, 0o630, 0o020, 0o036, 0o040, 0o111, 0o773, 0o737, 0o430, 0o473,
+];
+
+/// RP2C04-0003 RGB PPU palette, 3 bits per channel as octal digits. Same colors as the RP2C04-0001 in another order
+const RGB_2C04_0003_PALETTE_LEVELS: [u16; 64] = [
+    0o430, 0o326, 0o044, 0o660, 0o000, 0o755, 0o014, 0o630, 0o555, 0o310, 0o070, 0o003, 0o764,
+    0o770, 0o040, 0o572, 0o737, 0o200, 0o027, 0o747, 0o000, 0o222, 0o510, 0o740, 0o653, 0o053,
+    0o447, 0o140, 0o403, 0o000, 0o473, 0o357, 0o503, 0o031, 0o420, 0o006, 0o407, 0o507, 0o333,
+    0o704, 0o022, 0o666, 0o036, 0o020, 0o111, 0o773, 0o444, 0o707, 0o757, 0o777, 0o320, 0o700,
+    0o760, 0o276, 0o777, 0o467, 0o000, 0o750, 0o637, 0o567, 0o360, 0o657, 0o077, 0o120,
+];
+
+/// 2C02G palette computed from the measured signal levels, as 0xRRGGBB
+const MEASURED_2C02_PALETTE: [u32; 64] = [
+    0x666666, 0x002a88, 0x1412a7, 0x3b00a4, 0x5c007e, 0x6e0040, 0x6c0600, 0x561d00, 0x333500,
+    0x0b4800, 0x005200, 0x004f08, 0x00404d, 0x000000, 0x000000, 0x000000, 0xadadad, 0x155fd9,
+    0x4240ff, 0x7527fe, 0xa01acc, 0xb71e7b, 0xb53120, 0x994e00, 0x6b6d00, 0x388700, 0x0c9300,
+    0x008f32, 0x007c8d, 0x000000, 0x000000, 0x000000, 0xfffeff, 0x64b0ff, 0x9290ff, 0xc676ff,
+    0xf36aff, 0xfe6ecc, 0xfe8170, 0xea9e22, 0xbcbe00, 0x88d800, 0x5ce430, 0x45e082, 0x48cdde,
+    0x4f4f4f, 0x000000, 0x000000, 0xfffeff, 0xc0dfff, 0xd3d2ff, 0xe8c8ff, 0xfbc2ff, 0xfec4ea,
+    0xfeccc5, 0xf7d8a5, 0xe4e594, 0xcfef96, 0xbdf4ab, 0xb3f3cc, 0xb5ebf2, 0xb8b8b8, 0x000000,
+    0x000000,
+];
+
+/// FirebrandX Smooth palette, as 0xRRGGBB
+const SMOOTH_PALETTE: [u32; 64] = [
+    0x6a6d6a, 0x001380, 0x1e008a, 0x39007a, 0x550056, 0x5a0018, 0x4f1000, 0x3d1c00, 0x253200,
+    0x003d00, 0x004000, 0x003924, 0x002e55, 0x000000, 0x000000, 0x000000, 0xb9bcb9, 0x1850c7,
+    0x4b30e3, 0x7322d6, 0x951fa9, 0x9d285c, 0x983700, 0x7f4c00, 0x5e6400, 0x227700, 0x027e02,
+    0x007645, 0x006e8a, 0x000000, 0x000000, 0x000000, 0xffffff, 0x68a6ff, 0x8c9cff, 0xb586ff,
+    0xd975fd, 0xe377b9, 0xe58d68, 0xd49d29, 0xb3af0c, 0x7bc211, 0x55ca47, 0x46cb81, 0x47c1c5,
+    0x4a4d4a, 0x000000, 0x000000, 0xffffff, 0xcceaff, 0xdddeff, 0xecdaff, 0xf8d7fe, 0xfcd6f5,
+    0xfddbcf, 0xf9e7b5, 0xf1f0aa, 0xdafaa9, 0xc9ffbc, 0xc3fbd7, 0xc4f6f6, 0xbec1be, 0x000000,
+    0x000000,
+];
+
+/// FirebrandX NES Classic palette, as 0xRRGGBB
+const FBX_PALETTE: [u32; 64] = [
+    0x616161, 0x000088, 0x1f0d99, 0x371379, 0x561260, 0x5d0010, 0x520e00, 0x3a2308, 0x21350c,
+    0x0d410e, 0x174417, 0x003a1f, 0x002f57, 0x000000, 0x000000, 0x000000, 0xaaaaaa, 0x0d4dc4,
+    0x4b24de, 0x6912cf, 0x9014ad, 0x9d1c48, 0x923404, 0x735005, 0x5d6913, 0x167a11, 0x138008,
+    0x127649, 0x1c6691, 0x000000, 0x000000, 0x000000, 0xfcfcfc, 0x639afc, 0x8a7efc, 0xb06afc,
+    0xdd6df2, 0xe771ab, 0xe38658, 0xcc9e22, 0xa8b100, 0x72c100, 0x5acd4e, 0x34c28e, 0x4fbece,
+    0x424242, 0x000000, 0x000000, 0xfcfcfc, 0xbed4fc, 0xcacafc, 0xd9c4fc, 0xecc1fc, 0xfac3e7,
+    0xf7cec3, 0xe2cda7, 0xdadb9c, 0xc8e39e, 0xbfe5b8, 0xb2ebc8, 0xb7e5eb, 0xacacac, 0x000000,
+    0x000000,
+];
+
+/// NES color palette
+pub const PALETTE: [Color; 64] = [
+    Color::RGB(84, 84, 84),
+    Color::RGB(0, 30, 116),
+    Color::RGB(8, 16, 144),
+    Color::RGB(48, 0, 136),
+    Color::RGB(68, 0, 100),
+    Color::RGB(92, 0, 48),
+    Color::RGB(84, 4, 0),
+    Color::RGB(60, 24, 0),
+    Color::RGB(32, 42, 0),
+    Color::RGB(8, 58, 0),
+    Color::RGB(0, 64, 0),
+    Color::RGB(0, 60, 0),
+    Color::RGB(0, 50, 60),
+    Color::RGB(0, 0, 0),
+    Color::RGB(0, 0, 0),
+    Color::RGB(0, 0, 0),
+    Color::RGB(152, 150, 152),
+    Color::RGB(8, 76, 196),
+    Color::RGB(48, 50, 236),
+    Color::RGB(92, 30, 228),
+    Color::RGB(136, 20, 176),
+    Color::RGB(160, 20, 100),
+    Color::RGB(152, 34, 32),
+    Color::RGB(120, 60, 0),
+    Color::RGB(84, 90, 0),
+    Color::RGB(40, 114, 0),
+    Color::RGB(8, 124, 0),
+    Color::RGB(0, 118, 40),
+    Color::RGB(0, 102, 120),
+    Color::RGB(0, 0, 0),
+    Color::RGB(0, 0, 0),
+    Color::RGB(0, 0, 0),
+    Color::RGB(236, 238, 236),
+    Color::RGB(76, 154, 236),
+    Color::RGB(120, 124, 236),
+    Color::RGB(176, 98, 236),
+    Color::RGB(228, 84, 236),
+    Color::RGB(236, 88, 180),
+    Color::RGB(236, 106, 100),
+    Color::RGB(212, 136, 32),
+    Color::RGB(160, 170, 0),
+    Color::RGB(116, 196, 0),
+    Color::RGB(76, 208, 32),
+    Color::RGB(56, 204, 108),
+    Color::RGB(56, 180, 204),
+    Color::RGB(60, 60, 60),
+    Color::RGB(0, 0, 0),
+    Color::RGB(0, 0, 0),
+    Color::RGB(236, 238, 236),
+    Color::RGB(168, 204, 236),
+    Color::RGB(188, 188, 236),
+    Color::RGB(212, 178, 236),
+    Color::RGB(236, 174, 236),
+    Color::RGB(236, 174, 212),
+    Color::RGB(236, 180, 176),
+    Color::RGB(228, 196, 144),
+    Color::RGB(204, 210, 120),
+    Color::RGB(180, 222, 120),
+    Color::RGB(168, 226, 144),
+    Color::RGB(152, 226, 180),
+    Color::RGB(160, 214, 228),
+    Color::RGB(160, 162, 160),
+    Color::RGB(0, 0, 0),
+    Color::RGB(0, 0, 0),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emphasis_palette() {
+        let colors = build_emphasis_palette(&PALETTE);
+        assert_eq!(colors.len(), PALETTE_SIZE);
+        assert_eq!(colors[0x30], PALETTE[0x30]);
+        // Red emphasis darkens green and blue only
+        let color = colors[(0b001 << 6) | 0x30];
+        assert_eq!(color.r, PALETTE[0x30].r);
+        assert!(color.g < PALETTE[0x30].g && color.b < PALETTE[0x30].b);
+        // Black is not affected
+        assert_eq!(colors[(0b111 << 6) | 0x0f], PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn builtin_palettes() {
+        let names = [
+            "2c02",
+            "2c02-measured",
+            "composite",
+            "smooth",
+            "fbx",
+            "2c03",
+            "2c04-0001",
+            "2c04-0003",
+            "2c05",
+        ];
+        for name in names {
+            assert_eq!(load_palette(name).unwrap().len(), PALETTE_SIZE);
+        }
+        assert_ne!(
+            load_palette("smooth").unwrap(),
+            load_palette("fbx").unwrap()
+        );
+        assert_ne!(
+            load_palette("2c02").unwrap(),
+            load_palette("2c02-measured").unwrap()
+        );
+        assert!(load_palette("2c04").is_err());
+        // Generated palette is close to the default one : 0x16 is red, 0x12 blue and 0x1a green
+        let colors = get_builtin_palette(BuiltinPalette::Composite);
+        assert!(colors[0x16].r > colors[0x16].g && colors[0x16].r > colors[0x16].b);
+        assert!(colors[0x12].b > colors[0x12].r && colors[0x12].b > colors[0x12].g);
+        assert!(colors[0x1a].g > colors[0x1a].r && colors[0x1a].g > colors[0x1a].b);
+        assert_eq!(colors[0x0d], Color::RGB(0, 0, 0));
+        // RGB PPU emphasis saturates the channel
+        let colors = get_builtin_palette(BuiltinPalette::Rgb2C03);
+        assert_eq!(colors[(0b100 << 6) | 0x0f], Color::RGB(0, 0, 255));
+        // 2C04 variants scramble the same colors
+        let mut first = RGB_2C04_0001_PALETTE_LEVELS;
+        let mut third = RGB_2C04_0003_PALETTE_LEVELS;
+        assert_ne!(first, third);
+        first.sort();
+        third.sort();
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn palette_files() {
+        let bytes: Vec<u8> = (0..192).map(|value| value as u8).collect();
+        let colors = parse_palette_file(&bytes).unwrap();
+        assert_eq!(colors.len(), PALETTE_SIZE);
+        assert_eq!(colors[1], Color::RGB(3, 4, 5));
+        let bytes: Vec<u8> = (0..1536).map(|value| value as u8).collect();
+        assert_eq!(
+            parse_palette_file(&bytes).unwrap()[511],
+            Color::RGB(253, 254, 255)
+        );
+        assert!(parse_palette_file(&[0; 100]).is_err());
+    }
+}
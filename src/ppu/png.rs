@@ -0,0 +1,83 @@
+//! Minimal PNG encoder
+//! Writes 8 bits RGB images with uncompressed deflate blocks, pixel exact and without dependencies
+
+use crate::cartridge::crc32;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+/// Maximum length of a stored deflate block
+const MAX_BLOCK_SIZE: usize = 0xffff;
+
+/// Encode RGB24 pixels, without row padding, into a PNG file content
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), (width * height * 3) as usize);
+    let mut png = PNG_SIGNATURE.to_vec();
+
+    let mut header = vec![];
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits depth, RGB, deflate, no filter, no interlace
+    write_chunk(&mut png, b"IHDR", &header);
+
+    // Each row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity(rgb.len() + height as usize);
+    for row in rgb.chunks_exact(width as usize * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_store(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Append a chunk : length, type, data and CRC of type and data
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    let crc = crc32(chunk_type.iter().chain(data.iter()));
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wrap data in a zlib stream made of stored deflate blocks
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01]; // Deflate, 32k window, no preset dictionary
+    let block_count = data.len().div_ceil(MAX_BLOCK_SIZE).max(1);
+    for (index, block) in data.chunks(MAX_BLOCK_SIZE).enumerate() {
+        let is_last = index + 1 == block_count;
+        stream.push(is_last as u8);
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    if data.is_empty() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// Adler-32 checksum of the zlib stream
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_structure() {
+        let png = encode_png(2, 1, &[255, 0, 0, 0, 0, 255]);
+        assert_eq!(png[0..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // IEND CRC is a well known constant
+        assert_eq!(png[png.len() - 4..], [0xae, 0x42, 0x60, 0x82]);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+}
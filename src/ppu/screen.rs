@@ -1,19 +1,34 @@
 //! Screen component
 
-use sdl2::pixels::{Color, Palette, PixelFormatEnum};
+use super::ntsc_filter::{NtscFilter, NtscPreset, NTSC_WIDTH};
+use super::palette;
+use super::png;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::surface::Surface;
 use sdl2::video::Window;
 use std::cell::RefCell;
 use std::rc::Rc;
 pub struct Screen<'a> {
-    canvas: Canvas<Window>,
+    sdl_context: Rc<RefCell<sdl2::Sdl>>,
+    canvas: Option<Canvas<Window>>, // No window when headless
+    is_headless: bool,
     surface: Surface<'a>,
+    palette: Vec<Color>,
+    frame: Vec<u16>, // 9 bits pixel values of the frame, as given by the PPU
+    presented_frame: Vec<u16>, // Last complete frame
+    ntsc_filter: Option<NtscFilter>,
+    ntsc_surface: Surface<'a>,
+    frame_phase: usize, // Color subcarrier phase of the frame, for dot crawl
+    overlay: Vec<(u8, u8, Color)>, // Markers drawn over the frame
     g_scaling_mode: ScalingMode,
 }
 
 const SCREEN_WIDTH: u32 = 256;
 const SCREEN_HEIGHT: u32 = 240;
+/// Lines hidden by most NTSC TVs at the top and the bottom of the frame
+const OVERSCAN_LINES: u32 = 8;
 
 #[derive(PartialEq, Clone, Copy)]
 enum ScalingMode {
@@ -26,12 +41,58 @@ enum ScalingMode {
 }
 
 pub const TEXTURE_ASPECT_RATIO: f64 = SCREEN_WIDTH as f64 / SCREEN_HEIGHT as f64;
+/// Aspect ratio of the NTSC filter output, which has the 8:7 pixel aspect ratio of a TV
+pub const NTSC_ASPECT_RATIO: f64 = TEXTURE_ASPECT_RATIO * 8.0 / 7.0;
 
 impl Screen<'_> {
     /// Instantiate Screen component
     pub fn new(sdl_context: Rc<RefCell<sdl2::Sdl>>) -> Screen<'static> {
+        let _surface = Surface::new(SCREEN_WIDTH, SCREEN_HEIGHT, PixelFormatEnum::RGB24).unwrap();
+        let _ntsc_surface =
+            Surface::new(NTSC_WIDTH, SCREEN_HEIGHT, PixelFormatEnum::RGB24).unwrap();
+
+        Screen {
+            sdl_context,
+            canvas: None,
+            is_headless: false,
+            surface: _surface,
+            palette: palette::build_emphasis_palette(&palette::PALETTE),
+            frame: vec![0; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+            presented_frame: vec![0; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+            ntsc_filter: None,
+            ntsc_surface: _ntsc_surface,
+            frame_phase: 0,
+            overlay: vec![],
+            g_scaling_mode: ScalingMode::ScalingModeAspectFit,
+        }
+    }
+
+    /// Find SDL GL Driver to initiate SDL window
+    fn find_sdl_gl_driver() -> Option<u32> {
+        for (index, item) in sdl2::render::drivers().enumerate() {
+            if item.name == "opengl" {
+                return Some(index as u32);
+            }
+        }
+        None
+    }
+
+    /// Start the Screen component, opening the window unless headless
+    pub fn start(&mut self) {
+        if !self.is_headless {
+            self.canvas = Some(self.create_canvas());
+            self.update_window_viewport();
+        }
+        if let Some(canvas) = self.canvas.as_mut() {
+            canvas.clear();
+        }
+        self.present();
+    }
+
+    /// Create the window and its canvas
+    fn create_canvas(&self) -> Canvas<Window> {
         let scale = 3;
-        let _video_subsystem = sdl_context.borrow_mut().video().unwrap();
+        let _video_subsystem = self.sdl_context.borrow_mut().video().unwrap();
         let _window = _video_subsystem
             .window("Window", SCREEN_WIDTH, SCREEN_HEIGHT)
             .opengl() // this line DOES NOT enable opengl, but allows you to create/get an OpenGL context from your window.
@@ -43,11 +104,6 @@ impl Screen<'_> {
             .build()
             .unwrap();
 
-        let mut _surface =
-            Surface::new(SCREEN_WIDTH, SCREEN_HEIGHT, PixelFormatEnum::Index8).unwrap();
-        let palette = Palette::with_colors(&PALETTE).unwrap();
-        _surface.set_palette(&palette).unwrap();
-
         _canvas
             .window_mut()
             .set_size(
@@ -55,125 +111,142 @@ impl Screen<'_> {
                 (3 * SCREEN_HEIGHT).try_into().unwrap(),
             )
             .unwrap();
+        _canvas
+    }
 
-        Screen {
-            canvas: _canvas,
-            surface: _surface,
-            g_scaling_mode: ScalingMode::ScalingModeAspectFit,
-        }
+    /// Render frames in memory only, without window. Must be called before start
+    pub fn set_headless(&mut self) {
+        self.is_headless = true;
     }
 
-    /// Find SDL GL Driver to initiate SDL window
-    fn find_sdl_gl_driver() -> Option<u32> {
-        for (index, item) in sdl2::render::drivers().enumerate() {
-            if item.name == "opengl" {
-                return Some(index as u32);
-            }
-        }
-        None
+    /// Replace the 512 colors palette
+    pub fn set_palette(&mut self, palette: Vec<Color>) {
+        self.palette = palette;
     }
 
-    /// Start the Screen component
-    pub fn start(&mut self) {
-        self.canvas.clear();
-        self.present();
+    /// Enable the NTSC filter with a preset, or disable it
+    pub fn set_ntsc_filter(&mut self, preset: Option<NtscPreset>) {
+        self.ntsc_filter = preset.map(NtscFilter::new);
+        self.update_window_viewport();
     }
 
-    pub fn update_pixel(&mut self, x: u8, y: u8, color: u8) {
-        let address = x as usize + y as usize * SCREEN_WIDTH as usize;
-        self.surface.without_lock_mut().unwrap()[address] = color;
+    /// Get the RGB color of a 9 bits emphasis and color index value
+    pub fn get_color(&self, color: u16) -> Color {
+        self.palette[color as usize % palette::PALETTE_SIZE]
     }
 
-    /// Refresh the windows with the buffered canvas
-    pub fn present(&mut self) {
-        self.canvas.clear();
-        let creator = self.canvas.texture_creator();
-        let texture = self.surface.as_texture(&creator).unwrap();
+    /// Set the markers drawn over the next presented frames, as frame position and color
+    pub fn set_overlay(&mut self, overlay: Vec<(u8, u8, Color)>) {
+        self.overlay = overlay;
+    }
 
-        self.canvas
-            .copy(&texture, None, self.canvas.viewport())
-            .unwrap();
+    /// Set a pixel from its 9 bits emphasis and color index value
+    pub fn update_pixel(&mut self, x: u8, y: u8, color: u16) {
+        self.frame[x as usize + y as usize * SCREEN_WIDTH as usize] = color;
+        let color = self.palette[color as usize % palette::PALETTE_SIZE];
+        let address = 3 * x as usize + y as usize * self.surface.pitch() as usize;
+        let pixels = self.surface.without_lock_mut().unwrap();
+        pixels[address] = color.r;
+        pixels[address + 1] = color.g;
+        pixels[address + 2] = color.b;
+    }
 
-        self.canvas.present();
+    /// Get the last complete frame, as 9 bits emphasis and color index values
+    pub fn get_frame(&self) -> &[u16] {
+        &self.presented_frame
     }
 
-    pub fn update_window_viewport(&mut self) {
-        let (window_height, window_width) = self.canvas.window().size();
-
-        // If the scaling mode is fullscreen, use the window size
-        if self.g_scaling_mode == ScalingMode::ScalingModeFullscreen {
-            self.canvas.viewport().set_x(0);
-            self.canvas.viewport().set_y(0);
-            self.canvas.viewport().set_width(window_width);
-            self.canvas.viewport().set_height(window_height);
-            return;
+    /// Get the last complete frame as RGB24 pixels, without NTSC filter
+    pub fn get_frame_rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.presented_frame.len() * 3);
+        for pixel in self.presented_frame.iter() {
+            let color = self.palette[*pixel as usize % palette::PALETTE_SIZE];
+            rgb.extend_from_slice(&[color.r, color.g, color.b]);
         }
+        rgb
+    }
 
-        let texture_aspect_ratio;
-        if self.g_scaling_mode == ScalingMode::ScalingModeAspectCorrect {
-            texture_aspect_ratio = 4 as f64 / 3 as f64;
-        } else {
-            texture_aspect_ratio = TEXTURE_ASPECT_RATIO;
+    /// Save the last complete frame to a PNG file, 256x240 or 256x224 when the overscan is cropped
+    pub fn save_screenshot(&self, file_name: &str, crop_overscan: bool) -> std::io::Result<()> {
+        let mut rgb = self.get_frame_rgb();
+        let mut height = SCREEN_HEIGHT;
+        if crop_overscan {
+            let row_size = (SCREEN_WIDTH * 3) as usize;
+            height -= 2 * OVERSCAN_LINES;
+            rgb = rgb[OVERSCAN_LINES as usize * row_size..][..height as usize * row_size].to_vec();
         }
+        std::fs::write(file_name, png::encode_png(SCREEN_WIDTH, height, &rgb))
+    }
 
-        let mut max_viewport_width = window_width;
-        let mut max_viewport_height = window_height;
+    /// Refresh the windows with the buffered canvas
+    pub fn present(&mut self) {
+        self.presented_frame.copy_from_slice(&self.frame);
+        let Some(canvas) = self.canvas.as_mut() else {
+            return;
+        };
+        canvas.clear();
+        let creator = canvas.texture_creator();
+        let texture = match self.ntsc_filter.as_mut() {
+            Some(ntsc_filter) => {
+                let pitch = self.ntsc_surface.pitch() as usize;
+                ntsc_filter.apply(
+                    &self.frame,
+                    SCREEN_WIDTH as usize,
+                    self.frame_phase,
+                    &self.palette,
+                    self.ntsc_surface.without_lock_mut().unwrap(),
+                    pitch,
+                );
+                // NTSC frames alternate between two phases, one dot being skipped on odd frames
+                self.frame_phase = 4 - self.frame_phase;
+                self.ntsc_surface.as_texture(&creator).unwrap()
+            }
+            None => self.surface.as_texture(&creator).unwrap(),
+        };
 
-        // For "integer factor" scaling, pick the highest integer factor that fits into the window
-        if self.g_scaling_mode == ScalingMode::ScalingModeIntegerFactor {
-            max_viewport_width =
-                ((window_width as f64 / SCREEN_WIDTH as f64) * SCREEN_WIDTH as f64) as u32;
-            max_viewport_height =
-                ((window_height as f64 / SCREEN_HEIGHT as f64) * SCREEN_HEIGHT as f64) as u32;
-        }
+        // Copies are relative to the viewport, the texture fills it
+        let viewport = canvas.viewport();
+        canvas.copy(&texture, None, None).unwrap();
 
-        // If the resulting viewport is too small, do proportional scaling according to the window size
-        if max_viewport_width == 0 {
-            max_viewport_width = window_width;
-        }
-        if max_viewport_height == 0 {
-            max_viewport_height = window_height;
+        // Markers are 1 NES pixel wide, 2 screen pixels at least to stay visible
+        let (scale_x, scale_y) = (
+            viewport.width() as f64 / SCREEN_WIDTH as f64,
+            viewport.height() as f64 / SCREEN_HEIGHT as f64,
+        );
+        for (x, y, color) in self.overlay.iter() {
+            canvas.set_draw_color(*color);
+            let marker = Rect::new(
+                (*x as f64 * scale_x) as i32,
+                (*y as f64 * scale_y) as i32,
+                (scale_x as u32).max(2),
+                (scale_y as u32).max(2),
+            );
+            canvas.fill_rect(marker).unwrap();
         }
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
 
-        let screen_aspect_ratio = window_width as f64 / window_height as f64;
-        let mut should_preserve_width = texture_aspect_ratio > screen_aspect_ratio;
-
-        // The only difference between aspect fill and fit is that fit will leave black bars
-        // and fill will crop the image.
-        // TODO : does not seem to work, always in fit mode without preserving ratio
-        if self.g_scaling_mode == ScalingMode::ScalingModeAspectFill {
-            should_preserve_width = !should_preserve_width;
-        }
+        canvas.present();
+    }
 
-        if should_preserve_width {
-            self.canvas
-                .viewport()
-                .set_x((window_width as i32 - max_viewport_width as i32) >> 1);
-            self.canvas.viewport().set_width(max_viewport_width);
-            let viewport_width = self.canvas.viewport().width();
-            self.canvas
-                .viewport()
-                .set_height(viewport_width / texture_aspect_ratio as u32);
-            let viewport_height = self.canvas.viewport().height();
-            self.canvas
-                .viewport()
-                .set_y((window_height as i32 - viewport_height as i32) >> 1);
+    /// Place the frame into the window according to the scaling mode and the texture aspect ratio
+    pub fn update_window_viewport(&mut self) {
+        let texture_aspect_ratio = if self.g_scaling_mode == ScalingMode::ScalingModeAspectCorrect {
+            4.0 / 3.0
+        } else if self.ntsc_filter.is_some() {
+            NTSC_ASPECT_RATIO
         } else {
-            self.canvas
-                .viewport()
-                .set_y((window_height as i32 - max_viewport_height as i32) >> 1);
-            self.canvas.viewport().set_height(max_viewport_height);
-            let viewport_height = self.canvas.viewport().height();
-            self.canvas
-                .viewport()
-                .set_width(viewport_height * texture_aspect_ratio as u32);
-            let viewport_width = self.canvas.viewport().width();
-            self.canvas
-                .viewport()
-                .set_x((window_width as i32 - viewport_width as i32) >> 1);
-        }
-
-        self.canvas.present();
+            TEXTURE_ASPECT_RATIO
+        };
+        let Some(canvas) = self.canvas.as_mut() else {
+            return;
+        };
+        let (window_width, window_height) = canvas.window().size();
+        canvas.set_viewport(get_viewport(
+            window_width,
+            window_height,
+            self.g_scaling_mode,
+            texture_aspect_ratio,
+        ));
     }
 
     pub fn get_scaling_mode(&self) -> ScalingMode {
@@ -186,70 +259,124 @@ impl Screen<'_> {
     }
 }
 
-/// NES color palette
-const PALETTE: [Color; 64] = [
-    Color::RGB(84, 84, 84),
-    Color::RGB(0, 30, 116),
-    Color::RGB(8, 16, 144),
-    Color::RGB(48, 0, 136),
-    Color::RGB(68, 0, 100),
-    Color::RGB(92, 0, 48),
-    Color::RGB(84, 4, 0),
-    Color::RGB(60, 24, 0),
-    Color::RGB(32, 42, 0),
-    Color::RGB(8, 58, 0),
-    Color::RGB(0, 64, 0),
-    Color::RGB(0, 60, 0),
-    Color::RGB(0, 50, 60),
-    Color::RGB(0, 0, 0),
-    Color::RGB(0, 0, 0),
-    Color::RGB(0, 0, 0),
-    Color::RGB(152, 150, 152),
-    Color::RGB(8, 76, 196),
-    Color::RGB(48, 50, 236),
-    Color::RGB(92, 30, 228),
-    Color::RGB(136, 20, 176),
-    Color::RGB(160, 20, 100),
-    Color::RGB(152, 34, 32),
-    Color::RGB(120, 60, 0),
-    Color::RGB(84, 90, 0),
-    Color::RGB(40, 114, 0),
-    Color::RGB(8, 124, 0),
-    Color::RGB(0, 118, 40),
-    Color::RGB(0, 102, 120),
-    Color::RGB(0, 0, 0),
-    Color::RGB(0, 0, 0),
-    Color::RGB(0, 0, 0),
-    Color::RGB(236, 238, 236),
-    Color::RGB(76, 154, 236),
-    Color::RGB(120, 124, 236),
-    Color::RGB(176, 98, 236),
-    Color::RGB(228, 84, 236),
-    Color::RGB(236, 88, 180),
-    Color::RGB(236, 106, 100),
-    Color::RGB(212, 136, 32),
-    Color::RGB(160, 170, 0),
-    Color::RGB(116, 196, 0),
-    Color::RGB(76, 208, 32),
-    Color::RGB(56, 204, 108),
-    Color::RGB(56, 180, 204),
-    Color::RGB(60, 60, 60),
-    Color::RGB(0, 0, 0),
-    Color::RGB(0, 0, 0),
-    Color::RGB(236, 238, 236),
-    Color::RGB(168, 204, 236),
-    Color::RGB(188, 188, 236),
-    Color::RGB(212, 178, 236),
-    Color::RGB(236, 174, 236),
-    Color::RGB(236, 174, 212),
-    Color::RGB(236, 180, 176),
-    Color::RGB(228, 196, 144),
-    Color::RGB(204, 210, 120),
-    Color::RGB(180, 222, 120),
-    Color::RGB(168, 226, 144),
-    Color::RGB(152, 226, 180),
-    Color::RGB(160, 214, 228),
-    Color::RGB(160, 162, 160),
-    Color::RGB(0, 0, 0),
-    Color::RGB(0, 0, 0),
-];
+/// Compute the viewport of a frame with the given aspect ratio in a window
+fn get_viewport(
+    window_width: u32,
+    window_height: u32,
+    scaling_mode: ScalingMode,
+    texture_aspect_ratio: f64,
+) -> Rect {
+    // If the scaling mode is fullscreen, use the window size
+    if scaling_mode == ScalingMode::ScalingModeFullscreen {
+        return Rect::new(0, 0, window_width, window_height);
+    }
+
+    let mut max_viewport_width = window_width;
+    let mut max_viewport_height = window_height;
+
+    // For "integer factor" scaling, pick the highest integer factor of the frame height that fits
+    // into the window. The width follows the aspect ratio, 8:7 pixels are not square anyway
+    if scaling_mode == ScalingMode::ScalingModeIntegerFactor {
+        let frame_width = SCREEN_HEIGHT as f64 * texture_aspect_ratio;
+        let factor = (window_width as f64 / frame_width)
+            .floor()
+            .min((window_height / SCREEN_HEIGHT) as f64);
+        // If the resulting viewport is too small, do proportional scaling according to the window size
+        if factor >= 1.0 {
+            max_viewport_width = (factor * frame_width).round() as u32;
+            max_viewport_height = factor as u32 * SCREEN_HEIGHT;
+        }
+    }
+
+    let screen_aspect_ratio = window_width as f64 / window_height as f64;
+    let mut should_preserve_width = texture_aspect_ratio > screen_aspect_ratio;
+
+    // The only difference between aspect fill and fit is that fit will leave black bars
+    // and fill will crop the image.
+    if scaling_mode == ScalingMode::ScalingModeAspectFill {
+        should_preserve_width = !should_preserve_width;
+    }
+
+    let (viewport_width, viewport_height) = if should_preserve_width {
+        (
+            max_viewport_width,
+            (max_viewport_width as f64 / texture_aspect_ratio).round() as u32,
+        )
+    } else {
+        (
+            (max_viewport_height as f64 * texture_aspect_ratio).round() as u32,
+            max_viewport_height,
+        )
+    };
+    Rect::new(
+        (window_width as i32 - viewport_width as i32) >> 1,
+        (window_height as i32 - viewport_height as i32) >> 1,
+        viewport_width,
+        viewport_height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_viewport, ScalingMode, NTSC_ASPECT_RATIO, TEXTURE_ASPECT_RATIO};
+    use sdl2::rect::Rect;
+
+    #[test]
+    fn viewport_scaling_modes() {
+        assert_eq!(
+            get_viewport(
+                768,
+                720,
+                ScalingMode::ScalingModeAspectFit,
+                TEXTURE_ASPECT_RATIO
+            ),
+            Rect::new(0, 0, 768, 720)
+        );
+        // NTSC output is wider, black bars at the top and the bottom
+        assert_eq!(
+            get_viewport(
+                768,
+                720,
+                ScalingMode::ScalingModeAspectFit,
+                NTSC_ASPECT_RATIO
+            ),
+            Rect::new(0, 45, 768, 630)
+        );
+        assert_eq!(
+            get_viewport(
+                768,
+                720,
+                ScalingMode::ScalingModeAspectFill,
+                NTSC_ASPECT_RATIO
+            ),
+            Rect::new(-55, 0, 878, 720)
+        );
+        assert_eq!(
+            get_viewport(
+                1000,
+                1000,
+                ScalingMode::ScalingModeIntegerFactor,
+                NTSC_ASPECT_RATIO
+            ),
+            Rect::new(61, 140, 878, 720)
+        );
+        assert_eq!(
+            get_viewport(
+                800,
+                700,
+                ScalingMode::ScalingModeIntegerFactor,
+                TEXTURE_ASPECT_RATIO
+            ),
+            Rect::new(144, 110, 512, 480)
+        );
+        assert_eq!(
+            get_viewport(
+                640,
+                480,
+                ScalingMode::ScalingModeFullscreen,
+                NTSC_ASPECT_RATIO
+            ),
+            Rect::new(0, 0, 640, 480)
+        );
+    }
+}
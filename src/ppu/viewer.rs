@@ -0,0 +1,446 @@
+//! PPU debug views
+//! Renders nametables, pattern tables, OAM and palette RAM into RGB24 images, from the current PPU state
+
+use super::{get_palette_address, Ppu};
+use sdl2::pixels::Color;
+
+/// Color of the scroll rectangle drawn over the nametables
+const SCROLL_RECTANGLE_COLOR: Color = Color::RGB(255, 0, 255);
+/// Size of a palette view swatch
+const SWATCH_SIZE: u32 = 16;
+
+/// Available debug views
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PpuView {
+    /// The 4 nametables, with the scroll rectangle
+    Nametables,
+    /// Both pattern tables, drawn with a selectable palette
+    PatternTables,
+    /// The 64 sprites of OAM, 8 per row
+    Oam,
+    /// The 32 palette RAM entries
+    Palette,
+}
+
+impl PpuView {
+    /// All views
+    pub const ALL: [PpuView; 4] = [
+        PpuView::Nametables,
+        PpuView::PatternTables,
+        PpuView::Oam,
+        PpuView::Palette,
+    ];
+
+    /// Get the view name, used as window title and image file name
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            PpuView::Nametables => "nametables",
+            PpuView::PatternTables => "pattern_tables",
+            PpuView::Oam => "oam",
+            PpuView::Palette => "palette",
+        }
+    }
+
+    /// Get the view width and height
+    pub fn get_size(&self) -> (u32, u32) {
+        match self {
+            PpuView::Nametables => (512, 480),
+            PpuView::PatternTables => (256, 128),
+            PpuView::Oam => (64, 128), // 8 columns of 8x16 sprites
+            PpuView::Palette => (16 * SWATCH_SIZE, 2 * SWATCH_SIZE),
+        }
+    }
+}
+
+/// RGB24 image being drawn
+struct Image {
+    width: u32,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    fn new((width, height): (u32, u32)) -> Image {
+        Image {
+            width,
+            pixels: vec![0; (width * height * 3) as usize],
+        }
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+        let address = 3 * (x + y * self.width) as usize;
+        self.pixels[address..address + 3].copy_from_slice(&[color.r, color.g, color.b]);
+    }
+}
+
+impl Ppu<'_> {
+    /// Render a debug view into an RGB24 image. Pattern tables use one of the 8 palettes, 4 to 7 being sprite palettes
+    pub fn render_view(&self, view: PpuView, pattern_palette: u8) -> Vec<u8> {
+        let mut image = Image::new(view.get_size());
+        match view {
+            PpuView::Nametables => self.render_nametables(&mut image),
+            PpuView::PatternTables => self.render_pattern_tables(&mut image, pattern_palette),
+            PpuView::Oam => self.render_oam(&mut image),
+            PpuView::Palette => self.render_palette(&mut image),
+        }
+        image.pixels
+    }
+
+    /// Describe what is under a position of a view : addresses, attribute and CHR data
+    pub fn describe_view_position(&self, view: PpuView, x: u32, y: u32) -> Option<String> {
+        let (width, height) = view.get_size();
+        if x >= width || y >= height {
+            return None;
+        }
+        let description = match view {
+            PpuView::Nametables => {
+                let (nametable, tile_x, tile_y) =
+                    (x / 256 + 2 * (y / 240), x % 256 / 8, y % 240 / 8);
+                let address = 0x2000 + 0x400 * nametable as u16 + (tile_y * 32 + tile_x) as u16;
+                let tile_index = self.peek_ppu_memory(address);
+                let (attribute_address, palette) = self.get_attribute(nametable, tile_x, tile_y);
+                let chr_address = self.get_bg_pattern_table() + 16 * tile_index as u16;
+                format!(
+                    "Nametable ${:04x} tile ${:02x}, attribute ${:04x} = ${:02x} palette {}, {}",
+                    address,
+                    tile_index,
+                    attribute_address,
+                    self.peek_ppu_memory(attribute_address),
+                    palette,
+                    self.describe_chr(chr_address)
+                )
+            }
+            PpuView::PatternTables => {
+                let tile_index = (y / 8) * 16 + (x % 128) / 8;
+                let chr_address = 0x1000 * (x / 128) as u16 + 16 * tile_index as u16;
+                format!(
+                    "Tile ${:02x}, {}",
+                    tile_index,
+                    self.describe_chr(chr_address)
+                )
+            }
+            PpuView::Oam => {
+                let sprite = ((y / 16) * 8 + x / 8) as usize;
+                let oam = &self.primary_oam[4 * sprite..4 * sprite + 4];
+                format!(
+                    "Sprite {} : X {} Y {} tile ${:02x} attribute ${:02x}, {}",
+                    sprite,
+                    oam[3],
+                    oam[0],
+                    oam[1],
+                    oam[2],
+                    self.describe_chr(self.get_sprite_tile_address(oam[1]))
+                )
+            }
+            PpuView::Palette => {
+                let address = 0x3f00 + ((y / SWATCH_SIZE) * 16 + x / SWATCH_SIZE) as u16;
+                format!(
+                    "Palette ${:04x} = ${:02x}",
+                    address,
+                    self.peek_ppu_memory(address)
+                )
+            }
+        };
+        Some(description)
+    }
+
+    /// List OAM sprites, one per line
+    pub fn get_oam_as_string(&self) -> String {
+        self.primary_oam
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(sprite, oam)| {
+                format!(
+                    "{:2} X:{:3} Y:{:3} TILE:{:02x} ATTR:{:02x}{}{}{} PAL:{}\n",
+                    sprite,
+                    oam[3],
+                    oam[0],
+                    oam[1],
+                    oam[2],
+                    if oam[2] & 0x20 != 0 { " BEHIND" } else { "" },
+                    if oam[2] & 0x40 != 0 { " HFLIP" } else { "" },
+                    if oam[2] & 0x80 != 0 { " VFLIP" } else { "" },
+                    4 + (oam[2] & 0b11)
+                )
+            })
+            .collect()
+    }
+
+    /// Draw the 4 nametables as 2x2 screens, and the scroll rectangle from the temporary VRAM address
+    fn render_nametables(&self, image: &mut Image) {
+        for nametable in 0..4u32 {
+            for tile_y in 0..30 {
+                for tile_x in 0..32 {
+                    let address = 0x2000 + 0x400 * nametable as u16 + (tile_y * 32 + tile_x) as u16;
+                    let tile_index = self.peek_ppu_memory(address);
+                    let (_, palette) = self.get_attribute(nametable, tile_x, tile_y);
+                    self.draw_tile(
+                        image,
+                        self.get_bg_pattern_table() + 16 * tile_index as u16,
+                        palette,
+                        (nametable % 2) * 256 + tile_x * 8,
+                        (nametable / 2) * 240 + tile_y * 8,
+                        0,
+                    );
+                }
+            }
+        }
+
+        let scroll_x = ((self.register_t >> 10) & 1) as u32 * 256
+            + (self.register_t & 0x1f) as u32 * 8
+            + self.register_x as u32;
+        let scroll_y = ((self.register_t >> 11) & 1) as u32 * 240
+            + ((self.register_t >> 5) & 0x1f) as u32 * 8
+            + ((self.register_t >> 12) & 0b111) as u32;
+        for x in 0..256 {
+            image.set_pixel((scroll_x + x) % 512, scroll_y % 480, SCROLL_RECTANGLE_COLOR);
+            image.set_pixel(
+                (scroll_x + x) % 512,
+                (scroll_y + 239) % 480,
+                SCROLL_RECTANGLE_COLOR,
+            );
+        }
+        for y in 0..240 {
+            image.set_pixel(scroll_x % 512, (scroll_y + y) % 480, SCROLL_RECTANGLE_COLOR);
+            image.set_pixel(
+                (scroll_x + 255) % 512,
+                (scroll_y + y) % 480,
+                SCROLL_RECTANGLE_COLOR,
+            );
+        }
+    }
+
+    /// Draw both pattern tables side by side, 16x16 tiles each
+    fn render_pattern_tables(&self, image: &mut Image, palette: u8) {
+        for table in 0..2u32 {
+            for tile_index in 0..256u32 {
+                self.draw_tile(
+                    image,
+                    (0x1000 * table + 16 * tile_index) as u16,
+                    palette % 8,
+                    table * 128 + (tile_index % 16) * 8,
+                    (tile_index / 16) * 8,
+                    0,
+                );
+            }
+        }
+    }
+
+    /// Draw the 64 sprites with their palette and flipping, 8 per row in 8x16 cells
+    fn render_oam(&self, image: &mut Image) {
+        let height = self.get_sprite_height() as u32;
+        for (sprite, oam) in self.primary_oam.chunks_exact(4).enumerate() {
+            let (x, y) = ((sprite as u32 % 8) * 8, (sprite as u32 / 8) * 16);
+            let address = self.get_sprite_tile_address(oam[1]);
+            let palette = 4 + (oam[2] & 0b11);
+            for part in 0..height / 8 {
+                // Vertical flip also swaps the 2 tiles of 8x16 sprites
+                let row = if oam[2] & 0x80 != 0 && height == 16 {
+                    1 - part
+                } else {
+                    part
+                };
+                self.draw_tile(
+                    image,
+                    address + 16 * row as u16,
+                    palette,
+                    x,
+                    y + 8 * part,
+                    oam[2],
+                );
+            }
+        }
+    }
+
+    /// Draw palette RAM, background palettes on the first row and sprite palettes on the second
+    fn render_palette(&self, image: &mut Image) {
+        for entry in 0..32u32 {
+            let color = self.get_color(self.peek_ppu_memory(0x3f00 + entry as u16));
+            for y in 0..SWATCH_SIZE {
+                for x in 0..SWATCH_SIZE {
+                    image.set_pixel(
+                        (entry % 16) * SWATCH_SIZE + x,
+                        (entry / 16) * SWATCH_SIZE + y,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Draw an 8x8 tile, with the flipping bits of a sprite attribute
+    fn draw_tile(
+        &self,
+        image: &mut Image,
+        address: u16,
+        palette: u8,
+        x: u32,
+        y: u32,
+        attribute: u8,
+    ) {
+        for row in 0..8 {
+            let low = self.peek_ppu_memory(address + row);
+            let high = self.peek_ppu_memory(address + row + 8);
+            for column in 0..8 {
+                let value = ((low >> (7 - column)) & 1) | (((high >> (7 - column)) & 1) << 1);
+                let color = if value == 0 {
+                    self.palette_vram[0]
+                } else {
+                    self.palette_vram
+                        [get_palette_address(0x3f00 + 4 * palette as u16 + value as u16)]
+                };
+                let pixel_x = if attribute & 0x40 != 0 {
+                    7 - column
+                } else {
+                    column
+                };
+                let pixel_y = if attribute & 0x80 != 0 { 7 - row } else { row };
+                image.set_pixel(
+                    x + pixel_x as u32,
+                    y + pixel_y as u32,
+                    self.get_color(color),
+                );
+            }
+        }
+    }
+
+    /// Get the attribute byte address and the palette of a nametable tile
+    fn get_attribute(&self, nametable: u32, tile_x: u32, tile_y: u32) -> (u16, u8) {
+        let address = 0x23c0 + 0x400 * nametable as u16 + ((tile_y / 4) * 8 + tile_x / 4) as u16;
+        let shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
+        (address, (self.peek_ppu_memory(address) >> shift) & 0b11)
+    }
+
+    /// Get the background pattern table address selected by PPUCTRL
+    fn get_bg_pattern_table(&self) -> u16 {
+        ((self.ppuctrl as u16 >> 4) & 1) * 0x1000
+    }
+
+    /// Get the CHR address of the first tile of a sprite
+    fn get_sprite_tile_address(&self, tile_index: u8) -> u16 {
+        let tile_index = tile_index as u16;
+        if self.get_sprite_height() == 16 {
+            (tile_index & 1) * 0x1000 + 16 * (tile_index & 0xfe)
+        } else {
+            ((self.ppuctrl as u16 >> 3) & 1) * 0x1000 + 16 * tile_index
+        }
+    }
+
+    /// Describe the CHR data of a tile
+    fn describe_chr(&self, address: u16) -> String {
+        let data: Vec<String> = (0..16)
+            .map(|offset| format!("{:02x}", self.peek_ppu_memory(address + offset)))
+            .collect();
+        format!("CHR ${:04x} : {}", address, data.join(" "))
+    }
+
+    /// Get the RGB color of a palette entry
+    fn get_color(&self, value: u8) -> Color {
+        self.screen.get_color(value as u16 & 0x3f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::interrupt::Interrupt;
+    use crate::cartridge::Cartridge;
+    use crate::test_utils::{lock_sdl, write_test_rom};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Headless PPU with a test NROM inserted, CHR tile 1 in color 1 and tile 2 in color 2
+    fn new_ppu() -> Ppu<'static> {
+        let sdl_context = Rc::new(RefCell::new(sdl2::init().unwrap()));
+        let cartridge = Rc::new(RefCell::new(Cartridge::new(write_test_rom("viewer", &[]))));
+        let interrupt_bus = Rc::new(RefCell::new(Interrupt::new()));
+        let mut ppu = Ppu::new(sdl_context, cartridge, interrupt_bus);
+        ppu.set_headless();
+        for row in 0..8 {
+            ppu.write_vram(0x0010 + row, 0xff);
+            ppu.write_vram(0x0028 + row, 0xff);
+        }
+        ppu
+    }
+
+    /// Get the color of a view pixel
+    fn get_pixel(image: &[u8], view: PpuView, x: u32, y: u32) -> Color {
+        let address = 3 * (x + y * view.get_size().0) as usize;
+        Color::RGB(image[address], image[address + 1], image[address + 2])
+    }
+
+    #[test]
+    fn nametable_description() {
+        let _sdl_lock = lock_sdl();
+        let mut ppu = new_ppu();
+        // Tile (3, 2) of the first nametable, in the bottom right quadrant of its attribute byte
+        ppu.write_vram(0x2043, 0x01);
+        ppu.write_vram(0x23c0, 0b1110_0100);
+        assert_eq!(
+            ppu.describe_view_position(PpuView::Nametables, 3 * 8 + 5, 2 * 8 + 7)
+                .unwrap(),
+            "Nametable $2043 tile $01, attribute $23c0 = $e4 palette 3, \
+             CHR $0010 : ff ff ff ff ff ff ff ff 00 00 00 00 00 00 00 00"
+        );
+        // Bottom right nametable, background pattern table from PPUCTRL
+        ppu.write_0x2000(0x10);
+        assert!(ppu
+            .describe_view_position(PpuView::Nametables, 256, 240)
+            .unwrap()
+            .starts_with("Nametable $2c00 tile $00, attribute $2fc0 = $00 palette 0, CHR $1000"));
+        assert_eq!(
+            ppu.describe_view_position(PpuView::Nametables, 512, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn pattern_table_palettes() {
+        let _sdl_lock = lock_sdl();
+        let mut ppu = new_ppu();
+        ppu.write_vram(0x3f00, 0x0f);
+        ppu.write_vram(0x3f05, 0x16);
+        ppu.write_vram(0x3f15, 0x2a);
+        let view = PpuView::PatternTables;
+        let image = ppu.render_view(view, 1);
+        assert_eq!(get_pixel(&image, view, 8, 0), ppu.get_color(0x16));
+        assert_eq!(get_pixel(&image, view, 0, 0), ppu.get_color(0x0f));
+        // Palettes 4 to 7 are the sprite ones, the selection wraps around
+        let image = ppu.render_view(view, 5);
+        assert_eq!(get_pixel(&image, view, 8, 0), ppu.get_color(0x2a));
+        assert_eq!(ppu.render_view(view, 9), ppu.render_view(view, 1));
+        assert_eq!(
+            ppu.describe_view_position(view, 128 + 16, 8).unwrap(),
+            "Tile $12, CHR $1120 : 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
+        );
+    }
+
+    #[test]
+    fn oam_flipped_tall_sprite() {
+        let _sdl_lock = lock_sdl();
+        let mut ppu = new_ppu();
+        ppu.write_vram(0x3f00, 0x0f);
+        ppu.write_vram(0x3f12, 0x2a);
+        ppu.write_0x2000(0x20); // 8x16 sprites
+        ppu.primary_oam[..4].copy_from_slice(&[0x40, 0x02, 0x00, 0x30]);
+        let view = PpuView::Oam;
+
+        // Tiles 2 and 3, the vertical flip swaps them
+        let image = ppu.render_view(view, 0);
+        assert_eq!(get_pixel(&image, view, 0, 0), ppu.get_color(0x2a));
+        assert_eq!(get_pixel(&image, view, 0, 8), ppu.get_color(0x0f));
+        ppu.primary_oam[2] = 0x80;
+        let image = ppu.render_view(view, 0);
+        assert_eq!(get_pixel(&image, view, 0, 0), ppu.get_color(0x0f));
+        assert_eq!(get_pixel(&image, view, 0, 8), ppu.get_color(0x2a));
+        assert_eq!(get_pixel(&image, view, 0, 15), ppu.get_color(0x2a));
+
+        assert_eq!(
+            ppu.get_oam_as_string().lines().next().unwrap(),
+            " 0 X: 48 Y: 64 TILE:02 ATTR:80 VFLIP PAL:4"
+        );
+        assert!(ppu
+            .describe_view_position(view, 0, 0)
+            .unwrap()
+            .starts_with("Sprite 0 : X 48 Y 64 tile $02 attribute $80, CHR $0020"));
+    }
+}
@@ -0,0 +1,160 @@
+//! Console region timing profiles
+//! NTSC, PAL and Dendy consoles differ by their clocks, scanline counts and APU rate tables
+
+use std::str::FromStr;
+
+/// Noise channel periods in CPU cycles, NTSC and Dendy
+const NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+/// Noise channel periods in CPU cycles, PAL
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+/// DMC rates in CPU cycles, NTSC and Dendy
+const NTSC_DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+/// DMC rates in CPU cycles, PAL
+const PAL_DMC_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+/// Frame counter steps in CPU cycles, NTSC and Dendy. The 4th step ends the 4 step mode and sets the frame IRQ,
+/// the 5th one ends the 5 step mode
+const NTSC_FRAME_COUNTER_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+/// Frame counter steps in CPU cycles, PAL
+const PAL_FRAME_COUNTER_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+/// Console region
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Region {
+    /// North America and Japan, 2C02 PPU
+    Ntsc,
+    /// Europe and Australia, 2C07 PPU
+    Pal,
+    /// Famiclones, PAL clocks with NTSC like vblank
+    Dendy,
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Region, String> {
+        match value.to_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region : {}", value)),
+        }
+    }
+}
+
+impl Region {
+    /// Number of scanlines per frame, including vblank and pre-render line
+    pub fn get_scanline_count(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Scanline where vblank starts and NMI is raised
+    pub fn get_vblank_start_line(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291, // 51 post-render lines keep a 20 lines vblank
+        }
+    }
+
+    /// Pre-render scanline, last line of the frame
+    pub fn get_pre_render_line(&self) -> u16 {
+        self.get_scanline_count() - 1
+    }
+
+    /// Master clock frequency in Hz
+    pub fn get_master_clock(&self) -> f64 {
+        match self {
+            Region::Ntsc => 21_477_272.0,
+            Region::Pal | Region::Dendy => 26_601_712.0,
+        }
+    }
+
+    /// CPU clock frequency in Hz, also the APU mixing rate
+    pub fn get_cpu_clock(&self) -> f64 {
+        self.get_master_clock() / self.get_cpu_clock_divider() as f64
+    }
+
+    /// Master clock cycles per CPU cycle
+    pub fn get_cpu_clock_divider(&self) -> u32 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Master clock cycles per PPU dot
+    pub fn get_ppu_clock_divider(&self) -> u32 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// Video frame rate
+    pub fn get_frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.007,
+        }
+    }
+
+    /// Return true if the pre-render line is one dot shorter on odd frames when rendering
+    pub fn has_odd_frame_skip(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    /// Noise channel period lookup table
+    pub fn get_noise_periods(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_NOISE_PERIODS,
+            Region::Pal => &PAL_NOISE_PERIODS,
+        }
+    }
+
+    /// DMC rate lookup table
+    pub fn get_dmc_rates(&self) -> &'static [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_DMC_RATES,
+            Region::Pal => &PAL_DMC_RATES,
+        }
+    }
+
+    /// APU frame counter steps
+    pub fn get_frame_counter_steps(&self) -> &'static [u32; 5] {
+        match self {
+            Region::Ntsc | Region::Dendy => &NTSC_FRAME_COUNTER_STEPS,
+            Region::Pal => &PAL_FRAME_COUNTER_STEPS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Region;
+
+    #[test]
+    fn region_timings() {
+        assert_eq!("PAL".parse::<Region>(), Ok(Region::Pal));
+        assert!("secam".parse::<Region>().is_err());
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            // Every region has 240 visible lines, a post-render line and 20 lines of vblank at least
+            assert!(region.get_pre_render_line() - region.get_vblank_start_line() >= 20);
+            assert!(region.get_vblank_start_line() > 240);
+        }
+        assert_eq!(
+            Region::Pal.get_cpu_clock_divider() * 5,
+            Region::Pal.get_ppu_clock_divider() * 16
+        );
+    }
+}
@@ -0,0 +1,33 @@
+//! Helpers shared by unit tests
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Only one SDL context may exist at a time, tests creating one run one after another
+static SDL_LOCK: Mutex<()> = Mutex::new(());
+static ROM_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Hold the SDL lock until the end of a test, to be taken before creating an SDL context
+pub fn lock_sdl() -> MutexGuard<'static, ()> {
+    // A failed test poisons the lock, other tests can still run
+    SDL_LOCK.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Write a 16kB NROM image for unit tests, with the code at $C000 and the vectors pointing to
+/// RESET $C000, NMI $C100 and IRQ $C200. Returns the file path, unique for each call.
+pub fn write_test_rom(name: &str, code: &[u8]) -> String {
+    let mut prg_rom = vec![0xea; 0x4000]; // NOP
+    prg_rom[..code.len()].copy_from_slice(code);
+    prg_rom[0x3ffa..].copy_from_slice(&[0x00, 0xc1, 0x00, 0xc0, 0x00, 0xc2]);
+
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(prg_rom);
+    rom.extend(vec![0; 0x2000]);
+    let path = std::env::temp_dir().join(format!(
+        "{}-{}-{}.nes",
+        name,
+        std::process::id(),
+        ROM_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, rom).unwrap();
+    path.to_string_lossy().into_owned()
+}
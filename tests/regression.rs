@@ -0,0 +1,12 @@
+use nes_emu_rust::nes_emulator::regression;
+
+#[test]
+fn screenshot_regression() {
+    let reports = regression::run_manifest("tests/regression/manifest.yaml", "target/regression");
+    let failures: Vec<&str> = reports
+        .iter()
+        .filter(|report| report.is_failure())
+        .map(|report| report.name.as_str())
+        .collect();
+    assert!(failures.is_empty(), "Regressions in {:?}", failures);
+}
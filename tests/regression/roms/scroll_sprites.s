@@ -0,0 +1,164 @@
+; Regression test ROM : NROM-128, horizontal mirroring
+; Striped background scrolled by one pixel each frame, four sprites with flips and palettes.
+; Controller 1 : Right and Down move the first sprite, Start turns grayscale on.
+;
+; Build with cc65, the whole file being one flat segment :
+;   ca65 scroll_sprites.s && ld65 -t none -o scroll_sprites.nes scroll_sprites.o
+
+buttons = $10
+scroll  = $11
+oam     = $0200
+
+PPUCTRL   = $2000
+PPUMASK   = $2001
+PPUSTATUS = $2002
+PPUSCROLL = $2005
+PPUADDR   = $2006
+PPUDATA   = $2007
+OAMDMA    = $4014
+JOY1      = $4016
+
+; iNES header
+    .byte "NES", $1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+
+; 16kB PRG ROM, mapped at $C000
+.org $c000
+reset:
+    sei
+    cld
+    ldx #$ff
+    txs
+    inx
+    stx PPUCTRL
+    stx PPUMASK
+vblank_1:
+    bit PPUSTATUS
+    bpl vblank_1
+vblank_2:
+    bit PPUSTATUS
+    bpl vblank_2
+
+    ; Palettes
+    lda #$3f
+    sta PPUADDR
+    lda #$00
+    sta PPUADDR
+    ldx #$00
+palette_loop:
+    lda palettes,x
+    sta PPUDATA
+    inx
+    cpx #$20
+    bne palette_loop
+
+    ; Nametable 0 and its attributes : tiles 1 to 4, changing every 4 tiles
+    lda #$20
+    sta PPUADDR
+    lda #$00
+    sta PPUADDR
+    ldy #$04
+    ldx #$00
+fill_loop:
+    txa
+    lsr a
+    lsr a
+    and #$03
+    clc
+    adc #$01
+    sta PPUDATA
+    inx
+    bne fill_loop
+    dey
+    bne fill_loop
+
+    ; Shadow OAM : hidden sprites, then the sprite table
+    ldx #$00
+    lda #$ff
+clear_loop:
+    sta oam,x
+    inx
+    bne clear_loop
+sprite_loop:
+    lda sprites,x
+    sta oam,x
+    inx
+    cpx #$10
+    bne sprite_loop
+
+    lda #$00
+    sta PPUSCROLL
+    sta PPUSCROLL
+    lda #$80            ; NMI on
+    sta PPUCTRL
+    lda #$1e            ; Background and sprites, no left column clipping
+    sta PPUMASK
+forever:
+    jmp forever
+
+nmi:
+    pha
+    lda #>oam
+    sta OAMDMA
+
+    ; Read controller 1, A ends in bit 7 and Right in bit 0
+    lda #$01
+    sta JOY1
+    lda #$00
+    sta JOY1
+    ldx #$08
+read_loop:
+    lda JOY1
+    lsr a
+    rol buttons
+    dex
+    bne read_loop
+
+    lda buttons
+    and #$01            ; Right
+    beq no_right
+    inc oam+3
+no_right:
+    lda buttons
+    and #$04            ; Down
+    beq no_down
+    inc oam
+no_down:
+    lda buttons
+    and #$10            ; Start
+    beq no_start
+    lda #$1f
+    sta PPUMASK
+no_start:
+    inc scroll
+    lda scroll
+    sta PPUSCROLL
+    lda #$00
+    sta PPUSCROLL
+    pla
+irq:
+    rti
+
+palettes:
+    .byte $0f, $01, $11, $21, $0f, $06, $16, $26, $0f, $09, $19, $29, $0f, $04, $14, $24
+    .byte $0f, $30, $27, $16, $0f, $30, $2a, $12, $0f, $30, $21, $13, $0f, $30, $28, $17
+sprites:                ; Y, tile, attributes, X
+    .byte $40, $05, $00, $40
+    .byte $40, $05, $41, $50
+    .byte $60, $05, $82, $60
+    .byte $70, $04, $03, $70
+
+    .res $fffa - *, $ff
+
+    .word nmi, reset, irq
+
+; 8kB CHR ROM
+.org $0000
+    .res 16, $00                                            ; 0 : empty
+    .byte $ff, $ff, $ff, $ff, $ff, $ff, $ff, $ff, 0, 0, 0, 0, 0, 0, 0, 0    ; 1 : color 1
+    .byte 0, 0, 0, 0, 0, 0, 0, 0, $ff, $ff, $ff, $ff, $ff, $ff, $ff, $ff    ; 2 : color 2
+    .res 16, $ff                                            ; 3 : color 3
+    .byte $aa, $55, $aa, $55, $aa, $55, $aa, $55            ; 4 : checkerboard of colors 1 and 2
+    .byte $55, $aa, $55, $aa, $55, $aa, $55, $aa
+    .byte $3c, $7e, $ff, $ff, $ff, $ff, $7e, $3c            ; 5 : ball
+    .byte $3c, $42, $81, $81, $81, $81, $42, $3c
+    .res $2000 - 6 * 16, $00